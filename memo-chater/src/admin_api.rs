@@ -12,6 +12,7 @@ use std::sync::Arc;
 use crate::state::AppState;
 use crate::types::{LongTermMemory, ChatMessage};
 use crate::extractor::{MemoryExtractor, ExtractorConfig};
+use crate::pipeline::JobStatus;
 
 /// 记忆列表查询参数
 #[derive(Debug, Deserialize)]
@@ -267,6 +268,11 @@ pub async fn trigger_extraction(
         .map(|m| ChatMessage {
             role: m.role.clone(),
             content: m.content.clone(),
+            parts: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+            meta: None,
         })
         .collect();
     
@@ -909,4 +915,55 @@ pub async fn update_settings(
     }
     
     ApiResponse::ok(response)
-}
\ No newline at end of file
+}
+// ==================== 后台任务队列 API ====================
+
+/// 任务列表查询参数
+#[derive(Debug, Deserialize)]
+pub struct JobListQuery {
+    pub status: Option<JobStatus>,
+}
+
+/// 列出后台任务（可按状态过滤）
+pub async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<JobListQuery>,
+) -> impl IntoResponse {
+    match state.job_queue.list(query.status).await {
+        Ok(jobs) => ApiResponse::ok(jobs),
+        Err(e) => {
+            tracing::error!("读取后台任务失败: {}", e);
+            ApiResponse::err(e.to_string())
+        }
+    }
+}
+
+/// 手动重试后台任务（死信任务重新执行）
+pub async fn retry_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.job_queue.retry(&id).await {
+        Ok(Some(job)) => ApiResponse::ok(job),
+        Ok(None) => ApiResponse::err("任务不存在"),
+        Err(e) => {
+            tracing::error!("重试后台任务失败: {}", e);
+            ApiResponse::err(e.to_string())
+        }
+    }
+}
+
+/// 删除后台任务
+pub async fn delete_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.job_queue.delete(&id).await {
+        Ok(true) => ApiResponse::ok("删除成功"),
+        Ok(false) => ApiResponse::err("任务不存在"),
+        Err(e) => {
+            tracing::error!("删除后台任务失败: {}", e);
+            ApiResponse::err(e.to_string())
+        }
+    }
+}
@@ -1,7 +1,8 @@
 //! AI客户端封装
 
+use super::tokenizer::TokenUsage;
 use crate::config::AiConfig;
-use crate::types::ChatMessage;
+use crate::types::{ChatMessage, MessageContent, ToolCall, ToolCallDelta};
 use thiserror::Error;
 
 /// AI 调用错误
@@ -25,9 +26,40 @@ use once_cell::sync::Lazy;
 
 /// 用于匹配思考标签的正则表达式（支持 <think>、<thinking> 等变体）
 static THINKING_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"(?s)<think(?:ing)?[^>]*>.*?</think(?:ing)?>").unwrap()
+    Regex::new(r"(?s)<think(?:ing)?(?:\s[^>]*)?>.*?</think(?:ing)?\s*>").unwrap()
 });
 
+/// 透传给上游的工具定义（OpenAI `tools` / `tool_choice`，原样转发）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+}
+
+/// 非流式聊天结果
+#[derive(Debug, Clone)]
+pub struct ChatCompletion {
+    /// 回复内容（已去除思考标签）
+    pub content: String,
+    /// 模型发起的工具调用
+    pub tool_calls: Vec<ToolCall>,
+    /// 上游返回的用量（未返回时为 None）
+    pub usage: Option<TokenUsage>,
+}
+
+/// 流式聊天事件
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// 内容增量
+    Content(String),
+    /// 工具调用增量
+    ToolCalls(Vec<ToolCallDelta>),
+    /// 上游在流末尾返回的用量
+    Usage(TokenUsage),
+}
+
 /// AI客户端
 #[derive(Clone)]
 pub struct AiClient {
@@ -221,6 +253,21 @@ impl AiClient {
 
     /// 非流式聊天（指定模型）
     pub async fn chat_with_model(&self, messages: &[ChatMessage], model: Option<&str>) -> Result<String, AiError> {
+        self.chat_with_usage(messages, model).await.map(|c| c.content)
+    }
+
+    /// 非流式聊天（指定模型），同时返回上游用量
+    pub async fn chat_with_usage(&self, messages: &[ChatMessage], model: Option<&str>) -> Result<ChatCompletion, AiError> {
+        self.chat_with_tools(messages, model, &ToolOptions::default()).await
+    }
+
+    /// 非流式聊天（指定模型），透传工具定义，返回上游用量和工具调用
+    pub async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        model: Option<&str>,
+        tools: &ToolOptions,
+    ) -> Result<ChatCompletion, AiError> {
         let url = format!("{}/chat/completions", self.api_base.trim_end_matches('/'));
         let use_model = model.unwrap_or(&self.model);
         
@@ -234,12 +281,11 @@ impl AiClient {
 
         let request_body = ChatRequest {
             model: use_model.to_string(),
-            messages: messages.iter().map(|m| ApiMessage {
-                role: m.role.clone(),
-                content: m.content.clone(),
-            }).collect(),
+            messages: messages.iter().map(ApiMessage::from).collect(),
             stream: false,
+            stream_options: None,
             thinking_config,
+            tools: tools.clone(),
         };
 
         tracing::debug!(
@@ -273,11 +319,18 @@ impl AiClient {
             .await
             .map_err(|e| AiError::ParseError(e.to_string()))?;
 
-        chat_response
+        let message = chat_response
             .choices
-            .first()
-            .map(|c| Self::strip_thinking_tags(&c.message.content))
-            .ok_or_else(|| AiError::ParseError("响应中没有选项".to_string()))
+            .into_iter()
+            .next()
+            .map(|c| c.message)
+            .ok_or_else(|| AiError::ParseError("响应中没有选项".to_string()))?;
+
+        Ok(ChatCompletion {
+            content: Self::strip_thinking_tags(&message.content),
+            tool_calls: message.tool_calls.unwrap_or_default(),
+            usage: chat_response.usage,
+        })
     }
 
     /// 流式聊天
@@ -294,6 +347,34 @@ impl AiClient {
         messages: &[ChatMessage],
         model: Option<&str>,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AiError>> + Send>>, AiError> {
+        use futures::StreamExt;
+
+        let events = self.chat_stream_events(messages, model).await?;
+        Ok(Box::pin(events.filter_map(|event| async move {
+            match event {
+                Ok(StreamEvent::Content(content)) => Some(Ok(content)),
+                Ok(StreamEvent::Usage(_)) | Ok(StreamEvent::ToolCalls(_)) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })))
+    }
+
+    /// 流式聊天（指定模型），内容增量之外还会产出上游返回的用量
+    pub async fn chat_stream_events(
+        &self,
+        messages: &[ChatMessage],
+        model: Option<&str>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent, AiError>> + Send>>, AiError> {
+        self.chat_stream_events_with_tools(messages, model, &ToolOptions::default()).await
+    }
+
+    /// 流式聊天（指定模型），透传工具定义，额外产出工具调用增量
+    pub async fn chat_stream_events_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        model: Option<&str>,
+        tools: &ToolOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent, AiError>> + Send>>, AiError> {
         let url = format!("{}/chat/completions", self.api_base.trim_end_matches('/'));
         let use_model = model.unwrap_or(&self.model);
         
@@ -307,12 +388,12 @@ impl AiClient {
 
         let request_body = ChatRequest {
             model: use_model.to_string(),
-            messages: messages.iter().map(|m| ApiMessage {
-                role: m.role.clone(),
-                content: m.content.clone(),
-            }).collect(),
+            messages: messages.iter().map(ApiMessage::from).collect(),
             stream: true,
+            // 请求上游在流末尾返回 usage（OpenAI 兼容接口）
+            stream_options: Some(StreamOptions { include_usage: true }),
             thinking_config,
+            tools: tools.clone(),
         };
 
         let has_key = !self.api_key.is_empty();
@@ -403,9 +484,15 @@ impl AiClient {
                                             // 只取 content，忽略 reasoning_content（思考过程）
                                             if let Some(content) = &choice.delta.content {
                                                 if !content.is_empty() {
-                                                    yield Ok(content.clone());
+                                                    yield Ok(StreamEvent::Content(content.clone()));
                                                 }
                                             }
+                                            if let Some(tool_calls) = &choice.delta.tool_calls {
+                                                yield Ok(StreamEvent::ToolCalls(tool_calls.clone()));
+                                            }
+                                        }
+                                        if let Some(usage) = chunk.usage {
+                                            yield Ok(StreamEvent::Usage(usage));
                                         }
                                     }
                                     Err(e) => {
@@ -436,7 +523,17 @@ struct ChatRequest {
     messages: Vec<ApiMessage>,
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     thinking_config: Option<ThinkingConfig>,
+    #[serde(flatten)]
+    tools: ToolOptions,
+}
+
+/// 流式选项
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    include_usage: bool,
 }
 
 /// Gemini 思考配置
@@ -446,25 +543,50 @@ struct ThinkingConfig {
     thinking_budget: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 struct ApiMessage {
     role: String,
-    content: String,
+    content: Option<MessageContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl From<&ChatMessage> for ApiMessage {
+    /// 转换为上游消息（不携带内部元数据）
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            role: message.role.clone(),
+            content: message.to_content(),
+            name: message.name.clone(),
+            tool_calls: message.tool_calls.clone(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct ChatResponse {
     choices: Vec<ChatChoice>,
+    #[serde(default)]
+    usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ChatChoice {
-    message: ApiMessage,
+    message: ChatMessage,
 }
 
 #[derive(Debug, Deserialize)]
 struct StreamChunk {
+    /// 携带 usage 的最后一个 chunk 的 choices 为空
+    #[serde(default)]
     choices: Vec<StreamChoice>,
+    #[serde(default)]
+    usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -476,6 +598,8 @@ struct StreamChoice {
 struct StreamDelta {
     content: Option<String>,
     reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
 // ============ Embedding 请求/响应结构 ============
@@ -1,5 +1,7 @@
 //! AI客户端模块
 
 pub mod client;
+pub mod tokenizer;
 
-pub use client::{AiClient, AiError};
\ No newline at end of file
+pub use client::{AiClient, AiError, ChatCompletion, StreamEvent, ToolOptions};
+pub use tokenizer::{count_message_tokens, count_tokens, Encoding, TokenUsage};
\ No newline at end of file
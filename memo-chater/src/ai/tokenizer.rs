@@ -0,0 +1,228 @@
+//! Token 计数
+//!
+//! 基于 tiktoken 的离线 BPE 编码表估算 token 数，编码表随程序内置，无需联网。
+//! 非 OpenAI 模型没有公开的编码表，统一按 cl100k_base 估算，误差通常在一成以内，
+//! 用于用量统计和上下文预算足够；上游返回 usage 时以上游为准。
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+use crate::types::ChatMessage;
+
+/// 每条消息的固定开销（角色标记、分隔符）
+const TOKENS_PER_MESSAGE: usize = 3;
+
+/// 消息带参与者名称时的额外开销
+const TOKENS_PER_NAME: usize = 1;
+
+/// 每个工具调用的固定开销（类型、字段分隔符）
+const TOKENS_PER_TOOL_CALL: usize = 3;
+
+/// 回复的起始标记开销
+const TOKENS_REPLY_PRIMING: usize = 3;
+
+/// BPE 编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// GPT-3.5 / GPT-4，以及无公开编码表的模型
+    Cl100kBase,
+    /// GPT-4o / o 系列 / GPT-4.1 及之后
+    O200kBase,
+}
+
+/// 模型名前缀 → 编码（按顺序匹配，未命中时使用 cl100k_base）
+const MODEL_ENCODINGS: &[(&str, Encoding)] = &[
+    ("gpt-4o", Encoding::O200kBase),
+    ("gpt-4.1", Encoding::O200kBase),
+    ("gpt-4.5", Encoding::O200kBase),
+    ("gpt-5", Encoding::O200kBase),
+    ("chatgpt-4o", Encoding::O200kBase),
+    ("o1", Encoding::O200kBase),
+    ("o3", Encoding::O200kBase),
+    ("o4", Encoding::O200kBase),
+    ("gpt-4", Encoding::Cl100kBase),
+    ("gpt-3.5", Encoding::Cl100kBase),
+    ("text-embedding", Encoding::Cl100kBase),
+];
+
+impl Encoding {
+    /// 根据模型名选择编码（忽略 `openai/` 这类供应商前缀和大小写）
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        let name = model.rsplit('/').next().unwrap_or(&model);
+        MODEL_ENCODINGS
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map(|(_, encoding)| *encoding)
+            .unwrap_or(Encoding::Cl100kBase)
+    }
+
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100kBase => cl100k_base_singleton(),
+            Encoding::O200kBase => o200k_base_singleton(),
+        }
+    }
+
+    /// 计算文本的 token 数
+    pub fn count(self, text: &str) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+        self.bpe().encode_ordinary(text).len()
+    }
+
+    /// 计算消息列表作为请求时的 token 数（含每条消息的格式开销）
+    pub fn count_messages(self, messages: &[ChatMessage]) -> usize {
+        let content: usize = messages
+            .iter()
+            .map(|m| TOKENS_PER_MESSAGE + self.count(&m.role) + self.count(&m.content) + self.count_extras(m))
+            .sum();
+        content + TOKENS_REPLY_PRIMING
+    }
+
+    /// 消息正文之外同样发送给上游的字段：参与者名称、工具调用及其参数、工具结果的调用ID
+    fn count_extras(self, message: &ChatMessage) -> usize {
+        let name = message.name.as_deref().map_or(0, |name| TOKENS_PER_NAME + self.count(name));
+        let tool_calls: usize = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| {
+                TOKENS_PER_TOOL_CALL
+                    + self.count(&call.id)
+                    + self.count(&call.function.name)
+                    + self.count(&call.function.arguments)
+            })
+            .sum();
+        let tool_call_id = message.tool_call_id.as_deref().map_or(0, |id| self.count(id));
+        name + tool_calls + tool_call_id
+    }
+}
+
+/// 计算文本在指定模型下的 token 数
+pub fn count_tokens(text: &str, model: &str) -> usize {
+    Encoding::for_model(model).count(text)
+}
+
+/// 计算消息列表在指定模型下的 token 数
+pub fn count_message_tokens(messages: &[ChatMessage], model: &str) -> usize {
+    Encoding::for_model(model).count_messages(messages)
+}
+
+/// Token 用量（OpenAI usage 格式）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+impl TokenUsage {
+    /// 按本地编码估算一次调用的用量
+    pub fn estimate(messages: &[ChatMessage], completion: &str, model: &str) -> Self {
+        let encoding = Encoding::for_model(model);
+        let prompt_tokens = encoding.count_messages(messages) as u32;
+        let completion_tokens = encoding.count(completion) as u32;
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    /// 累加多次调用的用量（如服务端执行工具调用的多轮请求）
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FunctionCall, ToolCall};
+
+    #[test]
+    fn test_for_model() {
+        assert_eq!(Encoding::for_model("gpt-4o-mini"), Encoding::O200kBase);
+        assert_eq!(Encoding::for_model("o3-mini"), Encoding::O200kBase);
+        assert_eq!(Encoding::for_model("gpt-4.1-nano"), Encoding::O200kBase);
+        // gpt-4o 必须先于 gpt-4 匹配
+        assert_eq!(Encoding::for_model("gpt-4-turbo"), Encoding::Cl100kBase);
+        assert_eq!(Encoding::for_model("gpt-3.5-turbo"), Encoding::Cl100kBase);
+        // 供应商前缀和大小写
+        assert_eq!(Encoding::for_model("openai/GPT-4o"), Encoding::O200kBase);
+        assert_eq!(Encoding::for_model("azure/openai/o1"), Encoding::O200kBase);
+        // 无公开编码表的模型
+        assert_eq!(Encoding::for_model("qwen3:8b"), Encoding::Cl100kBase);
+        assert_eq!(Encoding::for_model("deepseek/deepseek-chat"), Encoding::Cl100kBase);
+        assert_eq!(Encoding::for_model(""), Encoding::Cl100kBase);
+    }
+
+    #[test]
+    fn test_count_messages_overhead() {
+        let encoding = Encoding::Cl100kBase;
+        assert_eq!(encoding.count(""), 0);
+        assert_eq!(encoding.count_messages(&[]), TOKENS_REPLY_PRIMING);
+
+        let messages = [ChatMessage::system("你是助手"), ChatMessage::user("你好")];
+        let expected = 2 * TOKENS_PER_MESSAGE
+            + encoding.count("system")
+            + encoding.count("你是助手")
+            + encoding.count("user")
+            + encoding.count("你好")
+            + TOKENS_REPLY_PRIMING;
+        assert_eq!(encoding.count_messages(&messages), expected);
+    }
+
+    #[test]
+    fn test_count_messages_includes_tool_calls() {
+        let encoding = Encoding::O200kBase;
+        let arguments = r#"{"query": "上周约定的见面地点和时间", "top_k": 5}"#;
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: "memory_search".to_string(),
+                arguments: arguments.to_string(),
+            },
+        };
+        let plain = [ChatMessage::assistant("")];
+        let with_call = [ChatMessage::assistant("").with_tool_calls(vec![call])];
+        let added = encoding.count_messages(&with_call) - encoding.count_messages(&plain);
+        assert_eq!(
+            added,
+            TOKENS_PER_TOOL_CALL
+                + encoding.count("call_1")
+                + encoding.count("memory_search")
+                + encoding.count(arguments)
+        );
+
+        let result = [ChatMessage::tool("call_1", "[]")];
+        assert_eq!(
+            encoding.count_messages(&result),
+            TOKENS_PER_MESSAGE + encoding.count("tool") + encoding.count("[]") + encoding.count("call_1")
+                + TOKENS_REPLY_PRIMING
+        );
+    }
+
+    #[test]
+    fn test_usage_estimate() {
+        let messages = [ChatMessage::user("今天天气怎么样")];
+        let usage = TokenUsage::estimate(&messages, "晴，最高 25 度", "gpt-4o");
+        assert_eq!(usage.prompt_tokens as usize, count_message_tokens(&messages, "gpt-4o"));
+        assert_eq!(usage.completion_tokens as usize, count_tokens("晴，最高 25 度", "gpt-4o"));
+        assert_eq!(usage.total_tokens, usage.prompt_tokens + usage.completion_tokens);
+
+        let mut total = usage;
+        total += usage;
+        assert_eq!(total.total_tokens, usage.total_tokens * 2);
+    }
+}
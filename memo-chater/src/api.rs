@@ -4,6 +4,7 @@
 
 use axum::{
     extract::State,
+    http::StatusCode,
     response::{sse::Event, Sse, IntoResponse},
     Json,
 };
@@ -12,17 +13,20 @@ use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::{convert::Infallible, sync::Arc};
 
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use crate::state::AppState;
-use crate::pipeline::{ConversationPacket, PipelineConfig, PipelineTiming, ThinkingEntry};
-use crate::ai::AiClient;
-use crate::types::{ChatMessage, ShortTermMemory, ThinkingSource, MemorySource};
+use crate::pipeline::{
+    BackgroundJob, ConversationPacket, MemoryTools, PacketDiff, PacketField, PipelineConfig, PipelineTiming,
+    SnapshotInfo, ThinkingEntry, ToolRound, TopicGuard, TopicLockError, TurnTrace,
+};
+use crate::ai::{count_message_tokens, count_tokens, AiClient, StreamEvent, TokenUsage, ToolOptions};
+use crate::types::{ChatMessage, ShortTermMemory, ThinkingSource, MemorySource, ToolCall, ToolCallDelta};
 
 /// OpenAI 兼容的请求格式（扩展支持助手隔离）
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
-    pub messages: Vec<Message>,
+    pub messages: Vec<ChatMessage>,
     #[serde(default)]
     pub stream: bool,
     #[serde(default)]
@@ -35,12 +39,20 @@ pub struct ChatCompletionRequest {
     /// 话题ID（用于会话隔离）
     #[serde(default)]
     pub topic_id: Option<String>,
+    /// 流式选项
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// 工具定义（tools / tool_choice，透传给上游）
+    #[serde(flatten)]
+    pub tools: ToolOptions,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct Message {
-    pub role: String,
-    pub content: String,
+/// 流式选项（OpenAI 兼容）
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StreamOptions {
+    /// 在 [DONE] 之前额外发送一个携带 usage 的 chunk
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 /// OpenAI 兼容的响应格式
@@ -51,23 +63,16 @@ pub struct ChatCompletionResponse {
     pub created: i64,
     pub model: String,
     pub choices: Vec<Choice>,
-    pub usage: Usage,
+    pub usage: TokenUsage,
 }
 
 #[derive(Debug, Serialize)]
 pub struct Choice {
     pub index: u32,
-    pub message: Message,
+    pub message: ChatMessage,
     pub finish_reason: String,
 }
 
-#[derive(Debug, Serialize)]
-pub struct Usage {
-    pub prompt_tokens: u32,
-    pub completion_tokens: u32,
-    pub total_tokens: u32,
-}
-
 /// 流式响应的 chunk
 #[derive(Debug, Serialize)]
 pub struct ChatCompletionChunk {
@@ -76,6 +81,9 @@ pub struct ChatCompletionChunk {
     pub created: i64,
     pub model: String,
     pub choices: Vec<ChunkChoice>,
+    /// 仅在请求 include_usage 时的最后一个 chunk 中出现
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Serialize)]
@@ -91,6 +99,8 @@ pub struct Delta {
     pub role: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
 /// 模型列表响应
@@ -146,19 +156,49 @@ pub async fn chat_completions(
         }
     };
 
+    // 同一话题的请求串行执行，锁在整轮处理（含同步后处理）结束后释放
+    let guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            tracing::warn!("话题 {}:{} 忙碌: {}", assistant_id, topic_id, e);
+            let body = serde_json::json!({
+                "error": {
+                    "message": e.to_string(),
+                    "type": "topic_busy",
+                }
+            });
+            return (StatusCode::CONFLICT, Json(body)).into_response();
+        }
+    };
+
     if request.stream {
-        create_stream_response(state, request, assistant_id, topic_id).await.into_response()
+        create_stream_response(state, request, assistant_id, topic_id, guard).await.into_response()
     } else {
-        create_response(state, request, assistant_id, topic_id).await.into_response()
+        create_response(state, request, assistant_id, topic_id, guard).await.into_response()
     }
 }
 
+/// 按助手的并发配置获取话题锁
+async fn acquire_topic(
+    state: &AppState,
+    assistant_id: &str,
+    topic_id: &str,
+) -> Result<TopicGuard, TopicLockError> {
+    let concurrency = state.assistant_manager
+        .get_assistant(assistant_id)
+        .await
+        .map(|config| config.concurrency)
+        .unwrap_or_default();
+    state.topic_locks.acquire(assistant_id, topic_id, &concurrency).await
+}
+
 /// 创建非流式响应
 async fn create_response(
     state: Arc<AppState>,
     request: ChatCompletionRequest,
     assistant_id: String,
     topic_id: String,
+    _guard: TopicGuard,
 ) -> Json<ChatCompletionResponse> {
     // 获取或创建数据包
     let mut packet = match get_or_create_packet(&state, &assistant_id, &topic_id).await {
@@ -169,9 +209,12 @@ async fn create_response(
         }
     };
 
-    // 提取用户消息
-    let user_message = extract_user_message(&request.messages);
-    packet.append_user_message(&user_message);
+    // 提取本轮输入（用户消息或工具结果）
+    let mut input = TurnInput::from_request(&request.messages);
+    if let Err(e) = input.apply_to(&mut packet) {
+        return error_response(&e, &request.model);
+    }
+    let mut trace = TurnTrace::new(packet.turn_index);
 
     // 创建处理器上下文
     let ctx = match state.dispatcher.context_factory()
@@ -186,65 +229,126 @@ async fn create_response(
     // 获取流水线配置（从助手配置读取）
     let pipeline_config = ctx.assistant_config.pipeline.clone();
 
-    // 执行 on_user_message 处理器
-    if let Err(e) = state.dispatcher.dispatch(
+    // 执行 on_user_message 处理器（工具结果续接时跳过，本轮的用户消息已处理过）
+    if input.is_tool_results() {
+        tracing::debug!("工具结果续接，跳过 on_user_message 处理器");
+    } else if let Err(e) = trace.record(state.dispatcher.dispatch(
         PipelineTiming::OnUserMessage,
         &mut packet,
         &pipeline_config,
         &ctx,
-    ).await {
-        tracing::warn!("on_user_message 处理器执行失败: {}", e);
+    ).await) {
+        // 处理器按 abort 策略中止本轮：不调用 AI，也不保存数据包
+        tracing::error!("on_user_message 处理器中止本轮: {}", e);
+        save_trace(&state, &assistant_id, &topic_id, trace).await;
+        return error_response(&e.to_string(), &request.model);
     }
 
     // 执行 before_ai_call 处理器
-    if let Err(e) = state.dispatcher.dispatch(
+    if let Err(e) = trace.record(state.dispatcher.dispatch(
         PipelineTiming::BeforeAiCall,
         &mut packet,
         &pipeline_config,
         &ctx,
-    ).await {
-        tracing::warn!("before_ai_call 处理器执行失败: {}", e);
+    ).await) {
+        // 处理器按 abort 策略中止本轮：不调用 AI，也不保存数据包
+        tracing::error!("before_ai_call 处理器中止本轮: {}", e);
+        save_trace(&state, &assistant_id, &topic_id, trace).await;
+        return error_response(&e.to_string(), &request.model);
     }
 
-    // 调用 AI（使用 AiClient）
+    // 调用 AI（使用 AiClient）；开启记忆工具时在服务端循环执行记忆工具调用
     let model = packet.main_model.clone().unwrap_or_else(|| request.model.clone());
-    let ai_response = match ctx.ai_client.chat_with_model(&packet.messages, Some(&model)).await {
-        Ok(response) => response,
-        Err(e) => {
-            tracing::error!("AI 调用失败: {}", e);
-            return error_response(&format!("AI 调用失败: {}", e), &model);
+    let memory_tools = ctx.memory_tools_enabled();
+    let mut usage = TokenUsage::default();
+    let mut round = 0;
+    let (ai_response, tool_calls) = loop {
+        let tools = if memory_tools && round < ctx.memory_tools().max_rounds {
+            MemoryTools::merge_into(&request.tools)
+        } else {
+            request.tools.clone()
+        };
+        let completion = match ctx.ai_client.chat_with_tools(&packet.messages, Some(&model), &tools).await {
+            Ok(completion) => completion,
+            Err(e) => {
+                tracing::error!("AI 调用失败: {}", e);
+                return error_response(&format!("AI 调用失败: {}", e), &model);
+            }
+        };
+        // 上游未返回用量时按本地编码估算
+        usage += completion
+            .usage
+            .unwrap_or_else(|| TokenUsage::estimate(&packet.messages, &completion.content, &model));
+
+        if !memory_tools {
+            break (completion.content, completion.tool_calls);
+        }
+        let executed = round;
+        let outcome = MemoryTools::run(&mut packet, &ctx, &completion.content, completion.tool_calls, &mut round).await;
+        if round > executed {
+            save_memory_changes(&state, &packet).await;
+        }
+        match outcome {
+            ToolRound::Continue => {}
+            ToolRound::Finish(client_calls) => break (completion.content, client_calls),
         }
     };
 
+    // AI 发起工具调用：转发给客户端，等待工具结果
+    if !tool_calls.is_empty() {
+        packet.append_assistant_tool_calls(&ai_response, tool_calls);
+        let message = packet.messages.last().cloned().unwrap_or_else(|| ChatMessage::assistant(&ai_response));
+        save_pending_tool_calls(&state, &packet, trace, input.into_messages()).await;
+        return Json(ChatCompletionResponse {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+            object: "chat.completion".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model,
+            choices: vec![Choice {
+                index: 0,
+                message,
+                finish_reason: "tool_calls".to_string(),
+            }],
+            usage,
+        });
+    }
+
     // 追加 AI 响应
     packet.append_assistant_message(&ai_response);
 
+    // 保存当前轮次对话（ContentChunker 按轮次增量切块）
+    packet.save_conversation_turn();
+
     // 执行 after_ai_response 处理器
-    if let Err(e) = state.dispatcher.dispatch(
+    if let Err(e) = trace.record(state.dispatcher.dispatch(
         PipelineTiming::AfterAiResponse,
         &mut packet,
         &pipeline_config,
         &ctx,
-    ).await {
-        tracing::warn!("after_ai_response 处理器执行失败: {}", e);
+    ).await) {
+        tracing::error!("after_ai_response 处理器中止，跳过后续处理器: {}", e);
     }
 
     // 轮次结束处理
     packet.end_turn();
 
     // 持久化数据包
+    let (turn_index, trace_id) = (trace.turn_index, trace.id.clone());
     if let Err(e) = state.packet_storage.save(&packet).await {
         tracing::error!("保存数据包失败: {}", e);
     }
+    save_trace(&state, &assistant_id, &topic_id, trace).await;
 
     // 持久化消息到话题历史
-    let messages = vec![
-        ChatMessage::user(&user_message),
-        ChatMessage::assistant(&ai_response),
-    ];
+    let mut messages = input.into_messages();
+    messages.push(ChatMessage::assistant(&ai_response));
     if let Err(e) = state.assistant_manager.append_messages(&assistant_id, &topic_id, messages).await {
         tracing::error!("消息持久化失败: {}", e);
     }
+    save_snapshot(&state, &packet).await;
+
+    // 后台处理入队
+    enqueue_background(&state, &pipeline_config, &assistant_id, &topic_id, turn_index, &trace_id).await;
 
     Json(ChatCompletionResponse {
         id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
@@ -253,17 +357,10 @@ async fn create_response(
         model,
         choices: vec![Choice {
             index: 0,
-            message: Message {
-                role: "assistant".to_string(),
-                content: ai_response,
-            },
+            message: ChatMessage::assistant(&ai_response),
             finish_reason: "stop".to_string(),
         }],
-        usage: Usage {
-            prompt_tokens: 0,
-            completion_tokens: 0,
-            total_tokens: 0,
-        },
+        usage,
     })
 }
 
@@ -273,8 +370,11 @@ async fn create_stream_response(
     request: ChatCompletionRequest,
     assistant_id: String,
     topic_id: String,
+    guard: TopicGuard,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let stream = async_stream::stream! {
+        // 话题锁随流一起释放（流结束或客户端断开）
+        let _guard = guard;
         let id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
         let created = chrono::Utc::now().timestamp();
 
@@ -289,9 +389,14 @@ async fn create_stream_response(
             }
         };
 
-        // 提取用户消息
-        let user_message = extract_user_message(&request.messages);
-        packet.append_user_message(&user_message);
+        // 提取本轮输入（用户消息或工具结果）
+        let mut input = TurnInput::from_request(&request.messages);
+        if let Err(e) = input.apply_to(&mut packet) {
+            yield Ok(Event::default().data(serde_json::json!({"error": e}).to_string()));
+            yield Ok(Event::default().data("[DONE]"));
+            return;
+        }
+        let mut trace = TurnTrace::new(packet.turn_index);
 
         // 创建处理器上下文
         let ctx = match state.dispatcher.context_factory()
@@ -308,24 +413,36 @@ async fn create_stream_response(
         // 获取流水线配置（从助手配置读取）
         let pipeline_config = ctx.assistant_config.pipeline.clone();
 
-        // 执行 on_user_message 处理器
-        if let Err(e) = state.dispatcher.dispatch(
+        // 执行 on_user_message 处理器（工具结果续接时跳过，本轮的用户消息已处理过）
+        if input.is_tool_results() {
+            tracing::debug!("工具结果续接，跳过 on_user_message 处理器");
+        } else if let Err(e) = trace.record(state.dispatcher.dispatch(
             PipelineTiming::OnUserMessage,
             &mut packet,
             &pipeline_config,
             &ctx,
-        ).await {
-            tracing::warn!("on_user_message 处理器执行失败: {}", e);
+        ).await) {
+            // 处理器按 abort 策略中止本轮：不调用 AI，也不保存数据包
+            tracing::error!("on_user_message 处理器中止本轮: {}", e);
+            save_trace(&state, &assistant_id, &topic_id, trace).await;
+            yield Ok(Event::default().data(serde_json::json!({"error": e.to_string()}).to_string()));
+            yield Ok(Event::default().data("[DONE]"));
+            return;
         }
 
         // 执行 before_ai_call 处理器
-        if let Err(e) = state.dispatcher.dispatch(
+        if let Err(e) = trace.record(state.dispatcher.dispatch(
             PipelineTiming::BeforeAiCall,
             &mut packet,
             &pipeline_config,
             &ctx,
-        ).await {
-            tracing::warn!("before_ai_call 处理器执行失败: {}", e);
+        ).await) {
+            // 处理器按 abort 策略中止本轮：不调用 AI，也不保存数据包
+            tracing::error!("before_ai_call 处理器中止本轮: {}", e);
+            save_trace(&state, &assistant_id, &topic_id, trace).await;
+            yield Ok(Event::default().data(serde_json::json!({"error": e.to_string()}).to_string()));
+            yield Ok(Event::default().data("[DONE]"));
+            return;
         }
 
         let model = packet.main_model.clone().unwrap_or_else(|| request.model.clone());
@@ -352,46 +469,184 @@ async fn create_stream_response(
                 delta: Delta {
                     role: Some("assistant".to_string()),
                     content: None,
+                    tool_calls: None,
                 },
                 finish_reason: None,
             }],
+            usage: None,
         };
         yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
 
         // 流式调用 AI
         let mut full_response = String::new();
-        
-        match ctx.ai_client.chat_stream_with_model(&packet.messages, Some(&model)).await {
-            Ok(mut ai_stream) => {
-                while let Some(result) = ai_stream.next().await {
-                    match result {
-                        Ok(content) => {
-                            full_response.push_str(&content);
-                            let chunk = ChatCompletionChunk {
-                                id: id.clone(),
-                                object: "chat.completion.chunk".to_string(),
-                                created,
-                                model: model.clone(),
-                                choices: vec![ChunkChoice {
-                                    index: 0,
-                                    delta: Delta {
-                                        role: None,
-                                        content: Some(content),
-                                    },
-                                    finish_reason: None,
-                                }],
-                            };
-                            yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+        let include_usage = request.stream_options.as_ref().is_some_and(|o| o.include_usage);
+        let prompt_tokens = if include_usage {
+            count_message_tokens(&packet.messages, &model) as u32
+        } else {
+            0
+        };
+        let mut upstream_usage: Option<TokenUsage> = None;
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        // 开启记忆工具时工具调用增量先缓冲，确认是客户端工具后再整体转发
+        let memory_tools = ctx.memory_tools_enabled();
+        let mut round = 0;
+
+        loop {
+            // 提供记忆工具的轮次内容也先缓冲：本轮以记忆工具调用结束时不转发，与非流式只返回最后一轮一致
+            let hold = memory_tools && round < ctx.memory_tools().max_rounds;
+            let tools = if hold {
+                MemoryTools::merge_into(&request.tools)
+            } else {
+                request.tools.clone()
+            };
+            let mut round_calls: Vec<ToolCall> = Vec::new();
+            let mut round_content = String::new();
+
+            match ctx.ai_client.chat_stream_events_with_tools(&packet.messages, Some(&model), &tools).await {
+                Ok(mut ai_stream) => {
+                    // 执行 on_stream_start 处理器（记忆工具的后续轮次不再执行）
+                    if round == 0 {
+                        if let Err(e) = trace.record(state.dispatcher.dispatch(
+                            PipelineTiming::OnStreamStart,
+                            &mut packet,
+                            &pipeline_config,
+                            &ctx,
+                        ).await) {
+                            tracing::error!("on_stream_start 处理器中止，跳过后续处理器: {}", e);
                         }
-                        Err(e) => {
-                            tracing::error!("流式响应错误: {}", e);
-                            break;
+                    }
+
+                    while let Some(result) = ai_stream.next().await {
+                        match result {
+                            Ok(StreamEvent::Usage(usage)) => {
+                                *upstream_usage.get_or_insert_with(TokenUsage::default) += usage;
+                            }
+                            Ok(StreamEvent::ToolCalls(deltas)) => {
+                                // 累积成完整调用；未开启记忆工具时增量原样转发
+                                for delta in &deltas {
+                                    delta.apply_to(&mut round_calls);
+                                }
+                                if memory_tools {
+                                    continue;
+                                }
+                                let chunk = ChatCompletionChunk {
+                                    id: id.clone(),
+                                    object: "chat.completion.chunk".to_string(),
+                                    created,
+                                    model: model.clone(),
+                                    choices: vec![ChunkChoice {
+                                        index: 0,
+                                        delta: Delta {
+                                            role: None,
+                                            content: None,
+                                            tool_calls: Some(deltas),
+                                        },
+                                        finish_reason: None,
+                                    }],
+                                    usage: None,
+                                };
+                                yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+                            }
+                            Ok(StreamEvent::Content(content)) => {
+                                // 执行 on_stream_chunk 处理器（可改写或屏蔽 delta）
+                                let Some(content) = state.dispatcher.dispatch_chunk(
+                                    content,
+                                    &mut packet,
+                                    &pipeline_config,
+                                    &ctx,
+                                ).await else {
+                                    continue;
+                                };
+                                round_content.push_str(&content);
+                                if hold {
+                                    continue;
+                                }
+                                full_response.push_str(&content);
+                                let chunk = ChatCompletionChunk {
+                                    id: id.clone(),
+                                    object: "chat.completion.chunk".to_string(),
+                                    created,
+                                    model: model.clone(),
+                                    choices: vec![ChunkChoice {
+                                        index: 0,
+                                        delta: Delta {
+                                            role: None,
+                                            content: Some(content),
+                                            tool_calls: None,
+                                        },
+                                        finish_reason: None,
+                                    }],
+                                    usage: None,
+                                };
+                                yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+                            }
+                            Err(e) => {
+                                tracing::error!("流式响应错误: {}", e);
+                                break;
+                            }
                         }
                     }
                 }
+                Err(e) => {
+                    tracing::error!("AI 流式调用失败: {}", e);
+                    let chunk = ChatCompletionChunk {
+                        id: id.clone(),
+                        object: "chat.completion.chunk".to_string(),
+                        created,
+                        model: model.clone(),
+                        choices: vec![ChunkChoice {
+                            index: 0,
+                            delta: Delta {
+                                role: None,
+                                content: Some(format!("[错误] {}", e)),
+                                tool_calls: None,
+                            },
+                            finish_reason: None,
+                        }],
+                        usage: None,
+                    };
+                    yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+                    break;
+                }
             }
-            Err(e) => {
-                tracing::error!("AI 流式调用失败: {}", e);
+
+            if !memory_tools {
+                tool_calls = round_calls;
+                break;
+            }
+
+            // 执行记忆工具后继续下一轮；剩余的客户端工具调用整体转发
+            let cleaned = AiClient::strip_thinking_tags(&round_content);
+            let executed = round;
+            let outcome = MemoryTools::run(&mut packet, &ctx, &cleaned, round_calls, &mut round).await;
+            if round > executed {
+                save_memory_changes(&state, &packet).await;
+            }
+            round_calls = match outcome {
+                ToolRound::Continue => continue,
+                ToolRound::Finish(client_calls) => client_calls,
+            };
+            if hold && !round_content.is_empty() {
+                full_response.push_str(&round_content);
+                let chunk = ChatCompletionChunk {
+                    id: id.clone(),
+                    object: "chat.completion.chunk".to_string(),
+                    created,
+                    model: model.clone(),
+                    choices: vec![ChunkChoice {
+                        index: 0,
+                        delta: Delta {
+                            role: None,
+                            content: Some(round_content),
+                            tool_calls: None,
+                        },
+                        finish_reason: None,
+                    }],
+                    usage: None,
+                };
+                yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+            }
+            if !round_calls.is_empty() {
                 let chunk = ChatCompletionChunk {
                     id: id.clone(),
                     object: "chat.completion.chunk".to_string(),
@@ -401,13 +656,43 @@ async fn create_stream_response(
                         index: 0,
                         delta: Delta {
                             role: None,
-                            content: Some(format!("[错误] {}", e)),
+                            content: None,
+                            tool_calls: Some(round_calls.iter().enumerate().map(|(i, c)| c.to_delta(i)).collect()),
                         },
                         finish_reason: None,
                     }],
+                    usage: None,
                 };
                 yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
             }
+            tool_calls = round_calls;
+            break;
+        }
+
+        // 发出流式处理器缓冲的剩余内容
+        if let Some(content) = state.dispatcher.finish_stream(
+            &mut packet,
+            &pipeline_config,
+            &ctx,
+        ).await {
+            full_response.push_str(&content);
+            let chunk = ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model.clone(),
+                choices: vec![ChunkChoice {
+                    index: 0,
+                    delta: Delta {
+                        role: None,
+                        content: Some(content),
+                        tool_calls: None,
+                    },
+                    finish_reason: None,
+                }],
+                usage: None,
+            };
+            yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
         }
 
         // 发送结束标记
@@ -421,11 +706,34 @@ async fn create_stream_response(
                 delta: Delta {
                     role: None,
                     content: None,
+                    tool_calls: None,
                 },
-                finish_reason: Some("stop".to_string()),
+                finish_reason: Some(if tool_calls.is_empty() { "stop" } else { "tool_calls" }.to_string()),
             }],
+            usage: None,
         };
         yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+
+        // 用量 chunk（上游未返回时按本地编码估算）
+        if include_usage {
+            let usage = upstream_usage.unwrap_or_else(|| {
+                let completion_tokens = count_tokens(&full_response, &model) as u32;
+                TokenUsage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
+                }
+            });
+            let chunk = ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model.clone(),
+                choices: Vec::new(),
+                usage: Some(usage),
+            };
+            yield Ok(Event::default().data(serde_json::to_string(&chunk).unwrap()));
+        }
 yield Ok(Event::default().data("[DONE]"));
 
         // 打印AI完整响应
@@ -438,16 +746,26 @@ yield Ok(Event::default().data("[DONE]"));
 
 // 过滤思考标签后追加 AI 响应到数据包
         let cleaned_response = AiClient::strip_thinking_tags(&full_response);
+
+        // AI 发起工具调用：本轮未结束，等待客户端回传工具结果
+        if !tool_calls.is_empty() {
+            packet.append_assistant_tool_calls(&cleaned_response, tool_calls);
+            save_pending_tool_calls(&state, &packet, trace, input.into_messages()).await;
+            yield Ok(Event::default().data("[POST_PROCESS_DONE]"));
+            return;
+        }
+
         packet.append_assistant_message(&cleaned_response);
 
         // 保存当前轮次对话（在后处理之前，避免被清理器清空）
         packet.save_conversation_turn();
 
         // 后处理（同步执行，确保在流结束前完成）
-        let post_result = post_process_and_save(
+        post_process_and_save(
             &state,
             packet,
-            &user_message,
+            trace,
+            input.into_messages(),
             &full_response,
             &assistant_id,
             &topic_id,
@@ -455,17 +773,69 @@ yield Ok(Event::default().data("[DONE]"));
 
         // 发送后处理完成标记（前端可以据此刷新数据，可以继续下一次对话）
         yield Ok(Event::default().data("[POST_PROCESS_DONE]"));
+    };
 
-        // 启动异步后处理任务（不阻塞下一次对话）
-        if let Some(result) = post_result {
-            let state_clone = state.clone();
-            tokio::spawn(async move {
-                background_process(state_clone, result).await;
-            });
+    Sse::new(stream)
+}
+
+/// AI 发起工具调用时的保存：本轮尚未结束，只持久化数据包、追踪和消息，
+/// 不执行 after_ai_response 处理器，等客户端回传工具结果后继续
+async fn save_pending_tool_calls(
+    state: &AppState,
+    packet: &ConversationPacket,
+    trace: TurnTrace,
+    mut messages: Vec<ChatMessage>,
+) {
+    if let Err(e) = state.packet_storage.save(packet).await {
+        tracing::error!("保存数据包失败: {}", e);
+    }
+    save_trace(state, &packet.assistant_id, &packet.topic_id, trace).await;
+
+    if let Some(tool_message) = packet.messages.last() {
+        messages.push(tool_message.clone());
+    }
+    if let Err(e) = state.assistant_manager.append_messages(&packet.assistant_id, &packet.topic_id, messages).await {
+        tracing::error!("消息持久化失败: {}", e);
+    }
+}
+
+/// 记忆工具执行后，立即把短期记忆的改动写回已保存的数据包
+///
+/// forget_memory 会直接删除话题记忆库和长期记忆库中的条目；之后的调用失败或客户端断开时
+/// 本轮不会保存，数据包中残留的记忆会在下一轮被重新向量化。这里只写回短期记忆，不写入本轮消息
+async fn save_memory_changes(state: &Arc<AppState>, packet: &ConversationPacket) {
+    let mut saved = match get_or_create_packet(state, &packet.assistant_id, &packet.topic_id).await {
+        Ok(saved) => saved,
+        Err(e) => {
+            tracing::error!("保存记忆工具改动失败: {}", e);
+            return;
         }
     };
+    saved.copy_field_from(packet, PacketField::ShortTermMemory);
+    if let Err(e) = state.packet_storage.save(&saved).await {
+        tracing::error!("保存记忆工具改动失败: {}", e);
+    }
+}
 
-    Sse::new(stream)
+/// 保存本轮执行追踪（失败只记录日志，不影响对话）
+async fn save_trace(state: &AppState, assistant_id: &str, topic_id: &str, trace: TurnTrace) {
+    if let Err(e) = state.trace_storage.append(assistant_id, topic_id, trace).await {
+        tracing::warn!("保存执行追踪失败: {}", e);
+    }
+}
+
+/// 保存轮次快照（需在消息写入话题历史之后调用），失败不影响对话
+async fn save_snapshot(state: &AppState, packet: &ConversationPacket) {
+    let history_len = match state.assistant_manager.get_history(&packet.assistant_id, &packet.topic_id).await {
+        Ok(history) => history.len(),
+        Err(e) => {
+            tracing::warn!("读取话题历史失败，跳过快照: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = state.packet_storage.save_snapshot(packet, history_len).await {
+        tracing::warn!("保存快照失败: {}", e);
+    }
 }
 
 /// 获取或创建对话数据包
@@ -474,10 +844,14 @@ async fn get_or_create_packet(
     assistant_id: &str,
     topic_id: &str,
 ) -> Result<ConversationPacket, String> {
-    // 尝试从存储加载
-    if let Ok(Some(packet)) = state.packet_storage.load(assistant_id, topic_id).await {
-        tracing::debug!("加载已有数据包: {}:{}", assistant_id, topic_id);
-        return Ok(packet);
+    // 尝试从存储加载（读取失败时不能新建，否则保存时会覆盖无法读取的数据包）
+    match state.packet_storage.load(assistant_id, topic_id).await {
+        Ok(Some(packet)) => {
+            tracing::debug!("加载已有数据包: {}:{}", assistant_id, topic_id);
+            return Ok(packet);
+        }
+        Ok(None) => {}
+        Err(e) => return Err(format!("加载数据包失败: {}", e)),
     }
 
     // 获取助手配置
@@ -508,14 +882,74 @@ async fn get_or_create_packet(
     Ok(packet)
 }
 
-/// 从请求消息中提取用户消息
-fn extract_user_message(messages: &[Message]) -> String {
+/// 本轮请求带来的新消息
+enum TurnInput {
+    /// 用户发言
+    User(ChatMessage),
+    /// 客户端回传的工具结果（上一次响应发起了工具调用，本轮继续）
+    ToolResults(Vec<ChatMessage>),
+}
+
+impl TurnInput {
+    /// 从请求消息中提取：以 tool 消息结尾时取末尾连续的工具结果，否则取最后一条用户消息
+    fn from_request(messages: &[ChatMessage]) -> Self {
+        let tool_results: Vec<ChatMessage> = messages
+            .iter()
+            .rev()
+            .take_while(|m| m.role == "tool")
+            .map(|m| ChatMessage { meta: None, ..m.clone() })
+            .collect();
+        if tool_results.is_empty() {
+            TurnInput::User(extract_user_message(messages))
+        } else {
+            TurnInput::ToolResults(tool_results.into_iter().rev().collect())
+        }
+    }
+
+    /// 追加到数据包（工具结果同时作为 ToolResult 短期记忆）
+    ///
+    /// 没有等待中调用的工具结果（重复提交或调用ID不存在）被忽略，也不会写入话题历史；
+    /// 全部被忽略时返回错误
+    fn apply_to(&mut self, packet: &mut ConversationPacket) -> Result<(), String> {
+        match self {
+            TurnInput::User(message) => packet.append_user_chat_message(message.clone()),
+            TurnInput::ToolResults(results) => {
+                results.retain(|result| {
+                    let accepted = packet.append_tool_result(result.clone());
+                    if !accepted {
+                        tracing::warn!("忽略没有等待中调用的工具结果: {:?}", result.tool_call_id);
+                    }
+                    accepted
+                });
+                if results.is_empty() {
+                    return Err("没有等待结果的工具调用".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_tool_results(&self) -> bool {
+        matches!(self, TurnInput::ToolResults(_))
+    }
+
+    /// 需要写入话题历史的消息
+    fn into_messages(self) -> Vec<ChatMessage> {
+        match self {
+            TurnInput::User(message) => vec![message],
+            TurnInput::ToolResults(results) => results,
+        }
+    }
+}
+
+/// 从请求消息中提取用户消息（保留多模态内容和 name，丢弃客户端传入的内部标记）
+fn extract_user_message(messages: &[ChatMessage]) -> ChatMessage {
     messages
         .iter()
         .rev()
         .find(|m| m.role == "user")
-        .map(|m| m.content.clone())
-        .unwrap_or_default()
+        .map(|m| ChatMessage { meta: None, ..m.clone() })
+        .unwrap_or_else(|| ChatMessage::user(""))
 }
 
 /// 创建错误响应
@@ -527,37 +961,23 @@ fn error_response(error: &str, model: &str) -> Json<ChatCompletionResponse> {
         model: model.to_string(),
         choices: vec![Choice {
             index: 0,
-            message: Message {
-                role: "assistant".to_string(),
-                content: format!("[错误] {}", error),
-            },
+            message: ChatMessage::assistant(format!("[错误] {}", error)),
             finish_reason: "stop".to_string(),
         }],
-        usage: Usage {
-            prompt_tokens: 0,
-            completion_tokens: 0,
-            total_tokens: 0,
-        },
+        usage: TokenUsage::default(),
     })
 }
 
-/// 后处理：执行 after_ai_response 处理器 + 消息持久化
-/// 同步后处理结果，用于传递给异步后处理任务
-struct PostProcessResult {
-    packet: ConversationPacket,
-    pipeline_config: PipelineConfig,
-    assistant_id: String,
-    topic_id: String,
-}
-
+/// 后处理：执行 after_ai_response 处理器 + 消息持久化，并为后台处理器入队任务
 async fn post_process_and_save(
     state: &Arc<AppState>,
     mut packet: ConversationPacket,
-    user_message: &str,
+    mut trace: TurnTrace,
+    input_messages: Vec<ChatMessage>,
     ai_response: &str,
     assistant_id: &str,
     topic_id: &str,
-) -> Option<PostProcessResult> {
+) {
     tracing::info!("post_process_and_save: {}:{}", assistant_id, topic_id);
 
     // 创建处理器上下文
@@ -566,7 +986,7 @@ async fn post_process_and_save(
         Ok(c) => c,
         Err(e) => {
             tracing::error!("创建上下文失败: {}", e);
-            return None;
+            return;
         }
     };
 
@@ -574,82 +994,156 @@ async fn post_process_and_save(
     let pipeline_config = ctx.assistant_config.pipeline.clone();
 
     // 执行 after_ai_response 处理器（同步）
-    if let Err(e) = state.dispatcher.dispatch(
+    if let Err(e) = trace.record(state.dispatcher.dispatch(
         PipelineTiming::AfterAiResponse,
         &mut packet,
         &pipeline_config,
         &ctx,
-    ).await {
-        tracing::warn!("after_ai_response 处理器执行失败: {}", e);
+    ).await) {
+        tracing::error!("after_ai_response 处理器中止，跳过后续处理器: {}", e);
     }
 
     // 轮次结束处理
+    let (turn_index, trace_id) = (trace.turn_index, trace.id.clone());
     packet.end_turn();
 
     // 持久化数据包
     if let Err(e) = state.packet_storage.save(&packet).await {
         tracing::error!("保存数据包失败: {}", e);
     }
+    save_trace(state, assistant_id, topic_id, trace).await;
 
     // 持久化消息到话题历史
-    let messages = vec![
-        ChatMessage::user(user_message),
-        ChatMessage::assistant(ai_response),
-    ];
+    let mut messages = input_messages;
+    messages.push(ChatMessage::assistant(ai_response));
     if let Err(e) = state.assistant_manager.append_messages(assistant_id, topic_id, messages).await {
         tracing::error!("消息持久化失败: {}", e);
     } else {
         tracing::info!("消息已保存: {}:{}", assistant_id, topic_id);
     }
+    save_snapshot(state, &packet).await;
 
-    // 返回结果，用于异步后处理
-    Some(PostProcessResult {
-        packet,
-        pipeline_config,
-        assistant_id: assistant_id.to_string(),
-        topic_id: topic_id.to_string(),
-    })
+    // 后台处理入队（不阻塞下一次对话）
+    enqueue_background(state, &pipeline_config, assistant_id, topic_id, turn_index, &trace_id).await;
 }
 
-/// 异步后处理任务（不阻塞下一次对话）
-async fn background_process(
-    state: Arc<AppState>,
-    mut result: PostProcessResult,
+/// 有后台处理器配置时入队后台任务
+async fn enqueue_background(
+    state: &AppState,
+    pipeline_config: &PipelineConfig,
+    assistant_id: &str,
+    topic_id: &str,
+    turn_index: u64,
+    trace_id: &str,
 ) {
-    if result.pipeline_config.background_process.is_empty() {
+    if pipeline_config.background_process.is_empty() {
         tracing::debug!("无后台处理器配置，跳过");
         return;
     }
+    if let Err(e) = state.job_queue.enqueue(assistant_id, topic_id, turn_index, trace_id).await {
+        tracing::error!("后台任务入队失败: {}", e);
+    }
+}
 
-    tracing::info!("启动后台处理: {}:{}", result.assistant_id, result.topic_id);
+/// 后台任务工作循环
+///
+/// 逐个执行到期的后台任务；无任务时等待新任务入队，或定期检查等待重试的任务
+pub async fn run_background_worker(state: Arc<AppState>) {
+    match state.job_queue.recover().await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("恢复 {} 个未完成的后台任务", count),
+        Err(e) => tracing::error!("恢复后台任务失败: {}", e),
+    }
 
-    // 创建处理器上下文
-    let ctx = match state.dispatcher.context_factory()
-        .create(&result.assistant_id, &result.topic_id).await {
-        Ok(c) => c,
-        Err(e) => {
-            tracing::error!("后台处理创建上下文失败: {}", e);
-            return;
+    loop {
+        let job = match state.job_queue.claim_next().await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                state.job_queue.wait(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("读取后台任务失败: {}", e);
+                state.job_queue.wait(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let result = match run_background_job(&state, &job).await {
+            Ok(()) => state.job_queue.complete(&job.id).await,
+            Err(e) => state.job_queue.fail(&job, &e).await,
+        };
+        if let Err(e) = result {
+            tracing::error!("更新后台任务 {} 状态失败: {}", job.id, e);
         }
+    }
+}
+
+/// 执行单个后台任务
+///
+/// 在最新数据包的副本上执行 background_process 处理器，
+/// 完成后重新读取最新数据包，只合并后台处理器做出的改动，避免覆盖期间进行的新一轮对话
+async fn run_background_job(state: &AppState, job: &BackgroundJob) -> Result<(), String> {
+    tracing::info!("启动后台处理: {}:{} (任务 {})", job.assistant_id, job.topic_id, job.id);
+
+    // 创建处理器上下文
+    let ctx = state.dispatcher.context_factory()
+        .create(&job.assistant_id, &job.topic_id).await
+        .map_err(|e| format!("创建上下文失败: {}", e))?;
+
+    let pipeline_config = ctx.assistant_config.pipeline.clone();
+    if pipeline_config.background_process.is_empty() {
+        tracing::debug!("无后台处理器配置，跳过");
+        return Ok(());
+    }
+
+    let Some(base) = state.packet_storage.load(&job.assistant_id, &job.topic_id).await
+        .map_err(|e| format!("加载数据包失败: {}", e))? else {
+        tracing::warn!("后台任务的数据包不存在，跳过: {}:{}", job.assistant_id, job.topic_id);
+        return Ok(());
     };
 
     // 执行 background_process 处理器
-    if let Err(e) = state.dispatcher.dispatch(
+    let mut packet = base.clone();
+    let mut trace = match &job.trace_id {
+        Some(id) => TurnTrace::with_id(id.clone(), job.turn_index),
+        None => TurnTrace::new(job.turn_index),
+    };
+    let result = trace.record(state.dispatcher.dispatch(
         PipelineTiming::BackgroundProcess,
-        &mut result.packet,
-        &result.pipeline_config,
+        &mut packet,
+        &pipeline_config,
         &ctx,
-    ).await {
-        tracing::warn!("background_process 处理器执行失败: {}", e);
+    ).await);
+    save_trace(state, &job.assistant_id, &job.topic_id, trace).await;
+    // abort 策略视为任务失败，交给队列重试
+    result.map_err(|e| e.to_string())?;
+
+    // 合并到最新数据包（持有话题锁，避免与对话请求交错写入）
+    let _guard = state.topic_locks.lock(&job.assistant_id, &job.topic_id).await;
+    let mut latest = state.packet_storage.load(&job.assistant_id, &job.topic_id).await
+        .map_err(|e| format!("重新加载数据包失败: {}", e))?
+        .unwrap_or_else(|| base.clone());
+    // 执行期间话题被恢复到更早的快照，本次结果属于已撤销的轮次
+    if latest.turn_index < base.turn_index {
+        tracing::info!(
+            "数据包已恢复到轮次 {}，丢弃后台处理结果: {}:{}",
+            latest.turn_index, job.assistant_id, job.topic_id
+        );
+        return Ok(());
     }
+    latest.merge_changes(&base, &packet);
 
-    // 持久化更新后的数据包
-    if let Err(e) = state.packet_storage.save(&result.packet).await {
-        tracing::error!("后台处理保存数据包失败: {}", e);
-    }
+    state.packet_storage.save(&latest).await
+        .map_err(|e| format!("后台处理保存数据包失败: {}", e))?;
+    // 刷新当前轮的快照，使回滚后保留后台处理结果
+    save_snapshot(state, &latest).await;
 
-    tracing::info!("后台处理完成: {}:{}", result.assistant_id, result.topic_id);
-}// ==================== Packet API ====================
+    tracing::info!("后台处理完成: {}:{}", job.assistant_id, job.topic_id);
+    Ok(())
+}
+
+// ==================== Packet API ====================
 
 /// 获取对话数据包（思考池和短期记忆）的响应结构
 #[derive(Debug, Serialize)]
@@ -696,10 +1190,18 @@ pub struct ShortTermMemoryDto {
     /// 是否需要展开（前端控制）
     #[serde(default)]
     pub should_expand: bool,
+    /// 相关性得分
+    #[serde(default = "default_memory_score")]
+    pub relevance: f32,
+    /// 置信度
+    #[serde(default = "default_memory_score")]
+    pub confidence: f32,
     pub source: String,
     pub timestamp: String,
 }
 
+fn default_memory_score() -> f32 { 1.0 }
+
 /// 更新思考池请求
 #[derive(Debug, Deserialize)]
 pub struct UpdateThinkingPoolRequest {
@@ -730,6 +1232,8 @@ impl From<&ShortTermMemory> for ShortTermMemoryDto {
             content: mem.content.clone(),
             memory_type: mem.memory_type.clone(),
             should_expand: mem.should_expand,
+            relevance: mem.relevance,
+            confidence: mem.confidence,
             source: format!("{:?}", mem.source),
             timestamp: mem.timestamp.to_rfc3339(),
         }
@@ -795,6 +1299,18 @@ pub async fn update_thinking_pool(
 ) -> Json<PacketMemoryResponse> {
     tracing::debug!("更新思考池: {}:{}", assistant_id, topic_id);
     
+    // 与对话请求互斥，避免覆盖进行中的一轮
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            return Json(PacketMemoryResponse {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            });
+        }
+    };
+    
     // 加载或创建数据包
     let mut packet = match get_or_create_packet(&state, &assistant_id, &topic_id).await {
         Ok(p) => p,
@@ -854,6 +1370,18 @@ pub async fn update_short_term_memory(
 ) -> Json<PacketMemoryResponse> {
     tracing::debug!("更新短期记忆: {}:{}", assistant_id, topic_id);
     
+    // 与对话请求互斥，避免覆盖进行中的一轮
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            return Json(PacketMemoryResponse {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            });
+        }
+    };
+    
     // 加载或创建数据包
     let mut packet = match get_or_create_packet(&state, &assistant_id, &topic_id).await {
         Ok(p) => p,
@@ -868,16 +1396,24 @@ pub async fn update_short_term_memory(
     
     // 更新短期记忆
 packet.short_term_memory = req.short_term_memory.iter().map(|dto| {
+        // 内容未修改的记忆保留提交标记，修改过的重新提交到长期记忆库
+        let committed = packet
+            .short_term_memory
+            .iter()
+            .any(|m| m.id == dto.id && m.committed && m.content == dto.content);
         ShortTermMemory {
             id: dto.id.clone(),
             summary: dto.summary.clone(),
             content: dto.content.clone(),
             memory_type: dto.memory_type.clone(),
             should_expand: dto.should_expand,
+            relevance: dto.relevance,
+            confidence: dto.confidence,
             source: parse_memory_source(&dto.source),
             timestamp: chrono::DateTime::parse_from_rfc3339(&dto.timestamp)
                 .map(|dt| dt.with_timezone(&chrono::Utc))
                 .unwrap_or_else(|_| chrono::Utc::now()),
+            committed,
         }
     }).collect();
     
@@ -928,4 +1464,286 @@ fn parse_memory_source(s: &str) -> MemorySource {
         "ToolResult" => MemorySource::ToolResult,
         _ => MemorySource::CurrentConversation,
     }
-}
\ No newline at end of file
+}
+
+// ==================== Snapshot API ====================
+
+/// 快照 API 响应
+#[derive(Debug, Serialize)]
+pub struct SnapshotResponse<T> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+impl<T> SnapshotResponse<T> {
+    fn ok(data: T) -> Json<Self> {
+        Json(Self { success: true, data: Some(data), error: None })
+    }
+
+    fn err(error: impl ToString) -> Json<Self> {
+        Json(Self { success: false, data: None, error: Some(error.to_string()) })
+    }
+}
+
+/// 快照对比查询参数
+#[derive(Debug, Deserialize)]
+pub struct SnapshotDiffQuery {
+    pub from: u64,
+    pub to: u64,
+}
+
+/// 列出话题的轮次快照（从旧到新）
+pub async fn list_snapshots(
+    State(state): State<Arc<AppState>>,
+    Path((assistant_id, topic_id)): Path<(String, String)>,
+) -> Json<SnapshotResponse<Vec<SnapshotInfo>>> {
+    match state.packet_storage.list_snapshots(&assistant_id, &topic_id).await {
+        Ok(snapshots) => SnapshotResponse::ok(snapshots),
+        Err(e) => SnapshotResponse::err(e),
+    }
+}
+
+/// 对比两个轮次快照
+pub async fn diff_snapshots(
+    State(state): State<Arc<AppState>>,
+    Path((assistant_id, topic_id)): Path<(String, String)>,
+    Query(query): Query<SnapshotDiffQuery>,
+) -> Json<SnapshotResponse<PacketDiff>> {
+    let mut snapshots = Vec::with_capacity(2);
+    for turn in [query.from, query.to] {
+        match state.packet_storage.load_snapshot(&assistant_id, &topic_id, turn).await {
+            Ok(Some(snapshot)) => snapshots.push(snapshot),
+            Ok(None) => return SnapshotResponse::err(format!("快照不存在: 轮次 {}", turn)),
+            Err(e) => return SnapshotResponse::err(e),
+        }
+    }
+    SnapshotResponse::ok(PacketDiff::between(&snapshots[0], &snapshots[1]))
+}
+
+/// 恢复到指定轮次的快照
+///
+/// 数据包回到该轮结束时的状态，更新的快照和之后轮次的后台任务被删除，
+/// 之后产生的记忆向量被清理，话题历史截断到该轮
+pub async fn restore_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path((assistant_id, topic_id, turn_index)): Path<(String, String, u64)>,
+) -> Json<SnapshotResponse<SnapshotInfo>> {
+    tracing::info!("恢复快照: {}:{} 轮次 {}", assistant_id, topic_id, turn_index);
+
+    // 与对话请求互斥，避免回滚与进行中的一轮交错
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return SnapshotResponse::err(e),
+    };
+
+    let snapshot = match state.packet_storage.restore_snapshot(&assistant_id, &topic_id, turn_index).await {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return SnapshotResponse::err(format!("快照不存在: 轮次 {}", turn_index)),
+        Err(e) => return SnapshotResponse::err(e),
+    };
+
+    // 取消被撤销轮次的后台任务
+    match state.job_queue.cancel_after(&assistant_id, &topic_id, turn_index).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("恢复快照后取消 {} 个后台任务", count),
+        Err(e) => return SnapshotResponse::err(format!("取消后台任务失败: {}", e)),
+    }
+
+    // 截断话题历史
+    let history = match state.assistant_manager.get_history(&assistant_id, &topic_id).await {
+        Ok(history) => history,
+        Err(e) => return SnapshotResponse::err(format!("读取话题历史失败: {}", e)),
+    };
+    if history.len() > snapshot.history_len {
+        if let Err(e) = state.assistant_manager
+            .save_history(&assistant_id, &topic_id, &history[..snapshot.history_len])
+            .await
+        {
+            return SnapshotResponse::err(format!("截断话题历史失败: {}", e));
+        }
+    }
+
+    SnapshotResponse::ok(SnapshotInfo::from(&snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    use crate::assistant::{AssistantConfig, TopicType};
+    use crate::pipeline::processors::short_term_vectorizer::{ShortTermVectorFile, ShortTermVectorizer, VectorizedMemory};
+    use crate::pipeline::{
+        create_all_processors, JobQueue, PacketStorage, PipelineDispatcher, ProcessorContext,
+        ProcessorContextFactory, TopicLocks, TraceStorage,
+    };
+
+    fn test_state(dir: &std::path::Path) -> Arc<AppState> {
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir);
+        let factory = ProcessorContextFactory::new(
+            ctx.ai_client.clone(),
+            ctx.global_config.clone(),
+            ctx.assistant_manager.clone(),
+            ctx.memory_manager.clone(),
+        );
+        let mut dispatcher = PipelineDispatcher::new(Arc::new(factory));
+        dispatcher.register_all(create_all_processors());
+        Arc::new(AppState {
+            config: (*ctx.global_config).clone(),
+            assistant_manager: ctx.assistant_manager.clone(),
+            dispatcher: Arc::new(dispatcher),
+            packet_storage: Arc::new(PacketStorage::new(dir.to_path_buf())),
+            trace_storage: Arc::new(TraceStorage::new(dir.to_path_buf())),
+            job_queue: Arc::new(JobQueue::new(dir)),
+            topic_locks: Arc::new(TopicLocks::new()),
+            memory_manager: ctx.memory_manager.clone(),
+        })
+    }
+
+    fn packet() -> ConversationPacket {
+        ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        )
+    }
+
+    fn vector(id: &str, timestamp: DateTime<Utc>, archived: bool) -> VectorizedMemory {
+        VectorizedMemory {
+            timestamp: timestamp.to_rfc3339(),
+            archived,
+            ..VectorizedMemory::for_test(id, vec![1.0])
+        }
+    }
+
+    /// 完成一轮：写入历史、结束轮次并保存数据包和快照
+    async fn finish_turn(state: &AppState, packet: &mut ConversationPacket, turn: usize) {
+        // 先写入数据包以创建话题目录
+        state.packet_storage.save(packet).await.unwrap();
+        state.assistant_manager
+            .append_message(&packet.assistant_id, &packet.topic_id, ChatMessage::user(format!("问题 {}", turn)))
+            .await
+            .unwrap();
+        state.assistant_manager
+            .append_message(&packet.assistant_id, &packet.topic_id, ChatMessage::assistant(format!("回答 {}", turn)))
+            .await
+            .unwrap();
+        packet.end_turn();
+        state.packet_storage.save(packet).await.unwrap();
+        save_snapshot(state, packet).await;
+    }
+
+    async fn pause() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    #[tokio::test]
+    async fn test_restore_snapshot_end_to_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let archived_before = Utc::now();
+        pause().await;
+
+        // 第 1 轮：产生记忆 m1，快照轮次 1
+        let mut packet = packet();
+        packet.short_term_memory.push(ShortTermMemory::for_test("m1"));
+        finish_turn(&state, &mut packet, 0).await;
+        pause().await;
+
+        // 第 2 轮：产生记忆 m2、被淘汰的 m3，m1 被淘汰归档，并排队后台任务
+        packet.short_term_memory = vec![ShortTermMemory::for_test("m2")];
+        state.job_queue.enqueue("ast_test", "topic_test", packet.turn_index, "trace_2").await.unwrap();
+        state.job_queue.enqueue("ast_test", "topic_other", 5, "trace_other").await.unwrap();
+        let mut file = ShortTermVectorFile::new("test-embedding".to_string(), 1);
+        file.vectors = vec![
+            vector("m0", archived_before, true),
+            vector("m1", Utc::now(), true),
+            vector("m2", Utc::now(), false),
+            vector("m3", Utc::now(), true),
+        ];
+        let vector_path = dir.path()
+            .join("assistants/ast_test/topics/topic_test/short_term_vectors.json");
+        ShortTermVectorizer::save_vector_file(&vector_path, &file).await.unwrap();
+        finish_turn(&state, &mut packet, 1).await;
+
+        let response = restore_snapshot(
+            State(state.clone()),
+            Path(("ast_test".to_string(), "topic_test".to_string(), 1)),
+        )
+        .await;
+        assert!(response.success, "{:?}", response.error);
+
+        // 数据包和历史回到第 1 轮结束时
+        let restored = state.packet_storage.load("ast_test", "topic_test").await.unwrap().unwrap();
+        assert_eq!(restored.turn_index, 1);
+        assert_eq!(restored.short_term_memory[0].id, "m1");
+        assert_eq!(state.assistant_manager.get_history("ast_test", "topic_test").await.unwrap().len(), 2);
+        assert!(state.packet_storage.load_snapshot("ast_test", "topic_test", 2).await.unwrap().is_none());
+
+        // 第 2 轮的后台任务被取消，其他话题不受影响
+        let jobs = state.job_queue.list(None).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].topic_id, "topic_other");
+
+        // 向量文件：m1 重新进入短期记忆，快照前归档的 m0 保留，之后产生的 m2/m3 删除
+        let file = ShortTermVectorizer::load_vector_file(&vector_path).await.unwrap().unwrap();
+        let ids: Vec<(&str, bool)> = file.vectors.iter().map(|v| (v.id.as_str(), v.archived)).collect();
+        assert_eq!(ids, vec![("m0", true), ("m1", false)]);
+    }
+
+    #[test]
+    fn test_turn_input_ignores_unexpected_tool_results() {
+        use crate::types::{FunctionCall, ToolCall};
+
+        let mut packet = packet();
+        packet.append_user_message("北京天气怎么样");
+        packet.append_assistant_tool_calls("", vec![ToolCall {
+            id: "call_1".to_string(),
+            call_type: "function".to_string(),
+            function: FunctionCall { name: "get_weather".to_string(), arguments: "{}".to_string() },
+        }]);
+
+        let request = vec![
+            ChatMessage::user("北京天气怎么样"),
+            ChatMessage::tool("call_1", "晴"),
+            ChatMessage::tool("call_unknown", "雨"),
+        ];
+        let mut input = TurnInput::from_request(&request);
+        input.apply_to(&mut packet).unwrap();
+        // 只有对应等待中调用的结果写入数据包和话题历史
+        let history = input.into_messages();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].tool_call_id.as_deref(), Some("call_1"));
+
+        // 客户端重试重复提交同一结果
+        let mut retry = TurnInput::from_request(&request[..2]);
+        assert!(retry.apply_to(&mut packet).is_err());
+        assert_eq!(packet.messages.iter().filter(|m| m.role == "tool").count(), 1);
+        assert_eq!(
+            packet.short_term_memory.iter().filter(|m| m.source == MemorySource::ToolResult).count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_memory_changes_keeps_turn_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let mut saved = packet();
+        saved.short_term_memory = vec![ShortTermMemory::for_test("m1"), ShortTermMemory::for_test("m2")];
+        state.packet_storage.save(&saved).await.unwrap();
+
+        // 本轮执行 forget_memory 后调用失败：只写回短期记忆，不写入本轮消息
+        let mut packet = saved.clone();
+        packet.append_user_message("忘掉 m1");
+        packet.remove_short_term_memories(&["m1".to_string()]);
+        save_memory_changes(&state, &packet).await;
+
+        let loaded = state.packet_storage.load("ast_test", "topic_test").await.unwrap().unwrap();
+        let ids: Vec<&str> = loaded.short_term_memory.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["m2"]);
+        assert!(loaded.messages.is_empty());
+    }
+}
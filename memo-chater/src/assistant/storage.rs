@@ -2,6 +2,7 @@
 
 use std::path::{Path, PathBuf};
 use tokio::fs;
+use crate::storage::{read_with_backup, write_atomic, ReadError};
 use crate::types::ChatMessage;
 use super::types::*;
 
@@ -24,6 +25,12 @@ pub enum StorageError {
     TopicNotFound(String),
 }
 
+impl ReadError for StorageError {
+    fn blocks_backup(&self) -> bool {
+        matches!(self, StorageError::Io(e) if e.blocks_backup())
+    }
+}
+
 /// 助手存储操作
 pub struct AssistantStorage {
     /// 助手根目录 (data_dir/assistants)
@@ -98,7 +105,7 @@ impl AssistantStorage {
         let path = self.assistant_config_path(id);
         let content = toml::to_string_pretty(config)
             .map_err(|e| StorageError::Serialize(e.to_string()))?;
-        fs::write(path, content).await?;
+        write_atomic(path, content).await?;
         Ok(())
     }
     
@@ -109,10 +116,10 @@ impl AssistantStorage {
             return Err(StorageError::AssistantNotFound(id.to_string()));
         }
         
-        let content = fs::read_to_string(path).await?;
-        let config: AssistantConfig = toml::from_str(&content)
-            .map_err(|e| StorageError::Deserialize(e.to_string()))?;
-        Ok(config)
+        read_with_backup(path, |content| {
+            toml::from_str::<AssistantConfig>(content)
+                .map_err(|e| StorageError::Deserialize(e.to_string()))
+        }).await
     }
     
     /// 删除助手（包括所有话题）
@@ -167,7 +174,7 @@ impl AssistantStorage {
         
         // 创建空的历史文件
         let history_path = self.topic_history_path(assistant_id, topic_id);
-        fs::write(history_path, "[]").await?;
+        write_atomic(history_path, "[]").await?;
         
         Ok(meta)
     }
@@ -177,7 +184,7 @@ impl AssistantStorage {
         let path = self.topic_meta_path(assistant_id, topic_id);
         let content = toml::to_string_pretty(meta)
             .map_err(|e| StorageError::Serialize(e.to_string()))?;
-        fs::write(path, content).await?;
+        write_atomic(path, content).await?;
         Ok(())
     }
     
@@ -188,10 +195,10 @@ impl AssistantStorage {
             return Err(StorageError::TopicNotFound(topic_id.to_string()));
         }
         
-        let content = fs::read_to_string(path).await?;
-        let meta: TopicMeta = toml::from_str(&content)
-            .map_err(|e| StorageError::Deserialize(e.to_string()))?;
-        Ok(meta)
+        read_with_backup(path, |content| {
+            toml::from_str::<TopicMeta>(content)
+                .map_err(|e| StorageError::Deserialize(e.to_string()))
+        }).await
     }
     
     /// 删除话题
@@ -233,10 +240,10 @@ impl AssistantStorage {
             return Ok(Vec::new());
         }
         
-        let content = fs::read_to_string(path).await?;
-        let messages: Vec<ChatMessage> = serde_json::from_str(&content)
-            .map_err(|e| StorageError::Deserialize(e.to_string()))?;
-        Ok(messages)
+        read_with_backup(path, |content| {
+            serde_json::from_str::<Vec<ChatMessage>>(content)
+                .map_err(|e| StorageError::Deserialize(e.to_string()))
+        }).await
     }
     
     /// 保存对话历史
@@ -244,7 +251,7 @@ impl AssistantStorage {
         let path = self.topic_history_path(assistant_id, topic_id);
         let content = serde_json::to_string_pretty(messages)
             .map_err(|e| StorageError::Serialize(e.to_string()))?;
-        fs::write(path, content).await?;
+        write_atomic(path, content).await?;
         
         // 更新话题元信息的消息数量
         if let Ok(mut meta) = self.load_topic_meta(assistant_id, topic_id).await {
@@ -35,6 +35,26 @@ pub struct AssistantConfig {
     #[serde(default)]
     pub memory: MemoryConfig,
     
+    /// 历史对话压缩配置
+    #[serde(default)]
+    pub history: HistoryConfig,
+    
+    /// 上下文窗口预算配置
+    #[serde(default)]
+    pub context_budget: ContextBudgetConfig,
+    
+    /// 短期记忆衰减与淘汰策略
+    #[serde(default)]
+    pub short_term: ShortTermPolicyConfig,
+    
+    /// 内置记忆工具（主模型通过函数调用管理记忆）
+    #[serde(default)]
+    pub memory_tools: MemoryToolsConfig,
+    
+    /// 同一话题并发请求的处理方式
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    
     /// 流水线配置
     #[serde(default)]
     pub pipeline: PipelineConfig,
@@ -56,6 +76,11 @@ impl Default for AssistantConfig {
             model: ModelConfig::default(),
             roles: AssistantRolesConfig::default(),
             memory: MemoryConfig::default(),
+            history: HistoryConfig::default(),
+            context_budget: ContextBudgetConfig::default(),
+            short_term: ShortTermPolicyConfig::default(),
+            memory_tools: MemoryToolsConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
             pipeline: PipelineConfig::default(),
             created_at: now,
             updated_at: now,
@@ -89,6 +114,10 @@ pub struct ModelConfig {
     /// 最大输出token
     #[serde(default = "default_max_tokens")]
     pub max_tokens: u32,
+    
+    /// 主模型的上下文窗口（token），0 表示按模型名使用内置值
+    #[serde(default)]
+    pub context_window: u32,
 }
 
 fn default_main_model() -> String { "gpt-4o-mini".to_string() }
@@ -107,6 +136,7 @@ impl Default for ModelConfig {
             extractor_model: default_extractor_model(),
             temperature: default_temperature(),
             max_tokens: default_max_tokens(),
+            context_window: 0,
         }
     }
 }
@@ -165,6 +195,203 @@ impl Default for MemoryConfig {
     }
 }
 
+/// 历史对话压缩配置（HistorySimplifier 使用）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// 上下文 token 预算（超出后压缩较早的轮次）
+    #[serde(default = "default_history_token_budget")]
+    pub token_budget: usize,
+    
+    /// 原样保留的最近轮次数
+    #[serde(default = "default_keep_recent_turns")]
+    pub keep_recent_turns: usize,
+}
+
+fn default_history_token_budget() -> usize { 8000 }
+fn default_keep_recent_turns() -> usize { 4 }
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            token_budget: default_history_token_budget(),
+            keep_recent_turns: default_keep_recent_turns(),
+        }
+    }
+}
+
+/// 上下文窗口预算配置
+///
+/// 可用窗口（上下文窗口减去最大输出）按以下份额分配，份额按总和归一化
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextBudgetConfig {
+    /// 系统提示词
+    #[serde(default = "default_system_prompt_share")]
+    pub system_prompt: f32,
+    
+    /// 滚动摘要
+    #[serde(default = "default_summary_share")]
+    pub summary: f32,
+    
+    /// 注入的短期记忆
+    #[serde(default = "default_memories_share")]
+    pub memories: f32,
+    
+    /// 展开的短期记忆
+    #[serde(default = "default_expanded_memories_share")]
+    pub expanded_memories: f32,
+    
+    /// 最近轮次
+    #[serde(default = "default_recent_turns_share")]
+    pub recent_turns: f32,
+}
+
+fn default_system_prompt_share() -> f32 { 0.1 }
+fn default_summary_share() -> f32 { 0.1 }
+fn default_memories_share() -> f32 { 0.25 }
+fn default_expanded_memories_share() -> f32 { 0.2 }
+fn default_recent_turns_share() -> f32 { 0.35 }
+
+impl Default for ContextBudgetConfig {
+    fn default() -> Self {
+        Self {
+            system_prompt: default_system_prompt_share(),
+            summary: default_summary_share(),
+            memories: default_memories_share(),
+            expanded_memories: default_expanded_memories_share(),
+            recent_turns: default_recent_turns_share(),
+        }
+    }
+}
+
+/// 短期记忆被淘汰时的去向
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionAction {
+    /// 直接删除（同时从话题向量文件中移除）
+    Delete,
+    /// 在话题向量文件中保留为归档（仍可通过对话记忆库搜索）
+    #[default]
+    Archive,
+    /// 提交到助手的长期记忆库
+    Promote,
+}
+
+/// 短期记忆衰减与淘汰策略（ShortTermEvictor 使用）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShortTermPolicyConfig {
+    /// 短期记忆最大条数（超出时淘汰相关性最低的，0 表示不限）
+    #[serde(default = "default_short_term_max_count")]
+    pub max_count: usize,
+    
+    /// 相关性衰减的半衰期（小时，0 表示不衰减）
+    #[serde(default = "default_decay_half_life_hours")]
+    pub decay_half_life_hours: f32,
+    
+    /// 单次衰减最多计入的时间（小时，0 表示不限）
+    ///
+    /// 衰减在每轮对话时按距上次衰减的时间计算。不设上限时，长时间不对话后的第一轮
+    /// 会一次性衰减整段空闲时间（默认半衰期 72 小时、阈值 0.05 时约 13 天），所有记忆同时被淘汰
+    #[serde(default = "default_max_decay_hours")]
+    pub max_decay_hours: f32,
+    
+    /// 相关性低于该值的记忆被淘汰
+    #[serde(default = "default_min_relevance")]
+    pub min_relevance: f32,
+    
+    /// 记忆被展开或再次检索时相关性的加强量
+    #[serde(default = "default_reinforce_boost")]
+    pub reinforce_boost: f32,
+    
+    /// 被淘汰记忆的去向
+    #[serde(default)]
+    pub eviction: EvictionAction,
+}
+
+fn default_short_term_max_count() -> usize { 50 }
+fn default_decay_half_life_hours() -> f32 { 72.0 }
+fn default_max_decay_hours() -> f32 { 24.0 }
+fn default_min_relevance() -> f32 { 0.05 }
+fn default_reinforce_boost() -> f32 { 0.3 }
+
+impl Default for ShortTermPolicyConfig {
+    fn default() -> Self {
+        Self {
+            max_count: default_short_term_max_count(),
+            decay_half_life_hours: default_decay_half_life_hours(),
+            max_decay_hours: default_max_decay_hours(),
+            min_relevance: default_min_relevance(),
+            reinforce_boost: default_reinforce_boost(),
+            eviction: EvictionAction::default(),
+        }
+    }
+}
+
+/// 内置记忆工具配置
+///
+/// 开启后主模型可以调用 recall_memory / expand_memory / save_memory / forget_memory，
+/// 工具在服务端循环执行，只在记忆话题中生效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryToolsConfig {
+    /// 是否向主模型提供记忆工具
+    #[serde(default)]
+    pub enabled: bool,
+    
+    /// 单轮对话中最多执行几轮工具调用（达到后不再提供记忆工具，要求模型直接回答）
+    #[serde(default = "default_memory_tools_max_rounds")]
+    pub max_rounds: usize,
+    
+    /// recall_memory 默认返回条数
+    #[serde(default = "default_memory_tools_recall_limit")]
+    pub recall_limit: usize,
+}
+
+fn default_memory_tools_max_rounds() -> usize { 4 }
+fn default_memory_tools_recall_limit() -> usize { 5 }
+
+impl Default for MemoryToolsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_rounds: default_memory_tools_max_rounds(),
+            recall_limit: default_memory_tools_recall_limit(),
+        }
+    }
+}
+
+/// 同一话题已有请求在处理时的策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyMode {
+    /// 排队等待前一个请求完成
+    #[default]
+    Queue,
+    /// 立即返回忙碌
+    Reject,
+}
+
+/// 话题并发配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// 处理策略
+    #[serde(default)]
+    pub mode: ConcurrencyMode,
+    
+    /// 排队等待的最长时间（秒），超时后返回忙碌
+    #[serde(default = "default_queue_timeout_secs")]
+    pub queue_timeout_secs: u64,
+}
+
+fn default_queue_timeout_secs() -> u64 { 120 }
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            mode: ConcurrencyMode::default(),
+            queue_timeout_secs: default_queue_timeout_secs(),
+        }
+    }
+}
+
 /// 话题类型
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 #[serde(rename_all = "snake_case")]
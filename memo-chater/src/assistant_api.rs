@@ -15,15 +15,39 @@ use std::sync::Arc;
 use crate::assistant::{
     AssistantConfig, AssistantId, AssistantManager, AssistantSummary,
     TopicId, TopicMeta, TopicSummary, TopicType, ModelConfig, AssistantRolesConfig, MemoryConfig,
+    HistoryConfig, ContextBudgetConfig, ShortTermPolicyConfig, MemoryToolsConfig, ConcurrencyConfig,
+};
+use crate::pipeline::{
+    PipelineConfig, PipelineDispatcher, TopicGuard, TopicLockError, TopicLocks, TraceStorage, TurnTrace,
 };
-use crate::pipeline::PipelineConfig;
 use crate::pipeline::processors::short_term_vectorizer::{ShortTermVectorFile, VectorizedMemory};
 use crate::ai::AiClient;
+use crate::storage::{read_with_backup, write_atomic, PACKET_SCHEMA, VECTOR_FILE_SCHEMA};
 
 /// API 状态
 pub struct AssistantApiState {
     pub manager: Arc<AssistantManager>,
     pub ai_client: Arc<AiClient>,
+    /// 流水线调度器（用于校验流水线配置中的处理器参数）
+    pub dispatcher: Arc<PipelineDispatcher>,
+    /// 执行追踪存储
+    pub trace_storage: Arc<TraceStorage>,
+    /// 话题锁（与对话请求共享，编辑数据包和向量文件时互斥）
+    pub topic_locks: Arc<TopicLocks>,
+}
+
+/// 按助手的并发配置获取话题锁，避免编辑与进行中的对话请求、后台任务写回互相覆盖
+async fn acquire_topic(
+    state: &AssistantApiState,
+    assistant_id: &str,
+    topic_id: &str,
+) -> Result<TopicGuard, TopicLockError> {
+    let concurrency = state.manager
+        .get_assistant(assistant_id)
+        .await
+        .map(|config| config.concurrency)
+        .unwrap_or_default();
+    state.topic_locks.acquire(assistant_id, topic_id, &concurrency).await
 }
 
 /// 创建助手路由
@@ -40,6 +64,8 @@ pub fn assistant_routes(state: Arc<AssistantApiState>) -> Router {
         // 消息操作
         .route("/assistants/:assistant_id/topics/:topic_id/messages/:index", put(update_message).delete(delete_message))
         .route("/assistants/:assistant_id/topics/:topic_id/branch", post(create_branch_topic))
+        // 流水线执行追踪
+        .route("/assistants/:assistant_id/topics/:topic_id/traces", get(get_traces))
         // 对话记忆库
         .route("/assistants/:assistant_id/topics/:topic_id/conversation-memory", get(list_conversation_memory))
         .route("/assistants/:assistant_id/topics/:topic_id/conversation-memory/search", post(search_conversation_memory))
@@ -72,6 +98,16 @@ pub struct UpdateAssistantRequest {
     #[serde(default)]
     pub memory: Option<MemoryConfig>,
     #[serde(default)]
+    pub history: Option<HistoryConfig>,
+    #[serde(default)]
+    pub context_budget: Option<ContextBudgetConfig>,
+    #[serde(default)]
+    pub short_term: Option<ShortTermPolicyConfig>,
+    #[serde(default)]
+    pub memory_tools: Option<MemoryToolsConfig>,
+    #[serde(default)]
+    pub concurrency: Option<ConcurrencyConfig>,
+    #[serde(default)]
     pub pipeline: Option<PipelineConfig>,
 }
 
@@ -189,7 +225,25 @@ async fn update_assistant(
     if let Some(memory) = req.memory {
         config.memory = memory;
     }
+    if let Some(history) = req.history {
+        config.history = history;
+    }
+    if let Some(context_budget) = req.context_budget {
+        config.context_budget = context_budget;
+    }
+    if let Some(short_term) = req.short_term {
+        config.short_term = short_term;
+    }
+    if let Some(memory_tools) = req.memory_tools {
+        config.memory_tools = memory_tools;
+    }
+    if let Some(concurrency) = req.concurrency {
+        config.concurrency = concurrency;
+    }
     if let Some(pipeline) = req.pipeline {
+        if let Err(e) = state.dispatcher.validate_config(&pipeline) {
+            return Ok(Json(ApiResponse::err(format!("流水线配置无效: {}", e))));
+        }
         config.pipeline = pipeline;
     }
     
@@ -311,6 +365,10 @@ async fn clear_history(
     State(state): State<Arc<AssistantApiState>>,
     Path((assistant_id, topic_id)): Path<(String, String)>,
 ) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     match state.manager.clear_history(&assistant_id, &topic_id).await {
         Ok(()) => Ok(Json(ApiResponse::ok(()))),
         Err(e) => {
@@ -341,6 +399,10 @@ async fn update_message(
     Path((assistant_id, topic_id, index)): Path<(String, String, usize)>,
     Json(req): Json<UpdateMessageRequest>,
 ) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     match state.manager.update_message(&assistant_id, &topic_id, index, &req.content).await {
         Ok(()) => Ok(Json(ApiResponse::ok(()))),
         Err(e) => {
@@ -355,6 +417,10 @@ async fn delete_message(
     State(state): State<Arc<AssistantApiState>>,
     Path((assistant_id, topic_id, index)): Path<(String, String, usize)>,
 ) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     match state.manager.delete_message(&assistant_id, &topic_id, index).await {
         Ok(()) => Ok(Json(ApiResponse::ok(()))),
         Err(e) => {
@@ -379,6 +445,22 @@ async fn create_branch_topic(
     }
 }
 
+// ==================== 执行追踪 API ====================
+
+/// 获取话题最近若干轮的流水线执行追踪
+async fn get_traces(
+    State(state): State<Arc<AssistantApiState>>,
+    Path((assistant_id, topic_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Vec<TurnTrace>>>, StatusCode> {
+    match state.trace_storage.load(&assistant_id, &topic_id).await {
+        Ok(traces) => Ok(Json(ApiResponse::ok(traces))),
+        Err(e) => {
+            tracing::error!("读取执行追踪失败: {}", e);
+            Ok(Json(ApiResponse::err(e.to_string())))
+        }
+    }
+}
+
 // ==================== 对话记忆库 API ====================
 
 /// 对话记忆库列表响应
@@ -428,6 +510,11 @@ fn get_vector_file_path(data_dir: &str, assistant_id: &str, topic_id: &str) -> s
         .join("short_term_vectors.json")
 }
 
+/// 读取向量文件（损坏时回退到备份）
+async fn read_vector_file(path: &std::path::Path) -> std::io::Result<ShortTermVectorFile> {
+    read_with_backup(path, |content| VECTOR_FILE_SCHEMA.parse_io(content)).await
+}
+
 /// 列出对话记忆库
 async fn list_conversation_memory(
     State(state): State<Arc<AssistantApiState>>,
@@ -444,22 +531,14 @@ async fn list_conversation_memory(
         })));
     }
     
-    match tokio::fs::read_to_string(&path).await {
-        Ok(content) => {
-            match serde_json::from_str::<ShortTermVectorFile>(&content) {
-                Ok(file) => {
-                    let total = file.vectors.len();
-                    Ok(Json(ApiResponse::ok(ConversationMemoryListResponse {
-                        memories: file.vectors,
-                        total,
-                        embedding_model: file.metadata.embedding_model,
-                    })))
-                }
-                Err(e) => {
-                    tracing::error!("解析向量文件失败: {}", e);
-                    Ok(Json(ApiResponse::err(format!("解析文件失败: {}", e))))
-                }
-            }
+    match read_vector_file(&path).await {
+        Ok(file) => {
+            let total = file.vectors.len();
+            Ok(Json(ApiResponse::ok(ConversationMemoryListResponse {
+                memories: file.vectors,
+                total,
+                embedding_model: file.metadata.embedding_model,
+            })))
         }
         Err(e) => {
             tracing::error!("读取向量文件失败: {}", e);
@@ -482,14 +561,9 @@ async fn search_conversation_memory(
     }
     
     // 读取向量文件
-    let content = match tokio::fs::read_to_string(&path).await {
-        Ok(c) => c,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
-    };
-    
-    let file: ShortTermVectorFile = match serde_json::from_str(&content) {
+    let file = match read_vector_file(&path).await {
         Ok(f) => f,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("解析文件失败: {}", e)))),
+        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
     };
     
     if file.vectors.is_empty() {
@@ -503,17 +577,11 @@ async fn search_conversation_memory(
         Err(e) => return Ok(Json(ApiResponse::err(format!("生成embedding失败: {}", e)))),
     };
     
-    // 计算加权余弦相似度并排序
-    // 权重: summary 0.4, content 0.6
-    const SUMMARY_WEIGHT: f32 = 0.4;
-    const CONTENT_WEIGHT: f32 = 0.6;
-    
+    // 计算加权余弦相似度并排序（权重见 VectorizedMemory::score）
     let mut results: Vec<SearchResult> = file.vectors
         .into_iter()
         .map(|mem| {
-            let summary_score = cosine_similarity(&query_embedding, &mem.summary_embedding);
-            let content_score = cosine_similarity(&query_embedding, &mem.content_embedding);
-            let score = SUMMARY_WEIGHT * summary_score + CONTENT_WEIGHT * content_score;
+            let score = mem.score(&query_embedding);
             SearchResult { memory: mem, score }
         })
         .collect();
@@ -530,6 +598,10 @@ async fn update_conversation_memory(
     Path((assistant_id, topic_id, memory_id)): Path<(String, String, String)>,
     Json(req): Json<UpdateConversationMemoryRequest>,
 ) -> Result<Json<ApiResponse<VectorizedMemory>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     let data_dir = state.manager.data_dir();
     let path = get_vector_file_path(&data_dir, &assistant_id, &topic_id);
     
@@ -538,14 +610,9 @@ async fn update_conversation_memory(
     }
     
     // 读取文件
-    let content = match tokio::fs::read_to_string(&path).await {
-        Ok(c) => c,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
-    };
-    
-    let mut file: ShortTermVectorFile = match serde_json::from_str(&content) {
+    let mut file = match read_vector_file(&path).await {
         Ok(f) => f,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("解析文件失败: {}", e)))),
+        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
     };
     
     // 查找并更新记忆
@@ -598,7 +665,7 @@ async fn update_conversation_memory(
         Err(e) => return Ok(Json(ApiResponse::err(format!("序列化失败: {}", e)))),
     };
     
-    if let Err(e) = tokio::fs::write(&path, json).await {
+    if let Err(e) = write_atomic(&path, json).await {
         return Ok(Json(ApiResponse::err(format!("写入文件失败: {}", e))));
     }
     
@@ -610,6 +677,10 @@ async fn delete_conversation_memory(
     State(state): State<Arc<AssistantApiState>>,
     Path((assistant_id, topic_id, memory_id)): Path<(String, String, String)>,
 ) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     let data_dir = state.manager.data_dir();
     let path = get_vector_file_path(&data_dir, &assistant_id, &topic_id);
     
@@ -618,14 +689,9 @@ async fn delete_conversation_memory(
     }
     
     // 读取文件
-    let content = match tokio::fs::read_to_string(&path).await {
-        Ok(c) => c,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
-    };
-    
-    let mut file: ShortTermVectorFile = match serde_json::from_str(&content) {
+    let mut file = match read_vector_file(&path).await {
         Ok(f) => f,
-        Err(e) => return Ok(Json(ApiResponse::err(format!("解析文件失败: {}", e)))),
+        Err(e) => return Ok(Json(ApiResponse::err(format!("读取文件失败: {}", e)))),
     };
     
     // 删除记忆
@@ -645,30 +711,13 @@ async fn delete_conversation_memory(
         Err(e) => return Ok(Json(ApiResponse::err(format!("序列化失败: {}", e)))),
     };
     
-    if let Err(e) = tokio::fs::write(&path, json).await {
+    if let Err(e) = write_atomic(&path, json).await {
         return Ok(Json(ApiResponse::err(format!("写入文件失败: {}", e))));
     }
     
     Ok(Json(ApiResponse::ok(())))
 }
 
-/// 计算余弦相似度
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    if a.len() != b.len() || a.is_empty() {
-        return 0.0;
-    }
-    
-    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
-    
-    if norm_a == 0.0 || norm_b == 0.0 {
-        return 0.0;
-    }
-    
-    dot / (norm_a * norm_b)
-}
-
 // ==================== 重建对话向量库 ====================
 
 /// 重建响应
@@ -685,6 +734,10 @@ async fn rebuild_conversation_memory(
     State(state): State<Arc<AssistantApiState>>,
     Path((assistant_id, topic_id)): Path<(String, String)>,
 ) -> Result<Json<ApiResponse<RebuildConversationMemoryResponse>>, StatusCode> {
+    let _guard = match acquire_topic(&state, &assistant_id, &topic_id).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(Json(ApiResponse::err(e.to_string()))),
+    };
     // 获取 data_dir
     let data_dir = state.manager.data_dir();
     
@@ -696,17 +749,12 @@ async fn rebuild_conversation_memory(
         .join(&topic_id)
         .join("conversation_state.json");
     
-    let packet_content = match tokio::fs::read_to_string(&packet_path).await {
-        Ok(c) => c,
-        Err(e) => {
-            return Ok(Json(ApiResponse::err(format!("读取 packet 失败: {}", e))));
-        }
-    };
-    
-    let packet: crate::pipeline::ConversationPacket = match serde_json::from_str(&packet_content) {
+    let packet: crate::pipeline::ConversationPacket = match read_with_backup(&packet_path, |content| {
+        PACKET_SCHEMA.parse_io(content)
+    }).await {
         Ok(p) => p,
         Err(e) => {
-            return Ok(Json(ApiResponse::err(format!("解析 packet 失败: {}", e))));
+            return Ok(Json(ApiResponse::err(format!("读取 packet 失败: {}", e))));
         }
     };
     
@@ -762,16 +810,40 @@ async fn rebuild_conversation_memory(
             source: source_str.to_string(),
             timestamp: memory.timestamp.to_rfc3339(),
             should_expand: memory.should_expand,
-            confidence: 1.0, // 新记忆默认置信度 1.0
+            confidence: memory.confidence,
             summary_embedding,
             content_embedding,
+            archived: false,
         });
         rebuilt_count += 1;
     }
     
+    // 已淘汰归档的记忆不在数据包中，从旧向量文件中取出一并重建
+    let vector_path = get_vector_file_path(&data_dir, &assistant_id, &topic_id);
+    let archived: Vec<VectorizedMemory> = match read_vector_file(&vector_path).await {
+        Ok(old) => old.vectors.into_iter().filter(|v| v.archived).collect(),
+        Err(_) => Vec::new(),
+    };
+    for mut memory in archived {
+        match (
+            state.ai_client.embedding_with_model(&memory.summary, Some(&embedding_model)).await,
+            state.ai_client.embedding_with_model(&memory.content, Some(&embedding_model)).await,
+        ) {
+            (Ok(summary_embedding), Ok(content_embedding)) => {
+                memory.summary_embedding = summary_embedding;
+                memory.content_embedding = content_embedding;
+                vectorized_memories.push(memory);
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!("重建归档记忆 embedding 失败 (id={}): {}", memory.id, e);
+            }
+        }
+    }
+    
     // 4. 构建新的向量文件
     let dimension = vectorized_memories.first().map(|v| v.summary_embedding.len()).unwrap_or(0);
     let vector_file = ShortTermVectorFile {
+        schema_version: VECTOR_FILE_SCHEMA.current,
         vectors: vectorized_memories,
         metadata: crate::pipeline::processors::short_term_vectorizer::VectorFileMetadata {
             embedding_model: embedding_model.clone(),
@@ -781,8 +853,6 @@ async fn rebuild_conversation_memory(
     };
     
     // 5. 写入向量文件
-    let vector_path = get_vector_file_path(&data_dir, &assistant_id, &topic_id);
-    
     let json = match serde_json::to_string_pretty(&vector_file) {
         Ok(j) => j,
         Err(e) => {
@@ -790,7 +860,7 @@ async fn rebuild_conversation_memory(
         }
     };
     
-    if let Err(e) = tokio::fs::write(&vector_path, json).await {
+    if let Err(e) = write_atomic(&vector_path, json).await {
         return Ok(Json(ApiResponse::err(format!("写入向量文件失败: {}", e))));
     }
     
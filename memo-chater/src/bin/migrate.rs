@@ -0,0 +1,137 @@
+//! 数据目录迁移工具
+//!
+//! 部署新版本前，将 data_dir 下所有持久化文件升级到当前格式版本
+//! （加载时也会自动迁移，但只在下次保存时写回）。
+//! 升级前的版本保留为 `.bak`。
+//!
+//! 运行方式:
+//!   cargo run --bin migrate                  (使用 config.toml 中的 data_dir)
+//!   cargo run --bin migrate -- ./data        (指定数据目录)
+//!   cargo run --bin migrate -- --dry-run     (只检查，不写入)
+
+use memo_chater::config::AppConfig;
+use memo_chater::storage::{
+    write_atomic, SchemaFormat, FILE_INDEX_SCHEMA, GRAPH_SCHEMA, PACKET_SCHEMA, VECTOR_FILE_SCHEMA,
+};
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// 关系图所在的目录名（见 GraphScope::storage_dir）
+const GRAPH_DIRS: &[&str] = &["graphs", "long_term_graphs", "global_graphs"];
+
+/// 文件对应的格式
+enum Target {
+    /// 整个文件是一种格式
+    Whole(&'static SchemaFormat),
+    /// 轮次快照：其中的 packet 字段为数据包
+    Snapshot,
+}
+
+/// 根据文件位置判断格式
+fn classify(path: &Path) -> Option<Target> {
+    let name = path.file_name()?.to_str()?;
+    if !name.ends_with(".json") {
+        return None;
+    }
+    let parent = path.parent()?.file_name()?.to_str()?;
+
+    match name {
+        "conversation_state.json" => Some(Target::Whole(&PACKET_SCHEMA)),
+        "short_term_vectors.json" => Some(Target::Whole(&VECTOR_FILE_SCHEMA)),
+        "index.json" => Some(Target::Whole(&FILE_INDEX_SCHEMA)),
+        _ if parent == "snapshots" && name.starts_with("turn_") => Some(Target::Snapshot),
+        _ if GRAPH_DIRS.contains(&parent) => Some(Target::Whole(&GRAPH_SCHEMA)),
+        _ => None,
+    }
+}
+
+/// 递归收集目录下的文件
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 迁移单个文件，返回是否需要（或已经）写回
+async fn migrate_file(path: &Path, target: &Target, dry_run: bool) -> Result<bool, String> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("读取失败: {}", e))?;
+    let mut value: Value = serde_json::from_str(&content).map_err(|e| format!("解析失败: {}", e))?;
+
+    let changed = match target {
+        Target::Whole(schema) => schema.migrate(&mut value).map_err(|e| e.to_string())?,
+        Target::Snapshot => match value.get_mut("packet") {
+            Some(packet) => PACKET_SCHEMA.migrate(packet).map_err(|e| e.to_string())?,
+            None => return Err("快照缺少 packet 字段".to_string()),
+        },
+    };
+
+    if changed && !dry_run {
+        let content = serde_json::to_string_pretty(&value).map_err(|e| format!("序列化失败: {}", e))?;
+        write_atomic(path, content)
+            .await
+            .map_err(|e| format!("写入失败: {}", e))?;
+    }
+
+    Ok(changed)
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let data_dir = match args.iter().find(|a| !a.starts_with("--")) {
+        Some(dir) => PathBuf::from(dir),
+        None => AppConfig::load_default().expect("加载配置失败").data_dir,
+    };
+
+    println!("=== 数据迁移 ===");
+    println!("数据目录: {}", data_dir.display());
+    if dry_run {
+        println!("(dry-run: 只检查，不写入)");
+    }
+    println!();
+
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(&data_dir, &mut files) {
+        eprintln!("遍历数据目录失败: {}", e);
+        std::process::exit(1);
+    }
+    files.sort();
+
+    let (mut migrated, mut current, mut failed) = (0, 0, 0);
+    for path in &files {
+        let Some(target) = classify(path) else {
+            continue;
+        };
+        match migrate_file(path, &target, dry_run).await {
+            Ok(true) => {
+                migrated += 1;
+                println!("  升级  {}", path.display());
+            }
+            Ok(false) => current += 1,
+            Err(e) => {
+                failed += 1;
+                eprintln!("  失败  {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    println!();
+    println!("升级 {} 个，已是最新 {} 个，失败 {} 个", migrated, current, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -94,6 +94,11 @@ fn parse_markdown_conversation(content: &str) -> Vec<ChatMessage> {
                     messages.push(ChatMessage {
                         role,
                         content,
+                        parts: None,
+                        name: None,
+                        tool_calls: None,
+                        tool_call_id: None,
+                        meta: None,
                     });
                 }
                 current_content.clear();
@@ -122,6 +127,11 @@ fn parse_markdown_conversation(content: &str) -> Vec<ChatMessage> {
             messages.push(ChatMessage {
                 role,
                 content,
+                parts: None,
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+                meta: None,
             });
         }
     }
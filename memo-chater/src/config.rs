@@ -33,6 +33,10 @@ pub struct GlobalConfig {
     /// 提示词目录（兼容旧代码）
     #[serde(default = "default_prompts_dir")]
     pub prompts_dir: PathBuf,
+    
+    /// 外部处理器配置
+    #[serde(default)]
+    pub remote_processors: RemoteProcessorsConfig,
 }
 
 fn default_prompts_dir() -> PathBuf { PathBuf::from("./prompts") }
@@ -49,6 +53,39 @@ impl Default for GlobalConfig {
             qdrant: QdrantConfig::default(),
             roles: RolesConfig::default(),
             prompts_dir: default_prompts_dir(),
+            remote_processors: RemoteProcessorsConfig::default(),
+        }
+    }
+}
+
+/// 外部处理器配置
+///
+/// stdio 外部处理器会在服务端启动子进程，只允许运行 `command_dir` 目录下的程序；
+/// HTTP 外部处理器会收到完整的数据包，只允许访问 `allowed_url_prefixes` 中的地址。
+/// 两者未配置时分别禁用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteProcessorsConfig {
+    /// 允许作为 stdio 外部处理器运行的程序目录（条目的 command 只能是该目录下的文件名）
+    #[serde(default)]
+    pub command_dir: Option<PathBuf>,
+
+    /// 允许的 HTTP 外部处理器地址前缀（条目的 url 必须以其中之一开头）
+    #[serde(default)]
+    pub allowed_url_prefixes: Vec<String>,
+    
+    /// 条目未设置 timeout_secs 时的调用超时（秒）
+    #[serde(default = "default_remote_timeout_secs")]
+    pub default_timeout_secs: u64,
+}
+
+fn default_remote_timeout_secs() -> u64 { 30 }
+
+impl Default for RemoteProcessorsConfig {
+    fn default() -> Self {
+        Self {
+            command_dir: None,
+            allowed_url_prefixes: Vec::new(),
+            default_timeout_secs: default_remote_timeout_secs(),
         }
     }
 }
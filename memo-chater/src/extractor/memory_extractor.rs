@@ -88,10 +88,20 @@ impl MemoryExtractor {
             ChatMessage {
                 role: "system".to_string(),
                 content: system_prompt.to_string(),
+                parts: None,
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+                meta: None,
             },
             ChatMessage {
                 role: "user".to_string(),
                 content: user_prompt.to_string(),
+                parts: None,
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+                meta: None,
             },
         ];
 
@@ -2,12 +2,12 @@
 
 use crate::graph::error::{GraphError, GraphResult};
 use crate::graph::types::*;
+use crate::storage::{read_with_backup, write_atomic, GRAPH_SCHEMA};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
 
 // ============================================================================
 // 邻接表索引（运行时缓存）
@@ -86,6 +86,9 @@ impl AdjacencyIndex {
 /// 单维度图 - 完全独立的图实例
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DimensionGraph {
+    /// 持久化格式版本（见 storage::GRAPH_SCHEMA）
+    #[serde(default)]
+    pub schema_version: u32,
     /// 维度名称
     pub dimension: String,
     /// 版本号
@@ -116,6 +119,7 @@ impl DimensionGraph {
     /// 创建空图
     pub fn new(dimension: &str) -> Self {
         Self {
+            schema_version: GRAPH_SCHEMA.current,
             dimension: dimension.to_string(),
             version: "1.0".to_string(),
             metadata: GraphMetadata::default(),
@@ -129,8 +133,9 @@ impl DimensionGraph {
 
     /// 从文件加载
     pub async fn load(path: &Path) -> GraphResult<Self> {
-        let content = fs::read_to_string(path).await?;
-        let mut graph: Self = serde_json::from_str(&content)?;
+        let mut graph: Self = read_with_backup(path, |content| {
+            GRAPH_SCHEMA.parse_io(content).map_err(GraphError::from)
+        }).await?;
         graph.storage_path = Some(path.to_path_buf());
         graph.rebuild_caches();
         Ok(graph)
@@ -152,9 +157,7 @@ impl DimensionGraph {
         }
 
         let content = serde_json::to_string_pretty(self)?;
-        let mut file = fs::File::create(path).await?;
-        file.write_all(content.as_bytes()).await?;
-        file.flush().await?;
+        write_atomic(path, content).await?;
 
         Ok(())
     }
@@ -24,6 +24,12 @@ pub enum GraphError {
     DimensionError(#[from] DimensionError),
 }
 
+impl crate::storage::ReadError for GraphError {
+    fn blocks_backup(&self) -> bool {
+        matches!(self, GraphError::Io(e) if e.blocks_backup())
+    }
+}
+
 /// 维度处理错误
 #[derive(Debug, Error)]
 pub enum DimensionError {
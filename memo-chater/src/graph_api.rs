@@ -15,6 +15,7 @@ use crate::graph::{
     DimensionGraph, GraphLocator, GraphScope, Node, Edge, GraphMetadata,
 };
 use crate::state::AppState;
+use crate::storage::{read_with_backup, VECTOR_FILE_SCHEMA};
 
 // ============================================================================
 // 响应类型
@@ -194,12 +195,11 @@ async fn build_graph_from_memories(
         return Err("对话记忆库文件不存在".to_string());
     }
     
-    let content = tokio::fs::read_to_string(&vectors_path)
-        .await
-        .map_err(|e| format!("读取文件失败: {}", e))?;
-    
-    let vector_file: ShortTermVectorFile = serde_json::from_str(&content)
-        .map_err(|e| format!("解析文件失败: {}", e))?;
+    let vector_file: ShortTermVectorFile = read_with_backup(&vectors_path, |content| {
+        VECTOR_FILE_SCHEMA.parse_io(content)
+    })
+    .await
+    .map_err(|e| format!("读取文件失败: {}", e))?;
     
     if vector_file.vectors.is_empty() {
         return Ok(GraphData {
@@ -24,7 +24,7 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use crate::ai::AiClient;
 use crate::config::AppConfig;
 use crate::memory::MemoryManager;
-use crate::pipeline::{PipelineDispatcher, ProcessorContextFactory, PacketStorage, create_all_processors};
+use crate::pipeline::{JobQueue, PipelineDispatcher, ProcessorContextFactory, PacketStorage, TopicLocks, TraceStorage, create_all_processors};
 use crate::qdrant::QdrantManager;
 use crate::state::AppState;
 use crate::assistant::AssistantManager;
@@ -132,6 +132,8 @@ async fn main() {
     
     // 创建数据包存储
     let packet_storage = Arc::new(PacketStorage::new(data_dir.clone()));
+    let trace_storage = Arc::new(TraceStorage::new(data_dir.clone()));
+    let job_queue = Arc::new(JobQueue::new(&data_dir));
     
     tracing::info!("流水线调度器初始化完成，已注册处理器: {:?}", dispatcher.list_processors());
     
@@ -139,15 +141,24 @@ async fn main() {
     let state = Arc::new(AppState {
         config,
         assistant_manager: assistant_manager.clone(),
-        dispatcher,
+        dispatcher: dispatcher.clone(),
         packet_storage,
+        trace_storage: trace_storage.clone(),
+        job_queue,
+        topic_locks: Arc::new(TopicLocks::new()),
         memory_manager: memory_manager.clone(),
     });
 
+    // 启动后台任务工作循环
+    tokio::spawn(api::run_background_worker(state.clone()));
+
     // 助手API状态
     let assistant_state = Arc::new(AssistantApiState {
         manager: assistant_manager,
         ai_client: ai_client_arc.clone(),
+        dispatcher,
+        trace_storage,
+        topic_locks: state.topic_locks.clone(),
     });
 
     // CORS 配置
@@ -184,6 +195,10 @@ async fn main() {
         .route("/admin/api/processors", axum::routing::get(admin_api::list_processors))
         .route("/admin/api/settings", axum::routing::get(admin_api::get_settings))
         .route("/admin/api/settings", axum::routing::put(admin_api::update_settings))
+        // 后台任务队列API
+        .route("/admin/api/jobs", axum::routing::get(admin_api::list_jobs))
+        .route("/admin/api/jobs/:id", axum::routing::delete(admin_api::delete_job))
+        .route("/admin/api/jobs/:id/retry", post(admin_api::retry_job))
         // 按助手隔离的记忆API
         .route("/assistants/:assistant_id/memories", axum::routing::get(admin_api::list_assistant_memories))
         .route("/assistants/:assistant_id/memories", post(admin_api::create_assistant_memory))
@@ -196,6 +211,10 @@ async fn main() {
         .route("/assistants/:assistant_id/topics/:topic_id/packet", axum::routing::get(api::get_packet_memory))
         .route("/assistants/:assistant_id/topics/:topic_id/packet/thinking", axum::routing::put(api::update_thinking_pool))
         .route("/assistants/:assistant_id/topics/:topic_id/packet/short-term", axum::routing::put(api::update_short_term_memory))
+        // 轮次快照 API
+        .route("/assistants/:assistant_id/topics/:topic_id/snapshots", axum::routing::get(api::list_snapshots))
+        .route("/assistants/:assistant_id/topics/:topic_id/snapshots/diff", axum::routing::get(api::diff_snapshots))
+        .route("/assistants/:assistant_id/topics/:topic_id/snapshots/:turn_index/restore", post(api::restore_snapshot))
         .with_state(state);
     
     // 合并路由
@@ -85,6 +85,18 @@ impl MemoryManager {
         })
     }
 
+    /// 创建不连接 Qdrant 的记忆管理器（测试用，没有全局长期记忆）
+    #[cfg(test)]
+    pub(crate) fn detached(config: MemoryManagerConfig, ai_client: AiClient) -> Self {
+        Self {
+            config,
+            ai_client,
+            assistants: HashMap::new(),
+            global_pending: PendingMemoryStore::new(),
+            global_long_term: None,
+        }
+    }
+
     /// 获取或创建助手的记忆存储
     pub async fn get_or_create_assistant(
         &mut self,
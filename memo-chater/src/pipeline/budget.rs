@@ -0,0 +1,209 @@
+//! 上下文窗口预算
+//!
+//! 按主模型的上下文窗口（扣除最大输出）在系统提示词、滚动摘要、注入记忆、
+//! 展开记忆和最近轮次之间分配 token 份额。处理器注入内容超出自己部分的份额时，
+//! 按价值从低到高丢弃条目，并把预算与丢弃记录写入处理器状态
+
+use serde::Serialize;
+
+use crate::assistant::ContextBudgetConfig;
+
+use super::context::ProcessorContext;
+
+/// 未知模型的上下文窗口
+const DEFAULT_CONTEXT_WINDOW: usize = 32_768;
+
+/// 内置的模型上下文窗口（模型名前缀 → 窗口大小，按顺序匹配）
+const MODEL_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5", 16_385),
+    ("gpt-5", 400_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini", 1_048_576),
+    ("deepseek", 65_536),
+    ("qwen", 131_072),
+];
+
+/// 按模型名查找内置的上下文窗口（忽略供应商前缀和大小写）
+pub fn context_window_for_model(model: &str) -> usize {
+    let model = model.to_lowercase();
+    let name = model.rsplit('/').next().unwrap_or(&model);
+    MODEL_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, window)| *window)
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+/// 预算分区
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetSection {
+    SystemPrompt,
+    Summary,
+    Memories,
+    ExpandedMemories,
+    RecentTurns,
+}
+
+/// 参与预算的条目
+#[derive(Debug, Clone)]
+pub struct BudgetItem {
+    /// 条目标识（写入丢弃记录）
+    pub id: String,
+    /// 注入后占用的 token 数
+    pub tokens: usize,
+    /// 价值，越低越先被丢弃
+    pub value: f64,
+}
+
+/// 被丢弃的条目
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedItem {
+    pub id: String,
+    pub tokens: usize,
+    pub value: f64,
+}
+
+/// 一个分区的预算结果
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetFit {
+    pub section: BudgetSection,
+    /// 分区份额（token）
+    pub limit: usize,
+    /// 保留条目占用的 token
+    pub used: usize,
+    /// 保留的条目下标（保持原顺序）
+    #[serde(skip)]
+    pub kept: Vec<usize>,
+    /// 丢弃的条目（按丢弃顺序）
+    pub dropped: Vec<DroppedItem>,
+}
+
+/// 上下文预算
+#[derive(Debug, Clone)]
+pub struct ContextBudget {
+    /// 可用于输入的 token（上下文窗口减去最大输出）
+    usable: usize,
+    /// 份额配置
+    shares: ContextBudgetConfig,
+}
+
+impl ContextBudget {
+    pub fn new(context_window: usize, reserved_output: usize, shares: ContextBudgetConfig) -> Self {
+        Self {
+            usable: context_window.saturating_sub(reserved_output),
+            shares,
+        }
+    }
+
+    /// 按助手的模型配置和份额配置创建
+    pub fn for_context(ctx: &ProcessorContext) -> Self {
+        let model = &ctx.assistant_config.model;
+        let window = if model.context_window > 0 {
+            model.context_window as usize
+        } else {
+            context_window_for_model(&model.main_model)
+        };
+        Self::new(window, model.max_tokens as usize, ctx.assistant_config.context_budget.clone())
+    }
+
+    /// 分区的 token 份额
+    pub fn limit(&self, section: BudgetSection) -> usize {
+        let s = &self.shares;
+        let share = match section {
+            BudgetSection::SystemPrompt => s.system_prompt,
+            BudgetSection::Summary => s.summary,
+            BudgetSection::Memories => s.memories,
+            BudgetSection::ExpandedMemories => s.expanded_memories,
+            BudgetSection::RecentTurns => s.recent_turns,
+        };
+        let total = s.system_prompt + s.summary + s.memories + s.expanded_memories + s.recent_turns;
+        if total <= 0.0 || share <= 0.0 {
+            return 0;
+        }
+        (self.usable as f64 * (share / total) as f64) as usize
+    }
+
+    /// 将条目放入分区份额：超出时按价值从低到高丢弃（同价值先丢靠后的条目）
+    pub fn fit(&self, section: BudgetSection, items: &[BudgetItem]) -> BudgetFit {
+        let limit = self.limit(section);
+        let mut used: usize = items.iter().map(|i| i.tokens).sum();
+
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by(|&a, &b| {
+            items[a]
+                .value
+                .total_cmp(&items[b].value)
+                .then(b.cmp(&a))
+        });
+
+        let mut keep = vec![true; items.len()];
+        let mut dropped = Vec::new();
+        for idx in order {
+            if used <= limit {
+                break;
+            }
+            let item = &items[idx];
+            keep[idx] = false;
+            used -= item.tokens;
+            dropped.push(DroppedItem {
+                id: item.id.clone(),
+                tokens: item.tokens,
+                value: item.value,
+            });
+        }
+
+        BudgetFit {
+            section,
+            limit,
+            used,
+            kept: (0..items.len()).filter(|&i| keep[i]).collect(),
+            dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_drops_lowest_value_first() {
+        let budget = ContextBudget::new(1100, 100, ContextBudgetConfig {
+            system_prompt: 0.0,
+            summary: 0.0,
+            memories: 1.0,
+            expanded_memories: 0.0,
+            recent_turns: 0.0,
+        });
+        assert_eq!(budget.limit(BudgetSection::Memories), 1000);
+        assert_eq!(budget.limit(BudgetSection::Summary), 0);
+
+        let items: Vec<BudgetItem> = [(400, 3.0), (400, 1.0), (400, 2.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (tokens, value))| BudgetItem { id: i.to_string(), tokens: *tokens, value: *value })
+            .collect();
+
+        let fit = budget.fit(BudgetSection::Memories, &items);
+        assert_eq!(fit.kept, vec![0, 2]);
+        assert_eq!(fit.used, 800);
+        assert_eq!(fit.dropped.len(), 1);
+        assert_eq!(fit.dropped[0].id, "1");
+    }
+
+    #[test]
+    fn test_context_window_for_model() {
+        assert_eq!(context_window_for_model("openai/GPT-4o-mini"), 128_000);
+        assert_eq!(context_window_for_model("gpt-4"), 8_192);
+        assert_eq!(context_window_for_model("unknown-model"), DEFAULT_CONTEXT_WINDOW);
+    }
+}
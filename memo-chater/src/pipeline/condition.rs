@@ -0,0 +1,176 @@
+//! 处理器执行条件
+//!
+//! 定义 ProcessorEntry 上的条件规则，调度器在执行处理器前对数据包和上下文求值，
+//! 所有条件都满足时才执行该处理器
+
+use serde::{Deserialize, Serialize};
+
+use super::packet::ConversationPacket;
+use crate::assistant::TopicType;
+
+/// 处理器状态的读取范围
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateScope {
+    /// 本轮状态（排在前面的处理器本轮写入的）
+    #[default]
+    Current,
+    /// 上一轮状态
+    Previous,
+}
+
+/// 处理器执行条件
+///
+/// 配置示例（助手 config.toml）：
+/// ```toml
+/// [[pipeline.after_ai_response]]
+/// name = "ContentChunker"
+/// conditions = [
+///     { type = "every_n_turns", every = 5 },
+///     { type = "topic_types", types = ["memory"] },
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProcessorCondition {
+    /// 每 N 轮执行一次（turn_index % every == offset）
+    EveryNTurns {
+        every: u64,
+        #[serde(default)]
+        offset: u64,
+    },
+    /// messages 条数达到阈值
+    MinMessages { count: usize },
+    /// 仅在指定话题类型下执行
+    TopicTypes { types: Vec<TopicType> },
+    /// 指定处理器状态中的字段等于给定值（key 支持 a.b 形式的嵌套路径）
+    ProcessorState {
+        processor: String,
+        key: String,
+        equals: serde_json::Value,
+        #[serde(default)]
+        scope: StateScope,
+    },
+}
+
+impl ProcessorCondition {
+    /// 对数据包和话题类型求值
+    pub fn matches(&self, packet: &ConversationPacket, topic_type: &TopicType) -> bool {
+        match self {
+            Self::EveryNTurns { every, offset } => {
+                *every == 0 || packet.turn_index % every == offset % every
+            }
+            Self::MinMessages { count } => packet.messages.len() >= *count,
+            Self::TopicTypes { types } => types.contains(topic_type),
+            Self::ProcessorState { processor, key, equals, scope } => {
+                let state = match scope {
+                    StateScope::Current => packet.current_states.get(processor),
+                    StateScope::Previous => packet.get_previous_state(processor),
+                };
+                state
+                    .and_then(|state| {
+                        key.split('.')
+                            .try_fold(state, |value, part| value.get(part))
+                    })
+                    .map(|value| value == equals)
+                    .unwrap_or(false)
+            }
+        }
+    }
+
+    /// 条件的简短描述（用于日志和执行报告）
+    pub fn describe(&self) -> String {
+        match self {
+            Self::EveryNTurns { every, offset } => format!("every_n_turns({}, offset {})", every, offset),
+            Self::MinMessages { count } => format!("min_messages({})", count),
+            Self::TopicTypes { types } => format!("topic_types({:?})", types),
+            Self::ProcessorState { processor, key, equals, scope } => {
+                format!("processor_state({:?} {}.{} == {})", scope, processor, key, equals)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ChatMessage;
+
+    fn packet() -> ConversationPacket {
+        ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_every_n_turns() {
+        let cond = ProcessorCondition::EveryNTurns { every: 5, offset: 0 };
+        let mut packet = packet();
+        assert!(cond.matches(&packet, &TopicType::Memory));
+        packet.turn_index = 3;
+        assert!(!cond.matches(&packet, &TopicType::Memory));
+        packet.turn_index = 10;
+        assert!(cond.matches(&packet, &TopicType::Memory));
+    }
+
+    #[test]
+    fn test_min_messages_and_topic_type() {
+        let mut packet = packet();
+        packet.messages.push(ChatMessage::user("你好"));
+        let cond = ProcessorCondition::MinMessages { count: 2 };
+        assert!(!cond.matches(&packet, &TopicType::Normal));
+        packet.messages.push(ChatMessage::assistant("你好"));
+        assert!(cond.matches(&packet, &TopicType::Normal));
+
+        let cond = ProcessorCondition::TopicTypes { types: vec![TopicType::Memory] };
+        assert!(cond.matches(&packet, &TopicType::Memory));
+        assert!(!cond.matches(&packet, &TopicType::Normal));
+    }
+
+    #[test]
+    fn test_processor_state() {
+        let mut packet = packet();
+        packet.set_processor_state("ContentChunker", serde_json::json!({"result": {"chunked": true}}));
+        let cond = ProcessorCondition::ProcessorState {
+            processor: "ContentChunker".to_string(),
+            key: "result.chunked".to_string(),
+            equals: serde_json::json!(true),
+            scope: StateScope::Current,
+        };
+        assert!(cond.matches(&packet, &TopicType::Memory));
+
+        packet.end_turn();
+        assert!(!cond.matches(&packet, &TopicType::Memory));
+        let previous = ProcessorCondition::ProcessorState {
+            processor: "ContentChunker".to_string(),
+            key: "result.chunked".to_string(),
+            equals: serde_json::json!(true),
+            scope: StateScope::Previous,
+        };
+        assert!(previous.matches(&packet, &TopicType::Memory));
+    }
+
+    #[test]
+    fn test_deserialize_from_toml() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            conditions: Vec<ProcessorCondition>,
+        }
+        let wrapper: Wrapper = toml::from_str(
+            r#"conditions = [
+                { type = "every_n_turns", every = 5 },
+                { type = "topic_types", types = ["memory"] },
+                { type = "processor_state", processor = "A", key = "ok", equals = true, scope = "previous" },
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(wrapper.conditions.len(), 3);
+        assert_eq!(
+            wrapper.conditions[0],
+            ProcessorCondition::EveryNTurns { every: 5, offset: 0 }
+        );
+    }
+}
@@ -2,9 +2,76 @@
 //!
 //! 定义流水线各时机的处理器列表
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-/// 处理器条目（包含名称和描述）
+use super::condition::ProcessorCondition;
+use super::packet::PacketField;
+use super::processor::ProcessorError;
+
+/// 处理器失败时的处理策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnErrorPolicy {
+    /// 跳过该处理器，保留其已做的修改，继续执行后续处理器
+    #[default]
+    Skip,
+    /// 中止本轮对话
+    Abort,
+    /// 将数据包回滚到该处理器执行前的快照，继续执行后续处理器
+    Rollback,
+}
+
+/// 处理器参数（助手配置中内联的 params 表）
+///
+/// 由各处理器自行校验和解析，未提供的字段使用处理器内置的默认配置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProcessorParams(pub serde_json::Map<String, serde_json::Value>);
+
+impl ProcessorParams {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 将参数覆盖到默认配置上，解析为处理器的配置类型
+    ///
+    /// 配置类型应标注 `#[serde(deny_unknown_fields)]`，以便拒绝拼错的参数名
+    pub fn merge_into<T: Serialize + DeserializeOwned>(&self, defaults: &T) -> Result<T, ProcessorError> {
+        let mut merged = match serde_json::to_value(defaults) {
+            Ok(serde_json::Value::Object(map)) => map,
+            Ok(_) => return Err(ProcessorError::Config("默认配置不是表结构".to_string())),
+            Err(e) => return Err(ProcessorError::Config(format!("序列化默认配置失败: {}", e))),
+        };
+        for (key, value) in &self.0 {
+            merged.insert(key.clone(), value.clone());
+        }
+        serde_json::from_value(serde_json::Value::Object(merged))
+            .map_err(|e| ProcessorError::Config(format!("参数无效: {}", e)))
+    }
+}
+
+/// 外部处理器配置
+///
+/// 条目配置 `remote` 后不再查找注册表，而是把数据包交给外部程序处理：
+/// `url` 通过 HTTP POST 调用，`command` 以子进程方式通过 stdin/stdout 调用，二者选其一
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSpec {
+    /// HTTP 处理器地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// stdio 处理器命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// stdio 处理器命令参数
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// 是否需要记忆功能开启才执行
+    #[serde(default)]
+    pub requires_memory: bool,
+}
+
+/// 处理器条目（包含名称、描述和执行策略）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessorEntry {
     /// 处理器名称（用于匹配注册的处理器）
@@ -12,20 +79,55 @@ pub struct ProcessorEntry {
     /// 处理器描述（用户自定义说明）
     #[serde(default)]
     pub description: String,
+    /// 单次执行超时（秒），不设置则不限时（外部处理器使用全局默认超时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// 失败后的重试次数
+    #[serde(default)]
+    pub retries: u32,
+    /// 重试退避基准时间（毫秒），每次重试翻倍
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// 重试耗尽后的处理策略
+    #[serde(default)]
+    pub on_error: OnErrorPolicy,
+    /// 执行条件（全部满足才执行，为空则总是执行）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<ProcessorCondition>,
+    /// 并行组名（同一时机中相邻且同组的处理器并行执行）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_group: Option<String>,
+    /// 并行执行时允许写回的数据包字段（处理器状态总会合并）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub writes: Vec<PacketField>,
+    /// 处理器参数（同一处理器可以多次出现并使用不同参数）
+    #[serde(default, skip_serializing_if = "ProcessorParams::is_empty")]
+    pub params: ProcessorParams,
+    /// 外部处理器配置（设置后 name 仅用于日志和执行报告）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteSpec>,
 }
 
+fn default_retry_backoff_ms() -> u64 { 500 }
+
 impl ProcessorEntry {
     pub fn new(name: impl Into<String>) -> Self {
-        Self {
-            name: name.into(),
-            description: String::new(),
-        }
+        Self::with_description(name, String::new())
     }
     
     pub fn with_description(name: impl Into<String>, description: impl Into<String>) -> Self {
         Self {
             name: name.into(),
             description: description.into(),
+            timeout_secs: None,
+            retries: 0,
+            retry_backoff_ms: default_retry_backoff_ms(),
+            on_error: OnErrorPolicy::default(),
+            conditions: Vec::new(),
+            parallel_group: None,
+            writes: Vec::new(),
+            params: ProcessorParams::default(),
+            remote: None,
         }
     }
 }
@@ -43,11 +145,11 @@ pub struct PipelineConfig {
     #[serde(default)]
     pub before_ai_call: Vec<ProcessorEntry>,
 
-    /// 开始收到返回后（预留）
+    /// 开始收到返回后
     #[serde(default)]
     pub on_stream_start: Vec<ProcessorEntry>,
 
-    /// 收到 chunk 时（预留）
+    /// 收到 chunk 时（调用处理器的 process_chunk，可改写或屏蔽 delta）
     #[serde(default)]
     pub on_stream_chunk: Vec<ProcessorEntry>,
 
@@ -63,6 +165,7 @@ pub struct PipelineConfig {
 fn default_on_user_message() -> Vec<ProcessorEntry> {
     vec![
         ProcessorEntry::with_description("HistorySimplifier", "简化/压缩历史对话，减少上下文长度"),
+        ProcessorEntry::with_description("LongTermRetriever", "根据用户输入检索长期记忆并注入短期记忆"),
         ProcessorEntry::with_description("MemoryAssembler", "将检索到的记忆装配到上下文中"),
     ]
 }
@@ -73,6 +176,7 @@ fn default_after_ai_response() -> Vec<ProcessorEntry> {
         ProcessorEntry::with_description("ContentChunker", "将内容切分成适合存储的块"),
         ProcessorEntry::with_description("ShortTermVectorizer", "将短期记忆向量化并存储到话题文件"),
         ProcessorEntry::with_description("MemoryCommitter", "将记忆块提交到存储系统"),
+        ProcessorEntry::with_description("ShortTermEvictor", "衰减短期记忆相关性并淘汰超出容量的记忆"),
     ]
 }
 
@@ -90,6 +194,17 @@ impl Default for PipelineConfig {
 }
 
 impl PipelineConfig {
+    /// 遍历所有时机的处理器条目
+    pub fn all_entries(&self) -> impl Iterator<Item = &ProcessorEntry> {
+        self.on_user_message
+            .iter()
+            .chain(&self.before_ai_call)
+            .chain(&self.on_stream_start)
+            .chain(&self.on_stream_chunk)
+            .chain(&self.after_ai_response)
+            .chain(&self.background_process)
+    }
+
     /// 创建空配置（不执行任何处理器）
     pub fn empty() -> Self {
         Self {
@@ -122,13 +237,32 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = PipelineConfig::default();
-        assert_eq!(config.on_user_message.len(), 2);
-        assert_eq!(config.after_ai_response.len(), 3);
+        assert_eq!(config.on_user_message.len(), 3);
         assert!(config.before_ai_call.is_empty());
         // 验证描述不为空
         assert!(!config.on_user_message[0].description.is_empty());
     }
 
+    #[test]
+    fn test_default_after_ai_response() {
+        let config = PipelineConfig::default();
+        let names: Vec<&str> = config
+            .after_ai_response
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "SubconsciousProcessor",
+                "ContentChunker",
+                "ShortTermVectorizer",
+                "MemoryCommitter",
+                "ShortTermEvictor",
+            ]
+        );
+    }
+
     #[test]
     fn test_empty_config() {
         let config = PipelineConfig::empty();
@@ -142,4 +276,45 @@ mod tests {
         assert_eq!(entry.name, "Test");
         assert_eq!(entry.description, "测试描述");
     }
+
+    #[test]
+    fn test_processor_entry_policy_defaults() {
+        let entry: ProcessorEntry = toml::from_str(r#"name = "ContentChunker""#).unwrap();
+        assert_eq!(entry.timeout_secs, None);
+        assert_eq!(entry.retries, 0);
+        assert_eq!(entry.on_error, OnErrorPolicy::Skip);
+
+        let entry: ProcessorEntry = toml::from_str(
+            "name = \"ContentChunker\"\ntimeout_secs = 30\nretries = 2\non_error = \"rollback\"",
+        )
+        .unwrap();
+        assert_eq!(entry.timeout_secs, Some(30));
+        assert_eq!(entry.retries, 2);
+        assert_eq!(entry.on_error, OnErrorPolicy::Rollback);
+    }
+
+    #[test]
+    fn test_params_merge_into_defaults() {
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            model: String,
+            prompt: String,
+        }
+        let defaults = Params { model: String::new(), prompt: "默认".to_string() };
+
+        let entry: ProcessorEntry = toml::from_str(
+            "name = \"ContentChunker\"\n[params]\nprompt = \"自定义\"",
+        )
+        .unwrap();
+        let merged = entry.params.merge_into(&defaults).unwrap();
+        assert_eq!(merged.prompt, "自定义");
+        assert_eq!(merged.model, "");
+
+        let entry: ProcessorEntry = toml::from_str(
+            "name = \"ContentChunker\"\n[params]\npromt = \"拼错\"",
+        )
+        .unwrap();
+        assert!(entry.params.merge_into(&defaults).is_err());
+    }
 }
\ No newline at end of file
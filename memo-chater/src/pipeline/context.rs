@@ -4,10 +4,11 @@
 
 use std::sync::Arc;
 
-use crate::ai::AiClient;
-use crate::assistant::{AssistantConfig, AssistantManager, TopicType};
+use crate::ai::{AiClient, Encoding};
+use crate::assistant::{AssistantConfig, AssistantManager, MemoryToolsConfig, ShortTermPolicyConfig, TopicType};
 use crate::config::GlobalConfig;
 use crate::memory::MemoryManager;
+use crate::types::ChatMessage;
 use tokio::sync::RwLock;
 
 /// 处理器上下文 - 提供处理器执行所需的公共依赖
@@ -115,6 +116,74 @@ impl ProcessorContext {
     pub fn relevance_threshold(&self) -> f32 {
         self.assistant_config.memory.relevance_threshold
     }
+
+    /// 获取短期记忆衰减与淘汰策略
+    pub fn short_term_policy(&self) -> &ShortTermPolicyConfig {
+        &self.assistant_config.short_term
+    }
+
+    /// 是否向主模型提供内置记忆工具（需助手开启且为记忆话题）
+    pub fn memory_tools_enabled(&self) -> bool {
+        self.assistant_config.memory_tools.enabled && self.is_memory_enabled()
+    }
+
+    /// 获取内置记忆工具配置
+    pub fn memory_tools(&self) -> &MemoryToolsConfig {
+        &self.assistant_config.memory_tools
+    }
+
+    /// 获取历史对话 token 预算
+    pub fn history_token_budget(&self) -> usize {
+        self.assistant_config.history.token_budget
+    }
+
+    /// 获取原样保留的最近轮次数
+    pub fn history_keep_recent_turns(&self) -> usize {
+        self.assistant_config.history.keep_recent_turns
+    }
+
+    /// 按主模型的编码计算文本 token 数
+    pub fn count_tokens(&self, text: &str) -> usize {
+        Encoding::for_model(self.main_model()).count(text)
+    }
+
+    /// 按主模型的编码计算消息列表作为请求时的 token 数
+    pub fn count_message_tokens(&self, messages: &[ChatMessage]) -> usize {
+        Encoding::for_model(self.main_model()).count_messages(messages)
+    }
+}
+
+#[cfg(test)]
+impl ProcessorContext {
+    /// 测试用上下文：上游模型和 Qdrant 指向不可用的本地地址，数据目录为 data_dir
+    pub(crate) fn for_test(
+        assistant_config: AssistantConfig,
+        topic_type: TopicType,
+        data_dir: &std::path::Path,
+    ) -> Self {
+        use crate::memory::MemoryManagerConfig;
+
+        let unreachable = "http://127.0.0.1:9".to_string();
+        let ai_client = AiClient::new(unreachable.clone(), String::new(), "test-model".to_string());
+        let memory_config = MemoryManagerConfig {
+            qdrant_url: unreachable,
+            file_storage_dir: data_dir.join("assistants").to_string_lossy().to_string(),
+            ..MemoryManagerConfig::default()
+        };
+        Self::new(
+            assistant_config,
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            topic_type,
+            Arc::new(ai_client.clone()),
+            Arc::new(GlobalConfig {
+                data_dir: data_dir.to_path_buf(),
+                ..GlobalConfig::default()
+            }),
+            Arc::new(AssistantManager::new(data_dir)),
+            Arc::new(RwLock::new(MemoryManager::detached(memory_config, ai_client))),
+        )
+    }
 }
 
 /// 处理器上下文工厂
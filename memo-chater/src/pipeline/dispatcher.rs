@@ -2,16 +2,21 @@
 //!
 //! 负责按配置顺序执行处理器
 
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
 use super::context::{ProcessorContext, ProcessorContextFactory};
 use super::packet::ConversationPacket;
-use super::processor::Processor;
-use super::config::PipelineConfig;
+use super::processor::{Processor, StreamChunkAction};
+use super::config::{OnErrorPolicy, PipelineConfig, ProcessorEntry};
+use super::processors::RemoteProcessor;
 
 /// 流水线时机
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PipelineTiming {
     /// 用户发言追加到 messages 后
     OnUserMessage,
@@ -35,6 +40,104 @@ pub enum DispatcherError {
 
     #[error("处理器执行失败: {0}")]
     ProcessorFailed(String),
+
+    #[error("处理器 {processor} 执行失败，本轮已中止: {error}")]
+    Aborted {
+        processor: String,
+        error: String,
+        /// 中止前该时机的执行报告
+        report: Box<DispatchReport>,
+    },
+}
+
+/// 处理器触发 abort 策略（内部传递，由 dispatch 附上报告后转为 DispatcherError）
+struct AbortSignal {
+    processor: String,
+    error: String,
+}
+
+/// 单个处理器的执行结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunOutcome {
+    /// 执行成功
+    Success,
+    /// 记忆功能未启用而跳过
+    SkippedMemoryDisabled,
+    /// 执行条件不满足而跳过
+    SkippedByCondition { condition: String },
+    /// 未找到处理器
+    NotFound,
+    /// 执行失败（重试耗尽），记录触发的策略
+    Failed { policy: OnErrorPolicy },
+}
+
+/// 单个处理器的执行记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessorRun {
+    /// 处理器名称
+    pub name: String,
+    /// 执行结果
+    pub outcome: RunOutcome,
+    /// 实际执行次数（含重试）
+    pub attempts: u32,
+    /// 最后一次失败的错误信息
+    pub error: Option<String>,
+    /// 开始时间
+    pub started_at: DateTime<Utc>,
+    /// 结束时间
+    pub finished_at: DateTime<Utc>,
+    /// 耗时（毫秒，含重试等待）
+    pub duration_ms: i64,
+    /// 执行前 messages 条数
+    pub messages_before: usize,
+    /// 执行后 messages 条数
+    pub messages_after: usize,
+}
+
+impl ProcessorRun {
+    /// 创建执行记录（未实际执行的处理器开始与结束时间相同）
+    fn new(name: &str, outcome: RunOutcome, messages: usize) -> Self {
+        let now = Utc::now();
+        Self {
+            name: name.to_string(),
+            outcome,
+            attempts: 0,
+            error: None,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+            messages_before: messages,
+            messages_after: messages,
+        }
+    }
+
+    /// 以实际开始时间补全耗时和执行后的 messages 条数
+    fn finish(mut self, started_at: DateTime<Utc>, messages_after: usize) -> Self {
+        self.started_at = started_at;
+        self.finished_at = Utc::now();
+        self.duration_ms = (self.finished_at - self.started_at).num_milliseconds();
+        self.messages_after = messages_after;
+        self
+    }
+}
+
+/// 一次调度的执行报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispatchReport {
+    /// 流水线时机
+    pub timing: PipelineTiming,
+    /// 各处理器执行记录（按执行顺序）
+    pub runs: Vec<ProcessorRun>,
+}
+
+impl DispatchReport {
+    fn new(timing: PipelineTiming) -> Self {
+        Self {
+            timing,
+            runs: Vec::new(),
+        }
+    }
 }
 
 /// 流水线调度器
@@ -78,6 +181,40 @@ impl PipelineDispatcher {
         self.processors.get(name)
     }
 
+    /// 按条目获取处理器：配置了 remote 的条目创建外部处理器，否则查找注册表
+    fn processor_for(&self, entry: &ProcessorEntry) -> Option<Arc<dyn Processor>> {
+        match &entry.remote {
+            Some(spec) => Some(Arc::new(RemoteProcessor::new(
+                &entry.name,
+                spec.clone(),
+                self.context_factory.global_config.remote_processors.clone(),
+            ))),
+            None => self.processors.get(&entry.name).cloned(),
+        }
+    }
+
+    /// 校验流水线配置中各条目的 params
+    ///
+    /// 未注册的处理器名不在此处报错（执行时记录为 not_found）
+    pub fn validate_config(&self, config: &PipelineConfig) -> Result<(), String> {
+        let errors: Vec<String> = config
+            .all_entries()
+            .filter_map(|entry| {
+                let processor = self.processor_for(entry)?;
+                processor
+                    .validate_params(&entry.params)
+                    .err()
+                    .map(|e| format!("{}: {}", entry.name, e))
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
     /// 执行指定时机的所有处理器
     ///
     /// # 参数
@@ -87,14 +224,15 @@ impl PipelineDispatcher {
     /// - `ctx`: 处理器上下文
     ///
     /// # 返回
-    /// 成功返回 Ok(())，即使部分处理器失败也会继续执行
+    /// 返回各处理器的执行报告；处理器失败时按其 `on_error` 策略处理，
+    /// 只有 `abort` 策略会返回 `DispatcherError::Aborted` 中止本轮
     pub async fn dispatch(
         &self,
         timing: PipelineTiming,
         packet: &mut ConversationPacket,
         pipeline_config: &PipelineConfig,
         ctx: &ProcessorContext,
-    ) -> Result<(), DispatcherError> {
+    ) -> Result<DispatchReport, DispatcherError> {
         let processor_names = match timing {
             PipelineTiming::OnUserMessage => &pipeline_config.on_user_message,
             PipelineTiming::BeforeAiCall => &pipeline_config.before_ai_call,
@@ -104,9 +242,11 @@ impl PipelineDispatcher {
             PipelineTiming::BackgroundProcess => &pipeline_config.background_process,
         };
 
+        let mut report = DispatchReport::new(timing);
+
         if processor_names.is_empty() {
             tracing::debug!("时机 {:?} 无处理器配置，跳过", timing);
-            return Ok(());
+            return Ok(report);
         }
 
         let names: Vec<&str> = processor_names.iter().map(|e| e.name.as_str()).collect();
@@ -116,39 +256,693 @@ impl PipelineDispatcher {
             names
         );
 
-        let memory_enabled = ctx.is_memory_enabled();
+        match self.run_entries(processor_names, packet, ctx, &mut report).await {
+            Ok(()) => Ok(report),
+            Err(AbortSignal { processor, error }) => Err(DispatcherError::Aborted {
+                processor,
+                error,
+                report: Box::new(report),
+            }),
+        }
+    }
+
+    /// 按顺序执行条目列表，结果记录到报告中
+    async fn run_entries(
+        &self,
+        processor_names: &[ProcessorEntry],
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        report: &mut DispatchReport,
+    ) -> Result<(), AbortSignal> {
+        let mut index = 0;
+        while index < processor_names.len() {
+            let entry = &processor_names[index];
 
-        for entry in processor_names {
+            // 连续的同组条目并行执行
+            if let Some(group) = &entry.parallel_group {
+                let len = processor_names[index..]
+                    .iter()
+                    .take_while(|e| e.parallel_group.as_ref() == Some(group))
+                    .count();
+                self.run_group(group, &processor_names[index..index + len], packet, ctx, report)
+                    .await?;
+                index += len;
+                continue;
+            }
+
+            index += 1;
             let name = &entry.name;
-            if let Some(processor) = self.processors.get(name) {
-                // 检查是否需要跳过（记忆功能未启用）
-                if processor.requires_memory() && !memory_enabled {
-                    tracing::debug!("跳过处理器 {} (记忆功能未启用)", name);
-                    continue;
+            let Some(processor) = self.resolve(entry, packet, ctx, report) else {
+                continue;
+            };
+
+            tracing::debug!("执行处理器: {} ({})", name, entry.description);
+            let run = self.run_entry(entry, &processor, packet, ctx).await;
+            Self::push_run(report, run)?;
+        }
+
+        Ok(())
+    }
+
+    /// 查找处理器并检查是否应跳过；需要跳过时记录到报告并返回 None
+    fn resolve(
+        &self,
+        entry: &ProcessorEntry,
+        packet: &ConversationPacket,
+        ctx: &ProcessorContext,
+        report: &mut DispatchReport,
+    ) -> Option<Arc<dyn Processor>> {
+        let Some(processor) = self.processor_for(entry) else {
+            tracing::warn!("未找到处理器: {}", entry.name);
+            report.runs.push(ProcessorRun::new(&entry.name, RunOutcome::NotFound, packet.messages.len()));
+            return None;
+        };
+
+        // 检查是否需要跳过（记忆功能未启用 / 执行条件不满足）
+        if let Some(skip) = Self::check_skip(entry, &processor, packet, ctx) {
+            tracing::debug!("跳过处理器 {}: {:?}", entry.name, skip);
+            report.runs.push(ProcessorRun::new(&entry.name, skip, packet.messages.len()));
+            return None;
+        }
+
+        Some(processor)
+    }
+
+    /// 记录执行结果；触发 abort 策略时返回错误
+    fn push_run(report: &mut DispatchReport, run: ProcessorRun) -> Result<(), AbortSignal> {
+        let aborted = matches!(run.outcome, RunOutcome::Failed { policy: OnErrorPolicy::Abort });
+        let name = run.name.clone();
+        let error = run.error.clone().unwrap_or_default();
+        report.runs.push(run);
+
+        if aborted {
+            tracing::error!("处理器 {} 失败，按 abort 策略中止本轮: {}", name, error);
+            return Err(AbortSignal { processor: name, error });
+        }
+        Ok(())
+    }
+
+    /// 并行执行一组处理器
+    ///
+    /// 每个成员在数据包的克隆上执行，全部完成后按配置顺序合并：
+    /// 成员声明的 `writes` 字段整体覆盖，成员写入的处理器状态并入 current_states。
+    /// 多个成员声明同一字段时，排在后面的成员生效。
+    async fn run_group(
+        &self,
+        group: &str,
+        entries: &[ProcessorEntry],
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        report: &mut DispatchReport,
+    ) -> Result<(), AbortSignal> {
+        let mut members = Vec::new();
+        for entry in entries {
+            if let Some(processor) = self.resolve(entry, packet, ctx, report) {
+                members.push((entry, processor));
+            }
+        }
+
+        if members.is_empty() {
+            return Ok(());
+        }
+
+        let names: Vec<&str> = members.iter().map(|(e, _)| e.name.as_str()).collect();
+        tracing::debug!("并行执行处理器组 {}: {:?}", group, names);
+
+        let mut declared = HashSet::new();
+        for (entry, _) in &members {
+            for field in &entry.writes {
+                if !declared.insert(*field) {
+                    tracing::warn!("处理器组 {} 中多个处理器声明写入 {:?}，以配置中靠后的为准", group, field);
                 }
+            }
+        }
+
+        let tasks = members.iter().map(|(entry, processor)| {
+            let mut member_packet = packet.clone();
+            async move {
+                let run = self.run_entry(entry, processor, &mut member_packet, ctx).await;
+                (run, member_packet)
+            }
+        });
+        let results = futures::future::join_all(tasks).await;
+
+        // 按配置顺序合并（join_all 保持输入顺序）
+        let base_states = packet.current_states.clone();
+        let mut aborted = None;
+        for ((entry, _), (run, member_packet)) in members.iter().zip(results) {
+            for field in &entry.writes {
+                packet.copy_field_from(&member_packet, *field);
+            }
+            for (key, value) in member_packet.current_states {
+                if base_states.get(&key) != Some(&value) {
+                    packet.current_states.insert(key, value);
+                }
+            }
+            if run.outcome == RunOutcome::Success {
+                packet.last_processor = Some(entry.name.clone());
+            }
+            if let Err(e) = Self::push_run(report, run) {
+                aborted.get_or_insert(e);
+            }
+        }
+
+        match aborted {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-                // 执行处理器
-                tracing::debug!("执行处理器: {} ({})", name, entry.description);
-                match processor.process(packet, ctx).await {
-                    Ok(()) => {
-                        packet.last_processor = Some(name.clone());
-                        tracing::debug!("处理器 {} 执行成功", name);
+    /// 检查处理器是否应跳过，返回跳过原因
+    fn check_skip(
+        entry: &ProcessorEntry,
+        processor: &Arc<dyn Processor>,
+        packet: &ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Option<RunOutcome> {
+        if processor.requires_memory() && !ctx.is_memory_enabled() {
+            return Some(RunOutcome::SkippedMemoryDisabled);
+        }
+        entry
+            .conditions
+            .iter()
+            .find(|c| !c.matches(packet, ctx.topic_type()))
+            .map(|c| RunOutcome::SkippedByCondition { condition: c.describe() })
+    }
+
+    /// 条目的单次执行超时：外部处理器未设置时使用全局默认超时，避免挂起的外部程序一直占用话题锁
+    fn timeout_secs(entry: &ProcessorEntry, ctx: &ProcessorContext) -> Option<u64> {
+        entry.timeout_secs.or_else(|| {
+            entry
+                .remote
+                .as_ref()
+                .map(|_| ctx.global_config.remote_processors.default_timeout_secs)
+        })
+    }
+
+    /// 按条目的超时/重试/失败策略执行单个处理器
+    async fn run_entry(
+        &self,
+        entry: &ProcessorEntry,
+        processor: &Arc<dyn Processor>,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> ProcessorRun {
+        let name = &entry.name;
+        let max_attempts = entry.retries + 1;
+        let started_at = Utc::now();
+        let messages_before = packet.messages.len();
+
+        // 需要重试或回滚时保存执行前快照
+        let snapshot = if entry.retries > 0 || entry.on_error == OnErrorPolicy::Rollback {
+            Some(packet.clone())
+        } else {
+            None
+        };
+
+        let timeout_secs = Self::timeout_secs(entry, ctx);
+        let mut last_error = String::new();
+        for attempt in 1..=max_attempts {
+            if attempt > 1 {
+                let backoff = entry.retry_backoff_ms.saturating_mul(1 << (attempt - 2).min(16));
+                tracing::info!("处理器 {} 第 {}/{} 次重试，等待 {}ms", name, attempt, max_attempts, backoff);
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                // 重试前恢复到执行前的状态，避免在半成品上继续处理
+                if let Some(ref snapshot) = snapshot {
+                    *packet = snapshot.clone();
+                }
+            }
+
+            let result = match timeout_secs {
+                Some(secs) => {
+                    let future = processor.process_with_params(packet, ctx, &entry.params);
+                    match tokio::time::timeout(Duration::from_secs(secs), future).await {
+                        Ok(result) => result.map_err(|e| e.to_string()),
+                        Err(_) => Err(format!("执行超时（{}秒）", secs)),
                     }
-                    Err(e) => {
-                        tracing::warn!("处理器 {} 执行失败，跳过: {}", name, e);
-                        // 继续下一个处理器，不中断流水线
+                }
+                None => processor
+                    .process_with_params(packet, ctx, &entry.params)
+                    .await
+                    .map_err(|e| e.to_string()),
+            };
+
+            match result {
+                Ok(()) => {
+                    packet.last_processor = Some(name.clone());
+                    tracing::debug!("处理器 {} 执行成功", name);
+                    let mut run = ProcessorRun::new(name, RunOutcome::Success, messages_before)
+                        .finish(started_at, packet.messages.len());
+                    run.attempts = attempt;
+                    return run;
+                }
+                Err(e) => {
+                    tracing::warn!("处理器 {} 第 {} 次执行失败: {}", name, attempt, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        match entry.on_error {
+            OnErrorPolicy::Skip => {
+                tracing::warn!("处理器 {} 执行失败，按 skip 策略跳过: {}", name, last_error);
+            }
+            OnErrorPolicy::Rollback => {
+                if let Some(snapshot) = snapshot {
+                    *packet = snapshot;
+                }
+                tracing::warn!("处理器 {} 执行失败，按 rollback 策略回滚数据包: {}", name, last_error);
+            }
+            OnErrorPolicy::Abort => {}
+        }
+
+        let mut run = ProcessorRun::new(name, RunOutcome::Failed { policy: entry.on_error }, messages_before)
+            .finish(started_at, packet.messages.len());
+        run.attempts = max_attempts;
+        run.error = Some(last_error);
+        run
+    }
+
+    /// 让 chunk 依次经过 on_stream_chunk 的处理器
+    ///
+    /// 返回最终要发送的内容；返回 None 表示该 chunk 被屏蔽。
+    /// 处理器失败时记录警告并保持 chunk 不变，不中断流式响应。
+    pub async fn dispatch_chunk(
+        &self,
+        chunk: String,
+        packet: &mut ConversationPacket,
+        pipeline_config: &PipelineConfig,
+        ctx: &ProcessorContext,
+    ) -> Option<String> {
+        self.run_chunk_processors(chunk, 0, packet, pipeline_config, ctx).await
+    }
+
+    /// 流式响应结束：收集各处理器缓冲的剩余内容
+    ///
+    /// 某处理器吐出的剩余内容会继续经过排在它之后的处理器
+    pub async fn finish_stream(
+        &self,
+        packet: &mut ConversationPacket,
+        pipeline_config: &PipelineConfig,
+        ctx: &ProcessorContext,
+    ) -> Option<String> {
+        let mut output = String::new();
+
+        for (index, entry) in pipeline_config.on_stream_chunk.iter().enumerate() {
+            let Some(processor) = self.processor_for(entry) else {
+                continue;
+            };
+            if Self::check_skip(entry, &processor, packet, ctx).is_some() {
+                continue;
+            }
+
+            match processor.finish_stream(packet, ctx).await {
+                Ok(Some(rest)) if !rest.is_empty() => {
+                    if let Some(passed) = self
+                        .run_chunk_processors(rest, index + 1, packet, pipeline_config, ctx)
+                        .await
+                    {
+                        output.push_str(&passed);
                     }
                 }
-            } else {
-                tracing::warn!("未找到处理器: {}", name);
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("处理器 {} 结束流式处理失败: {}", entry.name, e);
+                }
             }
         }
 
-        Ok(())
+        if output.is_empty() {
+            None
+        } else {
+            Some(output)
+        }
+    }
+
+    /// 从指定位置开始执行 on_stream_chunk 处理器
+    async fn run_chunk_processors(
+        &self,
+        chunk: String,
+        start: usize,
+        packet: &mut ConversationPacket,
+        pipeline_config: &PipelineConfig,
+        ctx: &ProcessorContext,
+    ) -> Option<String> {
+        let mut current = chunk;
+
+        for entry in pipeline_config.on_stream_chunk.iter().skip(start) {
+            let Some(processor) = self.processor_for(entry) else {
+                tracing::warn!("未找到处理器: {}", entry.name);
+                continue;
+            };
+            if Self::check_skip(entry, &processor, packet, ctx).is_some() {
+                continue;
+            }
+
+            match processor.process_chunk(&current, packet, ctx).await {
+                Ok(StreamChunkAction::Pass) => {}
+                Ok(StreamChunkAction::Replace(content)) => current = content,
+                Ok(StreamChunkAction::Suppress) => {
+                    tracing::trace!("chunk 被处理器 {} 屏蔽", entry.name);
+                    return None;
+                }
+                Err(e) => {
+                    tracing::warn!("处理器 {} 处理 chunk 失败，原样放行: {}", entry.name, e);
+                }
+            }
+        }
+
+        Some(current)
     }
 
     /// 获取上下文工厂
     pub fn context_factory(&self) -> &Arc<ProcessorContextFactory> {
         &self.context_factory
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    use async_trait::async_trait;
+
+    use crate::assistant::{AssistantConfig, TopicType};
+    use crate::pipeline::packet::PacketField;
+    use crate::pipeline::config::RemoteSpec;
+    use crate::pipeline::processor::ProcessorError;
+    use crate::types::ChatMessage;
+
+    /// 测试用处理器：追加一条以自身命名的消息并记录状态，前 `failures` 次执行失败
+    struct StubProcessor {
+        name: &'static str,
+        failures: u32,
+        delay: Duration,
+        calls: AtomicU32,
+    }
+
+    impl StubProcessor {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                failures: 0,
+                delay: Duration::ZERO,
+                calls: AtomicU32::new(0),
+            }
+        }
+
+        fn failing(mut self, failures: u32) -> Self {
+            self.failures = failures;
+            self
+        }
+
+        fn sleeping(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Processor for StubProcessor {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn requires_memory(&self) -> bool {
+            false
+        }
+
+        async fn process(
+            &self,
+            packet: &mut ConversationPacket,
+            _ctx: &ProcessorContext,
+        ) -> Result<(), ProcessorError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            packet.messages.push(ChatMessage::assistant(self.name));
+            packet.set_processor_state(self.name, serde_json::json!({ "call": call }));
+            tokio::time::sleep(self.delay).await;
+            if call <= self.failures {
+                return Err(ProcessorError::Internal(format!("第 {} 次失败", call)));
+            }
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        ctx: ProcessorContext,
+        dispatcher: PipelineDispatcher,
+    }
+
+    fn fixture(processors: Vec<StubProcessor>) -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let factory = ProcessorContextFactory::new(
+            ctx.ai_client.clone(),
+            ctx.global_config.clone(),
+            ctx.assistant_manager.clone(),
+            ctx.memory_manager.clone(),
+        );
+        let mut dispatcher = PipelineDispatcher::new(Arc::new(factory));
+        for processor in processors {
+            dispatcher.register(Arc::new(processor));
+        }
+        Fixture { _dir: dir, ctx, dispatcher }
+    }
+
+    fn packet() -> ConversationPacket {
+        ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        )
+    }
+
+    fn config(entries: Vec<ProcessorEntry>) -> PipelineConfig {
+        let mut config = PipelineConfig::empty();
+        config.after_ai_response = entries;
+        config
+    }
+
+    fn message_names(packet: &ConversationPacket) -> Vec<&str> {
+        packet.messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    async fn dispatch(
+        f: &Fixture,
+        packet: &mut ConversationPacket,
+        entries: Vec<ProcessorEntry>,
+    ) -> Result<DispatchReport, DispatcherError> {
+        f.dispatcher
+            .dispatch(PipelineTiming::AfterAiResponse, packet, &config(entries), &f.ctx)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_restores_packet() {
+        let f = fixture(vec![StubProcessor::new("Flaky").failing(2)]);
+        let mut entry = ProcessorEntry::new("Flaky");
+        entry.retries = 2;
+        entry.retry_backoff_ms = 20;
+
+        let mut packet = packet();
+        let report = dispatch(&f, &mut packet, vec![entry]).await.unwrap();
+
+        let run = &report.runs[0];
+        assert_eq!(run.outcome, RunOutcome::Success);
+        assert_eq!(run.attempts, 3);
+        // 退避 20ms + 40ms
+        assert!(run.duration_ms >= 60, "耗时 {}ms", run.duration_ms);
+        // 每次重试前恢复快照，只留下最后一次执行的修改
+        assert_eq!(message_names(&packet), vec!["Flaky"]);
+        assert_eq!(packet.current_states["Flaky"]["call"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_counts_as_failure() {
+        let f = fixture(vec![
+            StubProcessor::new("Slow").sleeping(Duration::from_secs(5)),
+            StubProcessor::new("Next"),
+        ]);
+        let mut entry = ProcessorEntry::new("Slow");
+        entry.timeout_secs = Some(1);
+
+        let started = Instant::now();
+        let mut packet = packet();
+        let report = dispatch(&f, &mut packet, vec![entry, ProcessorEntry::new("Next")])
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(report.runs[0].outcome, RunOutcome::Failed { policy: OnErrorPolicy::Skip });
+        assert!(report.runs[0].error.as_deref().unwrap().contains("超时"));
+        assert_eq!(report.runs[1].outcome, RunOutcome::Success);
+    }
+
+    #[test]
+    fn test_remote_entries_get_default_timeout() {
+        let f = fixture(vec![]);
+        let local = ProcessorEntry::new("Local");
+        assert_eq!(PipelineDispatcher::timeout_secs(&local, &f.ctx), None);
+
+        let mut remote = ProcessorEntry::new("Remote");
+        remote.remote = Some(RemoteSpec {
+            url: Some("http://127.0.0.1:9/process".to_string()),
+            command: None,
+            args: Vec::new(),
+            requires_memory: false,
+        });
+        assert_eq!(
+            PipelineDispatcher::timeout_secs(&remote, &f.ctx),
+            Some(f.ctx.global_config.remote_processors.default_timeout_secs)
+        );
+
+        remote.timeout_secs = Some(3);
+        assert_eq!(PipelineDispatcher::timeout_secs(&remote, &f.ctx), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_skip_keeps_changes_and_continues() {
+        let f = fixture(vec![StubProcessor::new("Broken").failing(u32::MAX), StubProcessor::new("Next")]);
+        let mut packet = packet();
+        let report = dispatch(
+            &f,
+            &mut packet,
+            vec![ProcessorEntry::new("Broken"), ProcessorEntry::new("Next")],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.runs[0].outcome, RunOutcome::Failed { policy: OnErrorPolicy::Skip });
+        assert_eq!(report.runs[0].attempts, 1);
+        assert_eq!(message_names(&packet), vec!["Broken", "Next"]);
+        assert_eq!(packet.last_processor.as_deref(), Some("Next"));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_snapshot() {
+        let f = fixture(vec![StubProcessor::new("Broken").failing(u32::MAX), StubProcessor::new("Next")]);
+        let mut entry = ProcessorEntry::new("Broken");
+        entry.on_error = OnErrorPolicy::Rollback;
+
+        let mut packet = packet();
+        let report = dispatch(&f, &mut packet, vec![entry, ProcessorEntry::new("Next")])
+            .await
+            .unwrap();
+
+        assert_eq!(report.runs[0].outcome, RunOutcome::Failed { policy: OnErrorPolicy::Rollback });
+        assert_eq!(message_names(&packet), vec!["Next"]);
+        assert!(!packet.current_states.contains_key("Broken"));
+    }
+
+    #[tokio::test]
+    async fn test_abort_stops_timing() {
+        let f = fixture(vec![StubProcessor::new("Broken").failing(u32::MAX), StubProcessor::new("Next")]);
+        let mut entry = ProcessorEntry::new("Broken");
+        entry.retries = 1;
+        entry.retry_backoff_ms = 1;
+        entry.on_error = OnErrorPolicy::Abort;
+
+        let mut packet = packet();
+        let err = dispatch(&f, &mut packet, vec![entry, ProcessorEntry::new("Next")])
+            .await
+            .unwrap_err();
+
+        let DispatcherError::Aborted { processor, report, .. } = err else {
+            panic!("应按 abort 策略中止");
+        };
+        assert_eq!(processor, "Broken");
+        assert_eq!(report.runs.len(), 1);
+        assert_eq!(report.runs[0].attempts, 2);
+        assert!(!message_names(&packet).contains(&"Next"));
+    }
+
+    fn grouped(name: &str, group: &str, writes: Vec<PacketField>) -> ProcessorEntry {
+        let mut entry = ProcessorEntry::new(name);
+        entry.parallel_group = Some(group.to_string());
+        entry.writes = writes;
+        entry
+    }
+
+    #[tokio::test]
+    async fn test_group_runs_in_parallel_and_merges_declared_writes() {
+        let delay = Duration::from_millis(300);
+        let f = fixture(vec![
+            StubProcessor::new("A").sleeping(delay),
+            StubProcessor::new("B").sleeping(delay),
+        ]);
+
+        let started = Instant::now();
+        let mut packet = packet();
+        let report = dispatch(
+            &f,
+            &mut packet,
+            vec![
+                grouped("A", "g", vec![PacketField::Messages]),
+                grouped("B", "g", vec![]),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert!(started.elapsed() < delay * 2, "耗时 {:?}", started.elapsed());
+        assert!(report.runs.iter().all(|r| r.outcome == RunOutcome::Success));
+        // 只有声明了 messages 的 A 的修改被写回，处理器状态总会合并
+        assert_eq!(message_names(&packet), vec!["A"]);
+        assert!(packet.current_states.contains_key("A"));
+        assert!(packet.current_states.contains_key("B"));
+        assert_eq!(packet.last_processor.as_deref(), Some("B"));
+    }
+
+    #[tokio::test]
+    async fn test_group_write_conflict_later_entry_wins() {
+        let f = fixture(vec![StubProcessor::new("A"), StubProcessor::new("B")]);
+        let mut packet = packet();
+        dispatch(
+            &f,
+            &mut packet,
+            vec![
+                grouped("A", "g", vec![PacketField::Messages]),
+                grouped("B", "g", vec![PacketField::Messages]),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(message_names(&packet), vec!["B"]);
+    }
+
+    #[tokio::test]
+    async fn test_group_abort_still_merges_other_members() {
+        let f = fixture(vec![
+            StubProcessor::new("A").failing(u32::MAX),
+            StubProcessor::new("B"),
+            StubProcessor::new("After"),
+        ]);
+        let mut failing = grouped("A", "g", vec![PacketField::Messages]);
+        failing.on_error = OnErrorPolicy::Abort;
+
+        let mut packet = packet();
+        let err = dispatch(
+            &f,
+            &mut packet,
+            vec![
+                failing,
+                grouped("B", "g", vec![]),
+                ProcessorEntry::new("After"),
+            ],
+        )
+        .await
+        .unwrap_err();
+
+        let DispatcherError::Aborted { processor, report, .. } = err else {
+            panic!("应按 abort 策略中止");
+        };
+        assert_eq!(processor, "A");
+        assert_eq!(report.runs.len(), 2);
+        assert!(packet.current_states.contains_key("B"));
+        assert!(!packet.current_states.contains_key("After"));
+    }
+}
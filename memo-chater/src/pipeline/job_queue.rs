@@ -0,0 +1,453 @@
+//! 后台任务队列
+//!
+//! 持久化 background_process 时机的执行任务，服务重启后可继续执行。
+//! 每个任务一个文件，存放在 data_dir/jobs 下；失败按退避时间重试，
+//! 超过最大次数后进入死信状态，需要通过管理 API 手动重试或删除。
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::{Mutex, Notify};
+
+use super::storage::{read_json, remove_json, write_json, StorageError};
+
+/// 默认最大执行次数
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// 默认重试退避基准（秒），每次失败翻倍
+const DEFAULT_RETRY_BASE_SECS: i64 = 10;
+
+/// 任务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// 等待执行（包括等待重试）
+    Pending,
+    /// 执行中
+    Running,
+    /// 重试耗尽（死信）
+    Dead,
+}
+
+/// 后台任务
+///
+/// 任务只记录要处理的话题，执行时读取最新数据包，完成后合并回最新数据包
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundJob {
+    /// 任务 ID
+    pub id: String,
+    /// 助手 ID
+    pub assistant_id: String,
+    /// 话题 ID
+    pub topic_id: String,
+    /// 触发任务的轮次
+    pub turn_index: u64,
+    /// 触发任务的请求的追踪ID（执行追踪合并到该记录）
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    /// 状态
+    pub status: JobStatus,
+    /// 已执行次数
+    pub attempts: u32,
+    /// 最大执行次数
+    pub max_attempts: u32,
+    /// 最后一次失败的错误信息
+    pub last_error: Option<String>,
+    /// 创建时间
+    pub created_at: DateTime<Utc>,
+    /// 最后更新时间
+    pub updated_at: DateTime<Utc>,
+    /// 最早可执行时间（重试退避）
+    pub next_run_at: DateTime<Utc>,
+}
+
+/// 后台任务队列
+pub struct JobQueue {
+    /// 任务文件目录
+    jobs_dir: PathBuf,
+    /// 最大执行次数
+    max_attempts: u32,
+    /// 重试退避基准（秒）
+    retry_base_secs: i64,
+    /// 串行化任务文件的读改写
+    lock: Mutex<()>,
+    /// 新任务通知
+    notify: Notify,
+}
+
+impl JobQueue {
+    /// 创建任务队列
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            jobs_dir: data_dir.join("jobs"),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_secs: DEFAULT_RETRY_BASE_SECS,
+            lock: Mutex::new(()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// 获取任务文件路径
+    fn job_path(&self, id: &str) -> PathBuf {
+        self.jobs_dir.join(format!("{}.json", id))
+    }
+
+    /// 读取所有任务（按创建时间排序）
+    async fn load_all(&self) -> Result<Vec<BackgroundJob>, StorageError> {
+        if !self.jobs_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(&self.jobs_dir)
+            .await
+            .map_err(|e| StorageError::ReadFailed(self.jobs_dir.clone(), e.to_string()))?;
+
+        let mut jobs = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_json::<BackgroundJob>(&path).await {
+                Ok(job) => jobs.push(job),
+                Err(e) => tracing::warn!("读取任务文件失败: {}", e),
+            }
+        }
+
+        jobs.sort_by_key(|j| j.created_at);
+        Ok(jobs)
+    }
+
+    /// 写入任务文件
+    async fn save(&self, job: &BackgroundJob) -> Result<(), StorageError> {
+        write_json(&self.job_path(&job.id), job).await
+    }
+
+    /// 删除任务文件
+    async fn remove(&self, id: &str) -> Result<(), StorageError> {
+        remove_json(&self.job_path(id)).await
+    }
+
+    /// 添加任务
+    ///
+    /// 同一话题已有等待中的任务时不重复添加（执行时总是读取最新数据包），只更新其轮次和追踪ID
+    pub async fn enqueue(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        turn_index: u64,
+        trace_id: &str,
+    ) -> Result<BackgroundJob, StorageError> {
+        let _guard = self.lock.lock().await;
+        let now = Utc::now();
+
+        let existing = self.load_all().await?.into_iter().find(|j| {
+            j.status == JobStatus::Pending && j.assistant_id == assistant_id && j.topic_id == topic_id
+        });
+
+        let job = match existing {
+            Some(mut job) => {
+                job.turn_index = turn_index;
+                job.trace_id = Some(trace_id.to_string());
+                job.updated_at = now;
+                job
+            }
+            None => BackgroundJob {
+                id: uuid::Uuid::new_v4().to_string(),
+                assistant_id: assistant_id.to_string(),
+                topic_id: topic_id.to_string(),
+                turn_index,
+                trace_id: Some(trace_id.to_string()),
+                status: JobStatus::Pending,
+                attempts: 0,
+                max_attempts: self.max_attempts,
+                last_error: None,
+                created_at: now,
+                updated_at: now,
+                next_run_at: now,
+            },
+        };
+
+        self.save(&job).await?;
+        self.notify.notify_one();
+        tracing::debug!("后台任务入队: {} ({}:{})", job.id, assistant_id, topic_id);
+        Ok(job)
+    }
+
+    /// 启动时恢复：上次退出时仍在执行的任务重新置为等待
+    pub async fn recover(&self) -> Result<usize, StorageError> {
+        let _guard = self.lock.lock().await;
+        let mut count = 0;
+        for mut job in self.load_all().await? {
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Pending;
+                job.updated_at = Utc::now();
+                self.save(&job).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// 取出下一个到期的任务并标记为执行中
+    pub async fn claim_next(&self) -> Result<Option<BackgroundJob>, StorageError> {
+        let _guard = self.lock.lock().await;
+        let now = Utc::now();
+
+        let next = self
+            .load_all()
+            .await?
+            .into_iter()
+            .find(|j| j.status == JobStatus::Pending && j.next_run_at <= now);
+
+        let Some(mut job) = next else {
+            return Ok(None);
+        };
+
+        job.status = JobStatus::Running;
+        job.attempts += 1;
+        job.updated_at = now;
+        self.save(&job).await?;
+        Ok(Some(job))
+    }
+
+    /// 任务执行成功：删除任务
+    pub async fn complete(&self, id: &str) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().await;
+        self.remove(id).await
+    }
+
+    /// 任务执行失败：未超过最大次数时按退避时间重新等待，否则进入死信
+    ///
+    /// 执行期间任务已被手动删除时不再写回
+    pub async fn fail(&self, job: &BackgroundJob, error: &str) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().await;
+        if !self.job_path(&job.id).exists() {
+            tracing::info!("后台任务 {} 执行期间已被删除，忽略失败: {}", job.id, error);
+            return Ok(());
+        }
+        let mut job = job.clone();
+        let now = Utc::now();
+        job.last_error = Some(error.to_string());
+        job.updated_at = now;
+
+        if job.attempts >= job.max_attempts {
+            job.status = JobStatus::Dead;
+            tracing::error!("后台任务 {} 重试耗尽，进入死信: {}", job.id, error);
+        } else {
+            let backoff = self.retry_base_secs.saturating_mul(1 << (job.attempts - 1).min(16));
+            job.status = JobStatus::Pending;
+            job.next_run_at = now + Duration::seconds(backoff);
+            tracing::warn!("后台任务 {} 第 {} 次执行失败，{} 秒后重试: {}", job.id, job.attempts, backoff, error);
+        }
+
+        self.save(&job).await
+    }
+
+    /// 列出任务（可按状态过滤）
+    pub async fn list(&self, status: Option<JobStatus>) -> Result<Vec<BackgroundJob>, StorageError> {
+        let jobs = self.load_all().await?;
+        Ok(match status {
+            Some(status) => jobs.into_iter().filter(|j| j.status == status).collect(),
+            None => jobs,
+        })
+    }
+
+    /// 手动重试任务（重置执行次数），任务不存在时返回 None
+    pub async fn retry(&self, id: &str) -> Result<Option<BackgroundJob>, StorageError> {
+        let _guard = self.lock.lock().await;
+        let Some(mut job) = self.load_all().await?.into_iter().find(|j| j.id == id) else {
+            return Ok(None);
+        };
+        if job.status == JobStatus::Running {
+            return Ok(Some(job));
+        }
+
+        let now = Utc::now();
+        job.status = JobStatus::Pending;
+        job.attempts = 0;
+        job.next_run_at = now;
+        job.updated_at = now;
+        self.save(&job).await?;
+        self.notify.notify_one();
+        Ok(Some(job))
+    }
+
+    /// 删除任务，返回任务是否存在
+    pub async fn delete(&self, id: &str) -> Result<bool, StorageError> {
+        let _guard = self.lock.lock().await;
+        let exists = self.job_path(id).exists();
+        self.remove(id).await?;
+        Ok(exists)
+    }
+
+    /// 恢复快照后取消话题中之后轮次的任务（执行中的任务写回时会发现数据包已回退），返回取消数
+    ///
+    /// 任务记录的是请求开始时的轮次，该轮结束后的快照轮次为 turn_index + 1
+    pub async fn cancel_after(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        snapshot_turn: u64,
+    ) -> Result<usize, StorageError> {
+        let _guard = self.lock.lock().await;
+        let mut count = 0;
+        for job in self.load_all().await? {
+            if job.status != JobStatus::Running
+                && job.assistant_id == assistant_id
+                && job.topic_id == topic_id
+                && job.turn_index >= snapshot_turn
+            {
+                self.remove(&job.id).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// 等待新任务或超时（用于工作循环轮询重试中的任务）
+    pub async fn wait(&self, timeout: std::time::Duration) {
+        let _ = tokio::time::timeout(timeout, self.notify.notified()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_enqueue_merges_pending_job() {
+        let temp_dir = tempdir().unwrap();
+        let queue = JobQueue::new(temp_dir.path());
+
+        let first = queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+        let second = queue.enqueue("ast_001", "topic_001", 2, "trace_2").await.unwrap();
+        queue.enqueue("ast_001", "topic_002", 1, "trace_3").await.unwrap();
+
+        // 同一话题只保留一个等待中的任务，轮次和追踪ID更新为最新
+        assert_eq!(first.id, second.id);
+        let jobs = queue.list(None).await.unwrap();
+        assert_eq!(jobs.len(), 2);
+        let merged = jobs.iter().find(|j| j.id == first.id).unwrap();
+        assert_eq!(merged.turn_index, 2);
+        assert_eq!(merged.trace_id.as_deref(), Some("trace_2"));
+    }
+
+    #[tokio::test]
+    async fn test_claim_next() {
+        let temp_dir = tempdir().unwrap();
+        let queue = JobQueue::new(temp_dir.path());
+        assert!(queue.claim_next().await.unwrap().is_none());
+
+        let job = queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+        let claimed = queue.claim_next().await.unwrap().unwrap();
+        assert_eq!(claimed.id, job.id);
+        assert_eq!(claimed.status, JobStatus::Running);
+        assert_eq!(claimed.attempts, 1);
+        assert!(queue.claim_next().await.unwrap().is_none());
+
+        // 执行中的任务不合并新请求
+        let next = queue.enqueue("ast_001", "topic_001", 2, "trace_2").await.unwrap();
+        assert_ne!(next.id, job.id);
+
+        queue.complete(&job.id).await.unwrap();
+        let jobs = queue.list(None).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, next.id);
+    }
+
+    #[tokio::test]
+    async fn test_fail_backoff_then_dead() {
+        let temp_dir = tempdir().unwrap();
+        let queue = JobQueue::new(temp_dir.path());
+        queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+
+        let claimed = queue.claim_next().await.unwrap().unwrap();
+        let before = Utc::now();
+        queue.fail(&claimed, "第一次失败").await.unwrap();
+
+        let job = queue.list(Some(JobStatus::Pending)).await.unwrap().remove(0);
+        assert_eq!(job.last_error.as_deref(), Some("第一次失败"));
+        assert!(job.next_run_at >= before + Duration::seconds(DEFAULT_RETRY_BASE_SECS));
+        // 退避期间不会被取出
+        assert!(queue.claim_next().await.unwrap().is_none());
+
+        // 退避时间翻倍
+        let mut second = job.clone();
+        second.attempts = 2;
+        let before = Utc::now();
+        queue.fail(&second, "第二次失败").await.unwrap();
+        let job = queue.list(Some(JobStatus::Pending)).await.unwrap().remove(0);
+        assert!(job.next_run_at >= before + Duration::seconds(DEFAULT_RETRY_BASE_SECS * 2));
+
+        // 达到最大次数后进入死信
+        let mut last = job.clone();
+        last.attempts = last.max_attempts;
+        queue.fail(&last, "最后一次失败").await.unwrap();
+        let dead = queue.list(Some(JobStatus::Dead)).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert!(queue.list(Some(JobStatus::Pending)).await.unwrap().is_empty());
+        assert!(queue.claim_next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_recover_after_crash() {
+        let temp_dir = tempdir().unwrap();
+        let job = {
+            let queue = JobQueue::new(temp_dir.path());
+            queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+            queue.claim_next().await.unwrap().unwrap()
+            // 执行中退出，任务文件保持 Running
+        };
+
+        let queue = JobQueue::new(temp_dir.path());
+        assert!(queue.claim_next().await.unwrap().is_none());
+        assert_eq!(queue.recover().await.unwrap(), 1);
+
+        let claimed = queue.claim_next().await.unwrap().unwrap();
+        assert_eq!(claimed.id, job.id);
+        assert_eq!(claimed.attempts, 2);
+        assert_eq!(claimed.trace_id.as_deref(), Some("trace_1"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_delete() {
+        let temp_dir = tempdir().unwrap();
+        let queue = JobQueue::new(temp_dir.path());
+        queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+
+        let mut claimed = queue.claim_next().await.unwrap().unwrap();
+        claimed.attempts = claimed.max_attempts;
+        queue.fail(&claimed, "失败").await.unwrap();
+
+        let retried = queue.retry(&claimed.id).await.unwrap().unwrap();
+        assert_eq!(retried.status, JobStatus::Pending);
+        assert_eq!(retried.attempts, 0);
+        assert!(queue.claim_next().await.unwrap().is_some());
+
+        // 执行中的任务不受手动重试影响
+        let running = queue.retry(&claimed.id).await.unwrap().unwrap();
+        assert_eq!(running.status, JobStatus::Running);
+
+        assert!(queue.retry("missing").await.unwrap().is_none());
+        assert!(queue.delete(&claimed.id).await.unwrap());
+        assert!(!queue.delete(&claimed.id).await.unwrap());
+        assert!(queue.list(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fail_after_delete_keeps_job_deleted() {
+        let temp_dir = tempdir().unwrap();
+        let queue = JobQueue::new(temp_dir.path());
+        queue.enqueue("ast_001", "topic_001", 1, "trace_1").await.unwrap();
+
+        // 执行中被手动删除，之后执行失败
+        let claimed = queue.claim_next().await.unwrap().unwrap();
+        assert!(queue.delete(&claimed.id).await.unwrap());
+        queue.fail(&claimed, "失败").await.unwrap();
+
+        assert!(queue.list(None).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,646 @@
+//! 内置记忆工具
+//!
+//! 助手开启 memory_tools 后，主模型可以通过函数调用管理自己的记忆：
+//! - `recall_memory`：检索长期记忆库和话题记忆库（含已归档的记忆）
+//! - `expand_memory`：读取一条记忆的完整内容
+//! - `save_memory`：写入短期记忆，之后由 MemoryCommitter 提交到长期记忆库
+//! - `forget_memory`：从短期记忆、话题记忆库和长期记忆库中删除
+//!
+//! 工具在服务端执行，调用和结果以临时消息写入数据包，不返回给客户端，也不写入话题历史
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::ai::ToolOptions;
+use crate::types::{ChatMessage, MemorySource, MessageMeta, ShortTermMemory, ToolCall};
+
+use super::context::ProcessorContext;
+use super::packet::ConversationPacket;
+use super::processor::ProcessorError;
+use super::processors::{MemoryCommitter, ShortTermVectorizer};
+
+/// 记忆工具消息的来源标记
+pub const MEMORY_TOOLS_ORIGIN: &str = "MemoryTools";
+
+/// 记忆工具消息的注入标识
+const INJECTION_ID: &str = "memory_tools";
+
+const RECALL_MEMORY: &str = "recall_memory";
+const EXPAND_MEMORY: &str = "expand_memory";
+const SAVE_MEMORY: &str = "save_memory";
+const FORGET_MEMORY: &str = "forget_memory";
+
+/// 一次响应中记忆工具调用的处理结果
+#[derive(Debug)]
+pub enum ToolRound {
+    /// 记忆工具已执行，携带结果继续请求模型
+    Continue,
+    /// 结束循环，剩余的客户端工具调用（可能为空）交给客户端
+    Finish(Vec<ToolCall>),
+}
+
+/// 检索结果中摘要的最大字符数
+const SUMMARY_CHARS: usize = 80;
+
+#[derive(Debug, Deserialize)]
+struct RecallArgs {
+    query: String,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdArgs {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SaveArgs {
+    content: String,
+    #[serde(rename = "type", default = "default_memory_type")]
+    memory_type: String,
+    #[serde(default)]
+    summary: Option<String>,
+}
+
+fn default_memory_type() -> String {
+    "fact".to_string()
+}
+
+/// 内置记忆工具
+pub struct MemoryTools;
+
+impl MemoryTools {
+    /// 工具定义（OpenAI function calling 格式）
+    pub fn definitions() -> Vec<Value> {
+        vec![
+            json!({
+                "type": "function",
+                "function": {
+                    "name": RECALL_MEMORY,
+                    "description": "按语义检索记忆（长期记忆和当前话题的记忆），返回记忆ID、摘要和相关度",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "query": {"type": "string", "description": "检索内容"},
+                            "limit": {"type": "integer", "description": "最多返回条数"}
+                        },
+                        "required": ["query"]
+                    }
+                }
+            }),
+            json!({
+                "type": "function",
+                "function": {
+                    "name": EXPAND_MEMORY,
+                    "description": "按ID读取一条记忆的完整内容",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "id": {"type": "string", "description": "记忆ID"}
+                        },
+                        "required": ["id"]
+                    }
+                }
+            }),
+            json!({
+                "type": "function",
+                "function": {
+                    "name": SAVE_MEMORY,
+                    "description": "保存一条值得记住的信息",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "content": {"type": "string", "description": "记忆内容"},
+                            "type": {
+                                "type": "string",
+                                "enum": ["fact", "preference", "event", "knowledge", "task", "other"],
+                                "description": "记忆类型"
+                            },
+                            "summary": {"type": "string", "description": "一句话摘要"}
+                        },
+                        "required": ["content"]
+                    }
+                }
+            }),
+            json!({
+                "type": "function",
+                "function": {
+                    "name": FORGET_MEMORY,
+                    "description": "按ID删除一条过时或错误的记忆",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "id": {"type": "string", "description": "记忆ID"}
+                        },
+                        "required": ["id"]
+                    }
+                }
+            }),
+        ]
+    }
+
+    /// 是否为内置记忆工具
+    pub fn is_memory_tool(name: &str) -> bool {
+        matches!(name, RECALL_MEMORY | EXPAND_MEMORY | SAVE_MEMORY | FORGET_MEMORY)
+    }
+
+    /// 在客户端传入的工具之外追加记忆工具
+    pub fn merge_into(tools: &ToolOptions) -> ToolOptions {
+        let mut merged = tools.clone();
+        merged
+            .tools
+            .get_or_insert_with(Vec::new)
+            .extend(Self::definitions());
+        merged
+    }
+
+    /// 执行一次响应中的记忆工具调用，返回本轮之后的去向
+    ///
+    /// 记忆工具的调用消息和结果以临时消息追加到数据包，供下一次请求使用；
+    /// 同时有客户端工具调用时，回复文本留给客户端工具调用消息。
+    /// 已执行 max_rounds 轮后不再执行记忆工具调用，直接以当前回复结束
+    pub async fn run(
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        content: &str,
+        calls: Vec<ToolCall>,
+        round: &mut usize,
+    ) -> ToolRound {
+        let (memory_calls, client_calls): (Vec<ToolCall>, Vec<ToolCall>) = calls
+            .into_iter()
+            .partition(|call| Self::is_memory_tool(&call.function.name));
+        if memory_calls.is_empty() {
+            return ToolRound::Finish(client_calls);
+        }
+        if *round >= ctx.memory_tools().max_rounds {
+            warn!(
+                "记忆工具已执行 {} 轮，忽略本轮 {} 个记忆工具调用",
+                *round,
+                memory_calls.len()
+            );
+            return ToolRound::Finish(client_calls);
+        }
+        *round += 1;
+
+        let content = if client_calls.is_empty() { content } else { "" };
+        packet.messages.push(
+            ChatMessage::assistant(content)
+                .with_tool_calls(memory_calls.clone())
+                .with_meta(MessageMeta::injected(MEMORY_TOOLS_ORIGIN, INJECTION_ID)),
+        );
+
+        for call in &memory_calls {
+            info!("执行记忆工具 {}({})", call.function.name, call.function.arguments);
+            let result = match Self::execute(call, packet, ctx).await {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("记忆工具 {} 执行失败: {}", call.function.name, e);
+                    json!({"error": e.to_string()})
+                }
+            };
+            packet.messages.push(
+                ChatMessage::tool(&call.id, result.to_string())
+                    .with_meta(MessageMeta::injected(MEMORY_TOOLS_ORIGIN, INJECTION_ID)),
+            );
+        }
+
+        if client_calls.is_empty() {
+            ToolRound::Continue
+        } else {
+            ToolRound::Finish(client_calls)
+        }
+    }
+
+    async fn execute(
+        call: &ToolCall,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Result<Value, ProcessorError> {
+        let arguments = if call.function.arguments.trim().is_empty() {
+            "{}"
+        } else {
+            &call.function.arguments
+        };
+        let invalid = |e: serde_json::Error| ProcessorError::Internal(format!("参数无效: {}", e));
+
+        match call.function.name.as_str() {
+            RECALL_MEMORY => {
+                let args: RecallArgs = serde_json::from_str(arguments).map_err(invalid)?;
+                let limit = args.limit.unwrap_or(ctx.memory_tools().recall_limit).max(1);
+                Self::recall(packet, ctx, &args.query, limit).await
+            }
+            EXPAND_MEMORY => {
+                let args: IdArgs = serde_json::from_str(arguments).map_err(invalid)?;
+                Self::expand(packet, ctx, &args.id).await
+            }
+            SAVE_MEMORY => {
+                let args: SaveArgs = serde_json::from_str(arguments).map_err(invalid)?;
+                Ok(Self::save(packet, args))
+            }
+            FORGET_MEMORY => {
+                let args: IdArgs = serde_json::from_str(arguments).map_err(invalid)?;
+                Self::forget(packet, ctx, &args.id).await
+            }
+            other => Err(ProcessorError::Internal(format!("未知的记忆工具: {}", other))),
+        }
+    }
+
+    /// 检索长期记忆库和话题记忆库，按相关度合并
+    async fn recall(
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        query: &str,
+        limit: usize,
+    ) -> Result<Value, ProcessorError> {
+        let mut hits: Vec<(f32, Value)> = Vec::new();
+
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(&ctx.assistant_id, Some(ctx.embedding_model()))
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+        let results = long_term
+            .search(query, limit)
+            .await
+            .map_err(|e| ProcessorError::MemoryError(e.to_string()))?;
+        for result in results {
+            hits.push((result.relevance, json!({
+                "id": result.memory.id,
+                "summary": Self::truncate(&result.memory.content),
+                "relevance": result.relevance,
+                "source": "long_term",
+            })));
+        }
+
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        if let Ok(Some(file)) = ShortTermVectorizer::load_vector_file(&path).await {
+            if !file.vectors.is_empty() {
+                let query_embedding = ctx
+                    .ai_client
+                    .embedding_with_model(query, Some(&file.metadata.embedding_model))
+                    .await
+                    .map_err(|e| ProcessorError::AiError(e.to_string()))?;
+                for memory in &file.vectors {
+                    let relevance = memory.score(&query_embedding);
+                    hits.push((relevance, json!({
+                        "id": memory.id,
+                        "summary": memory.summary,
+                        "relevance": relevance,
+                        "source": if memory.archived { "topic_archive" } else { "topic" },
+                    })));
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        hits.truncate(limit);
+
+        // 被检索到的短期记忆视为被访问，加强相关性
+        let ids: Vec<String> = hits
+            .iter()
+            .filter_map(|(_, hit)| hit["id"].as_str().map(|s| s.to_string()))
+            .collect();
+        packet.reinforce_short_term_memory(&ids, ctx.short_term_policy().reinforce_boost);
+
+        Ok(json!({ "memories": hits.into_iter().map(|(_, hit)| hit).collect::<Vec<_>>() }))
+    }
+
+    /// 依次在短期记忆、话题记忆库、长期记忆库中查找记忆全文
+    async fn expand(
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        id: &str,
+    ) -> Result<Value, ProcessorError> {
+        if let Some(memory) = packet.get_short_term_memory().iter().find(|m| m.id == id) {
+            let found = json!({
+                "id": memory.id,
+                "summary": memory.summary,
+                "content": memory.content,
+                "type": memory.memory_type,
+            });
+            packet.reinforce_short_term_memory(&[id.to_string()], ctx.short_term_policy().reinforce_boost);
+            return Ok(found);
+        }
+
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        if let Ok(Some(file)) = ShortTermVectorizer::load_vector_file(&path).await {
+            if let Some(memory) = file.vectors.iter().find(|v| v.id == id) {
+                return Ok(json!({
+                    "id": memory.id,
+                    "summary": memory.summary,
+                    "content": memory.content,
+                    "type": memory.memory_type,
+                }));
+            }
+        }
+
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(&ctx.assistant_id, Some(ctx.embedding_model()))
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+        match long_term
+            .get(id)
+            .await
+            .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        {
+            Some(memory) => Ok(json!({
+                "id": memory.id,
+                "content": memory.content,
+                "type": memory.category,
+            })),
+            None => Ok(json!({ "error": format!("未找到记忆 {}", id) })),
+        }
+    }
+
+    /// 写入短期记忆（来源为当前对话，由 MemoryCommitter 提交到长期记忆库）
+    fn save(packet: &mut ConversationPacket, args: SaveArgs) -> Value {
+        let id = format!("saved_{}", uuid::Uuid::new_v4());
+        let summary = args
+            .summary
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| Self::truncate(&args.content));
+        packet.add_short_term_memory(ShortTermMemory {
+            id: id.clone(),
+            summary,
+            content: args.content,
+            memory_type: args.memory_type,
+            should_expand: false,
+            relevance: 1.0,
+            confidence: 1.0,
+            source: MemorySource::CurrentConversation,
+            timestamp: Utc::now(),
+            committed: false,
+        });
+        json!({ "saved": true, "id": id })
+    }
+
+    /// 从短期记忆、话题记忆库和长期记忆库中删除
+    async fn forget(
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        id: &str,
+    ) -> Result<Value, ProcessorError> {
+        let removed_short_term = packet.remove_short_term_memories(&[id.to_string()]).len();
+
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        let mut removed_vectors = 0;
+        if let Some(mut file) = ShortTermVectorizer::load_vector_file(&path).await? {
+            let before = file.vectors.len();
+            file.vectors.retain(|v| v.id != id);
+            removed_vectors = before - file.vectors.len();
+            if removed_vectors > 0 {
+                file.metadata.last_updated = Utc::now().to_rfc3339();
+                ShortTermVectorizer::save_vector_file(&path, &file).await?;
+            }
+        }
+
+        // 已提交的短期记忆在长期记忆库中使用派生ID
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(&ctx.assistant_id, Some(ctx.embedding_model()))
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+        let committed_id = MemoryCommitter::long_term_id(&ctx.assistant_id, &ctx.topic_id, id);
+        let mut removed_long_term = 0;
+        for long_term_id in [id.to_string(), committed_id] {
+            let exists = long_term
+                .get(&long_term_id)
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+                .is_some();
+            if exists {
+                long_term
+                    .delete(&long_term_id)
+                    .await
+                    .map_err(|e| ProcessorError::MemoryError(e.to_string()))?;
+                removed_long_term += 1;
+            }
+        }
+
+        Ok(json!({
+            "forgotten": removed_short_term + removed_vectors + removed_long_term > 0,
+            "short_term": removed_short_term,
+            "topic": removed_vectors,
+            "long_term": removed_long_term,
+        }))
+    }
+
+    fn truncate(text: &str) -> String {
+        let text = text.trim();
+        if text.chars().count() <= SUMMARY_CHARS {
+            return text.to_string();
+        }
+        let truncated: String = text.chars().take(SUMMARY_CHARS).collect();
+        format!("{}...", truncated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assistant::{AssistantConfig, TopicType};
+    use crate::pipeline::processors::short_term_vectorizer::{ShortTermVectorFile, VectorizedMemory};
+    use crate::types::FunctionCall;
+
+    fn packet() -> ConversationPacket {
+        ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        )
+    }
+
+    fn call(id: &str, name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    fn memory(id: &str, relevance: f32) -> ShortTermMemory {
+        ShortTermMemory {
+            content: "用户喜欢喝乌龙茶，不加糖".to_string(),
+            memory_type: "preference".to_string(),
+            relevance,
+            ..ShortTermMemory::for_test(id)
+        }
+    }
+
+    fn context(dir: &std::path::Path) -> ProcessorContext {
+        ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir)
+    }
+
+    #[tokio::test]
+    async fn test_run_executes_memory_calls_and_returns_client_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let mut packet = packet();
+        let calls = vec![
+            call("call_1", SAVE_MEMORY, r#"{"content": "用户下周去杭州"}"#),
+            call("call_2", "get_weather", r#"{"city": "杭州"}"#),
+        ];
+
+        let mut round = 0;
+        let outcome = MemoryTools::run(&mut packet, &ctx, "我记下了", calls, &mut round).await;
+
+        let ToolRound::Finish(client_calls) = outcome else {
+            panic!("有客户端工具调用时应结束循环");
+        };
+        assert_eq!(client_calls.len(), 1);
+        assert_eq!(client_calls[0].id, "call_2");
+        assert_eq!(round, 1);
+        assert_eq!(packet.get_short_term_memory().len(), 1);
+
+        // 记忆工具的调用和结果以临时消息写入，回复文本留给客户端工具调用消息
+        let [assistant, result] = &packet.messages[..] else {
+            panic!("应写入调用消息和结果消息");
+        };
+        assert_eq!(assistant.content, "");
+        assert_eq!(assistant.tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(result.tool_call_id.as_deref(), Some("call_1"));
+        assert!(assistant.meta.is_some() && result.meta.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_run_continues_after_memory_only_round() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let mut packet = packet();
+
+        let mut round = 0;
+        let outcome = MemoryTools::run(
+            &mut packet,
+            &ctx,
+            "",
+            vec![call("call_1", SAVE_MEMORY, r#"{"content": "用户养了一只猫"}"#)],
+            &mut round,
+        )
+        .await;
+        assert!(matches!(outcome, ToolRound::Continue));
+        assert_eq!(round, 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_at_max_rounds() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let mut packet = packet();
+        let calls = vec![
+            call("call_1", SAVE_MEMORY, r#"{"content": "不应写入"}"#),
+            call("call_2", "get_weather", "{}"),
+        ];
+
+        let mut round = ctx.memory_tools().max_rounds;
+        let outcome = MemoryTools::run(&mut packet, &ctx, "回复", calls, &mut round).await;
+
+        // 超出轮数后记忆工具调用被丢弃，不执行也不写入消息
+        let ToolRound::Finish(client_calls) = outcome else {
+            panic!("超出轮数后应结束循环");
+        };
+        assert_eq!(client_calls.len(), 1);
+        assert_eq!(client_calls[0].id, "call_2");
+        assert_eq!(round, ctx.memory_tools().max_rounds);
+        assert!(packet.messages.is_empty());
+        assert!(packet.get_short_term_memory().is_empty());
+
+        let outcome = MemoryTools::run(
+            &mut packet,
+            &ctx,
+            "回复",
+            vec![call("call_3", SAVE_MEMORY, r#"{"content": "不应写入"}"#)],
+            &mut round,
+        )
+        .await;
+        assert!(matches!(outcome, ToolRound::Finish(calls) if calls.is_empty()));
+        assert!(packet.get_short_term_memory().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expand_returns_content_and_reinforces() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let mut packet = packet();
+        packet.add_short_term_memory(memory("m1", 0.5));
+
+        let result = MemoryTools::expand(&mut packet, &ctx, "m1").await.unwrap();
+        assert_eq!(result["content"], "用户喜欢喝乌龙茶，不加糖");
+        assert_eq!(result["type"], "preference");
+        assert!(packet.get_short_term_memory()[0].relevance > 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_forget_removes_short_term_and_topic_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let mut packet = packet();
+        packet.add_short_term_memory(memory("m1", 1.0));
+        packet.add_short_term_memory(memory("m2", 1.0));
+
+        let path = ShortTermVectorizer::get_vector_file_path(&ctx, &packet);
+        let mut file = ShortTermVectorFile::new("test-embedding".to_string(), 1);
+        file.vectors = vec![VectorizedMemory::for_test("m1", vec![1.0]), VectorizedMemory::for_test("m2", vec![1.0])];
+        ShortTermVectorizer::save_vector_file(&path, &file).await.unwrap();
+
+        // 测试环境没有向量库，长期记忆库一步会失败；短期记忆和话题记忆库在此之前已删除
+        let _ = MemoryTools::forget(&mut packet, &ctx, "m1").await;
+
+        let ids: Vec<&str> = packet.get_short_term_memory().iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["m2"]);
+        let file = ShortTermVectorizer::load_vector_file(&path).await.unwrap().unwrap();
+        let ids: Vec<&str> = file.vectors.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["m2"]);
+    }
+
+    #[test]
+    fn test_merge_into_keeps_client_tools() {
+        let client = ToolOptions {
+            tools: Some(vec![json!({"type": "function", "function": {"name": "get_weather"}})]),
+            tool_choice: Some(json!("auto")),
+        };
+        let merged = MemoryTools::merge_into(&client);
+        let names: Vec<&str> = merged
+            .tools
+            .as_ref()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t["function"]["name"].as_str())
+            .collect();
+        assert_eq!(names[0], "get_weather");
+        assert_eq!(names.len(), 5);
+        assert!(names[1..].iter().all(|name| MemoryTools::is_memory_tool(name)));
+        assert_eq!(merged.tool_choice, client.tool_choice);
+    }
+
+    #[test]
+    fn test_save_adds_short_term_memory() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        let args: SaveArgs = serde_json::from_str(r#"{"content": "用户喜欢喝乌龙茶", "type": "preference"}"#).unwrap();
+        let result = MemoryTools::save(&mut packet, args);
+
+        let memory = &packet.get_short_term_memory()[0];
+        assert_eq!(result["id"], memory.id.as_str());
+        assert_eq!(memory.memory_type, "preference");
+        assert_eq!(memory.summary, "用户喜欢喝乌龙茶");
+        assert_eq!(memory.source, MemorySource::CurrentConversation);
+    }
+}
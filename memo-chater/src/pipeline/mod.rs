@@ -12,8 +12,8 @@
 //! ## 处理时机
 //! 1. `on_user_message`: 用户发言追加到 messages 后
 //! 2. `before_ai_call`: 发送给 AI API 前
-//! 3. `on_stream_start`: 开始收到 AI 流式响应（预留）
-//! 4. `on_stream_chunk`: 收到每个 chunk 时（预留）
+//! 3. `on_stream_start`: 开始收到 AI 流式响应
+//! 4. `on_stream_chunk`: 收到每个 chunk 时（调用 `Processor::process_chunk`）
 //! 5. `after_ai_response`: AI 响应完整接收后
 //!
 //! ## 默认处理器
@@ -27,16 +27,30 @@
 pub mod packet;
 pub mod processor;
 pub mod context;
+pub mod budget;
+pub mod memory_tools;
 pub mod config;
+pub mod condition;
 pub mod dispatcher;
 pub mod storage;
+pub mod trace;
+pub mod job_queue;
+pub mod topic_lock;
 pub mod processors;
 
 // 导出核心类型
-pub use packet::{ConversationPacket, ThinkingEntry};
-pub use processor::{Processor, ProcessorError};
+pub use packet::{ConversationPacket, PacketField, ThinkingEntry};
+pub use processor::{Processor, ProcessorError, StreamChunkAction};
 pub use context::{ProcessorContext, ProcessorContextFactory};
-pub use config::{PipelineConfig, ProcessorEntry};
-pub use dispatcher::{PipelineDispatcher, PipelineTiming, DispatcherError};
-pub use storage::{PacketStorage, StorageError};
+pub use budget::{BudgetFit, BudgetItem, BudgetSection, ContextBudget, DroppedItem};
+pub use memory_tools::{MemoryTools, ToolRound};
+pub use config::{OnErrorPolicy, PipelineConfig, ProcessorEntry, ProcessorParams, RemoteSpec};
+pub use condition::{ProcessorCondition, StateScope};
+pub use dispatcher::{
+    DispatchReport, DispatcherError, PipelineDispatcher, PipelineTiming, ProcessorRun, RunOutcome,
+};
+pub use storage::{PacketDiff, PacketSnapshot, PacketStorage, SnapshotInfo, StorageError};
+pub use trace::{TraceStorage, TurnTrace};
+pub use job_queue::{BackgroundJob, JobQueue, JobStatus};
+pub use topic_lock::{TopicGuard, TopicLockError, TopicLocks};
 pub use processors::create_all_processors;
\ No newline at end of file
@@ -7,10 +7,11 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 
-use crate::types::{ChatMessage, ShortTermMemory, ThinkingSource};
+use crate::types::{ChatMessage, MemorySource, ShortTermMemory, ThinkingSource, ToolCall};
+use crate::storage::PACKET_SCHEMA;
 
 /// 思考条目 - 存储AI的内部推理（内嵌于Packet）
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ThinkingEntry {
     pub content: String,
     pub source: ThinkingSource,
@@ -28,9 +29,23 @@ pub struct ConversationTurn {
     pub timestamp: DateTime<Utc>,
 }
 
+/// 数据包中可由处理器写入的字段（并行执行时用于声明合并范围）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketField {
+    Messages,
+    ThinkingPool,
+    ShortTermMemory,
+    HistorySummary,
+}
+
 /// 对话数据包 - 跟随对话生命周期的核心数据结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationPacket {
+    /// 持久化格式版本（见 storage::PACKET_SCHEMA）
+    #[serde(default)]
+    pub schema_version: u32,
+
     // ===== 定位信息 =====
     /// 助手ID（用于定位配置和记忆存储）
     pub assistant_id: String,
@@ -57,6 +72,9 @@ pub struct ConversationPacket {
     /// 短期记忆池 - 检索注入的相关信息
     #[serde(default)]
     pub short_term_memory: Vec<ShortTermMemory>,
+    /// 上次按时间衰减短期记忆相关性的时间（ShortTermEvictor 维护）
+    #[serde(default)]
+    pub memory_decayed_at: Option<DateTime<Utc>>,
 
     // ===== 处理器状态 =====
     /// 当前轮次的处理器状态 <处理器名, 状态数据>
@@ -66,12 +84,23 @@ pub struct ConversationPacket {
     #[serde(default)]
     pub history_states: VecDeque<HashMap<String, serde_json::Value>>,
 
+    // ===== 历史摘要 =====
+    /// 滚动摘要 - HistorySimplifier 压缩较早轮次后的累积摘要
+    #[serde(default)]
+    pub history_summary: String,
+
     // ===== 历史对话轮次 =====
     /// 历史对话轮次（每轮包含 user + assistant）
     #[serde(default)]
     pub conversation_turns: Vec<ConversationTurn>,
+    /// 已切块的最后一轮的时间戳（ContentChunker 维护的水位线）
+    #[serde(default)]
+    pub chunked_until: Option<DateTime<Utc>>,
 
     // ===== 流程控制 =====
+    /// 已完成的轮次数（end_turn 时递增，用于按轮次条件执行处理器）
+    #[serde(default)]
+    pub turn_index: u64,
     /// 最后成功通过的处理器名称
     #[serde(default)]
     pub last_processor: Option<String>,
@@ -107,6 +136,7 @@ impl ConversationPacket {
         assistant_name: String,
     ) -> Self {
         Self {
+            schema_version: PACKET_SCHEMA.current,
             assistant_id,
             topic_id,
             user_id: None,
@@ -115,7 +145,11 @@ impl ConversationPacket {
             messages: Vec::new(),
             thinking_pool: Vec::new(),
             short_term_memory: Vec::new(),
+            memory_decayed_at: None,
+            history_summary: String::new(),
             conversation_turns: Vec::new(),
+            chunked_until: None,
+            turn_index: 0,
             current_states: HashMap::new(),
             history_states: VecDeque::new(),
             last_processor: None,
@@ -132,8 +166,13 @@ impl ConversationPacket {
 
     /// 追加用户消息
     pub fn append_user_message(&mut self, content: &str) {
-        self.messages.push(ChatMessage::user(content));
-        self.user_input = content.to_string();
+        self.append_user_chat_message(ChatMessage::user(content));
+    }
+
+    /// 追加完整的用户消息（保留多模态内容和 name），user_input 取其文本投影
+    pub fn append_user_chat_message(&mut self, message: ChatMessage) {
+        self.user_input = message.content.clone();
+        self.messages.push(message);
     }
 
     /// 追加助手消息
@@ -142,6 +181,73 @@ impl ConversationPacket {
         self.ai_response = Some(content.to_string());
     }
 
+    /// 追加助手发起的工具调用消息（本轮尚未结束，不设置 ai_response）
+    pub fn append_assistant_tool_calls(&mut self, content: &str, tool_calls: Vec<ToolCall>) {
+        self.messages.push(ChatMessage::assistant(content).with_tool_calls(tool_calls));
+    }
+
+    /// 追加工具结果消息，并作为短期记忆（来源 ToolResult）供处理器使用
+    ///
+    /// 只接受等待结果的工具调用（见 `pending_tool_call_ids`），没有对应调用或已有结果时忽略并返回 false
+    pub fn append_tool_result(&mut self, message: ChatMessage) -> bool {
+        let call_id = message.tool_call_id.clone().unwrap_or_default();
+        if !self.pending_tool_call_ids().contains(&call_id) {
+            return false;
+        }
+        let tool_name = self
+            .find_tool_call(&call_id)
+            .map(|call| call.function.name.clone())
+            .unwrap_or_else(|| "unknown".to_string());
+        self.short_term_memory.push(ShortTermMemory {
+            id: format!("tool_{}_{}", self.turn_index, call_id),
+            summary: format!("工具 {} 的调用结果", tool_name),
+            content: message.content.clone(),
+            memory_type: "tool_result".to_string(),
+            should_expand: false,
+            relevance: 1.0,
+            confidence: 1.0,
+            source: MemorySource::ToolResult,
+            timestamp: Utc::now(),
+            committed: false,
+        });
+        self.messages.push(message);
+        true
+    }
+
+    /// 等待结果的工具调用ID：最近一条助手消息发起的、之后还没有对应 tool 消息的调用
+    ///
+    /// 最近一条助手消息没有发起工具调用，或之后已有新的用户发言时为空
+    pub fn pending_tool_call_ids(&self) -> Vec<String> {
+        let mut answered = Vec::new();
+        for message in self.messages.iter().rev() {
+            match message.role.as_str() {
+                "tool" => answered.extend(message.tool_call_id.clone()),
+                "assistant" => {
+                    return message
+                        .tool_calls
+                        .iter()
+                        .flatten()
+                        .filter(|call| !answered.contains(&call.id))
+                        .map(|call| call.id.clone())
+                        .collect();
+                }
+                "user" if !message.is_ephemeral() => return Vec::new(),
+                _ => {}
+            }
+        }
+        Vec::new()
+    }
+
+    /// 按调用ID查找助手发起的工具调用（从最近的消息开始）
+    pub fn find_tool_call(&self, call_id: &str) -> Option<&ToolCall> {
+        self.messages
+            .iter()
+            .rev()
+            .filter_map(|m| m.tool_calls.as_ref())
+            .flatten()
+            .find(|call| call.id == call_id)
+    }
+
     /// 保存当前轮次的对话（user_input + ai_response）
     /// 
     /// 在 AI 响应完成后、after_ai_response 处理器执行前调用
@@ -178,6 +284,22 @@ impl ConversationPacket {
         self.messages.insert(0, ChatMessage::system(content));
     }
 
+    /// 移除指定注入标识的消息，返回移除条数
+    pub fn remove_injection(&mut self, injection_id: &str) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|m| !m.is_injection(injection_id));
+        before - self.messages.len()
+    }
+
+    /// 移除所有临时消息，返回被移除的消息
+    pub fn remove_ephemeral_messages(&mut self) -> Vec<ChatMessage> {
+        let (removed, kept) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|m| m.is_ephemeral());
+        self.messages = kept;
+        removed
+    }
+
     // ===== 思考池操作 =====
 
     /// 添加思考条目
@@ -221,13 +343,43 @@ impl ConversationPacket {
         &self.short_term_memory
     }
 
-/// 按时间排序获取短期记忆（最新的在前）
+    /// 按相关性排序获取短期记忆（相关性相同时最新的在前）
     pub fn get_short_term_memory_sorted(&self) -> Vec<&ShortTermMemory> {
         let mut sorted: Vec<_> = self.short_term_memory.iter().collect();
-        sorted.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        sorted.sort_by(|a, b| {
+            b.relevance
+                .total_cmp(&a.relevance)
+                .then(b.timestamp.cmp(&a.timestamp))
+        });
         sorted
     }
 
+    /// 衰减所有短期记忆的相关性（乘以 factor）
+    pub fn decay_short_term_memory(&mut self, factor: f32) {
+        for memory in &mut self.short_term_memory {
+            memory.relevance *= factor;
+        }
+    }
+
+    /// 加强指定短期记忆的相关性（增加 boost，上限 1.0），返回命中的条数
+    pub fn reinforce_short_term_memory(&mut self, ids: &[String], boost: f32) -> usize {
+        let mut count = 0;
+        for memory in self.short_term_memory.iter_mut().filter(|m| ids.contains(&m.id)) {
+            memory.relevance = (memory.relevance + boost).min(1.0);
+            count += 1;
+        }
+        count
+    }
+
+    /// 移除指定的短期记忆，返回被移除的条目
+    pub fn remove_short_term_memories(&mut self, ids: &[String]) -> Vec<ShortTermMemory> {
+        let (removed, kept) = std::mem::take(&mut self.short_term_memory)
+            .into_iter()
+            .partition(|m| ids.contains(&m.id));
+        self.short_term_memory = kept;
+        removed
+    }
+
     // ===== 处理器状态操作 =====
 
     /// 轮次结束处理：状态轮转（思考池和短期记忆保留）
@@ -242,6 +394,7 @@ impl ConversationPacket {
         }
         // 清空当前轮次状态（思考池和短期记忆保留）
         self.current_states.clear();
+        self.turn_index += 1;
         self.last_processor = None;
         self.user_input.clear();
         self.ai_response = None;
@@ -254,6 +407,71 @@ impl ConversationPacket {
             .and_then(|states| states.get(processor_name))
     }
 
+    /// 从另一个数据包复制指定字段（并行处理器合并结果时使用）
+    pub fn copy_field_from(&mut self, other: &ConversationPacket, field: PacketField) {
+        match field {
+            PacketField::Messages => self.messages = other.messages.clone(),
+            PacketField::ThinkingPool => self.thinking_pool = other.thinking_pool.clone(),
+            PacketField::ShortTermMemory => {
+                self.short_term_memory = other.short_term_memory.clone();
+                self.memory_decayed_at = other.memory_decayed_at;
+            }
+            PacketField::HistorySummary => self.history_summary = other.history_summary.clone(),
+        }
+    }
+
+    /// 将 base 到 updated 之间的变化合并到当前数据包
+    ///
+    /// 后台任务在数据包副本上执行，期间最新数据包可能已被下一轮修改，
+    /// 写回时只合并后台处理器实际做出的改动：
+    /// - 短期记忆按 id 合并（新增追加、修改覆盖、删除移除），衰减时间发生变化时覆盖
+    /// - 思考池追加新增条目
+    /// - 处理器状态合并发生变化的键
+    /// - history_summary、切块水位线发生变化时覆盖
+    ///
+    /// messages 不合并（后台处理器不应修改对话上下文）
+    pub fn merge_changes(&mut self, base: &ConversationPacket, updated: &ConversationPacket) {
+        for memory in &updated.short_term_memory {
+            let original = base.short_term_memory.iter().find(|m| m.id == memory.id);
+            if original == Some(memory) {
+                continue;
+            }
+            match self.short_term_memory.iter_mut().find(|m| m.id == memory.id) {
+                Some(existing) => *existing = memory.clone(),
+                None => self.short_term_memory.push(memory.clone()),
+            }
+        }
+        let removed: Vec<&str> = base
+            .short_term_memory
+            .iter()
+            .filter(|m| !updated.short_term_memory.iter().any(|u| u.id == m.id))
+            .map(|m| m.id.as_str())
+            .collect();
+        self.short_term_memory.retain(|m| !removed.contains(&m.id.as_str()));
+        if updated.memory_decayed_at != base.memory_decayed_at {
+            self.memory_decayed_at = updated.memory_decayed_at;
+        }
+
+        for entry in &updated.thinking_pool {
+            if !base.thinking_pool.contains(entry) && !self.thinking_pool.contains(entry) {
+                self.thinking_pool.push(entry.clone());
+            }
+        }
+
+        for (key, value) in &updated.current_states {
+            if base.current_states.get(key) != Some(value) {
+                self.current_states.insert(key.clone(), value.clone());
+            }
+        }
+
+        if updated.history_summary != base.history_summary {
+            self.history_summary = updated.history_summary.clone();
+        }
+        if updated.chunked_until != base.chunked_until {
+            self.chunked_until = updated.chunked_until;
+        }
+    }
+
     /// 记录处理器状态
     pub fn set_processor_state(&mut self, processor_name: &str, state: serde_json::Value) {
         self.current_states.insert(processor_name.to_string(), state);
@@ -263,7 +481,7 @@ impl ConversationPacket {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::MemorySource;
+    use crate::types::{FunctionCall, ToolCallDelta};
 
     #[test]
     fn test_packet_creation() {
@@ -317,20 +535,24 @@ mod tests {
             summary: "用户喜欢编程".to_string(),
             content: "用户喜欢编程，经常讨论代码相关话题".to_string(),
             memory_type: "preference".to_string(),
+            should_expand: false,
             relevance: 0.9,
             confidence: 1.0,
             source: MemorySource::LongTermRetrieval,
             timestamp: Utc::now(),
+            committed: false,
         };
         let mem2 = ShortTermMemory {
             id: "mem_2".to_string(),
             summary: "用户住在北京".to_string(),
             content: "用户住在北京".to_string(),
             memory_type: "fact".to_string(),
+            should_expand: false,
             relevance: 0.7,
             confidence: 1.0,
             source: MemorySource::LongTermRetrieval,
             timestamp: Utc::now(),
+            committed: false,
         };
         
         packet.add_short_term_memory(mem1);
@@ -352,6 +574,144 @@ mod tests {
         assert!(packet.short_term_memory[0].relevance < 0.5);
     }
 
+    #[test]
+    fn test_remove_ephemeral_messages() {
+        use crate::types::MessageMeta;
+
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.messages.push(ChatMessage::system("系统提示词"));
+        packet.messages.push(
+            ChatMessage::user("任意模板内容").with_meta(MessageMeta::injected("ShortTermAssembler", "short_term_memory")),
+        );
+        packet.append_user_message("你好");
+        packet.append_assistant_message("你好呀");
+
+        // 内部元数据不出现在没有元数据的消息中
+        let json = serde_json::to_value(&packet.messages[2]).unwrap();
+        assert!(json.get("meta").is_none());
+
+        let removed = packet.remove_ephemeral_messages();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(packet.messages.len(), 3);
+        assert!(packet.messages.iter().all(|m| m.meta.is_none()));
+    }
+
+    #[test]
+    fn test_append_multimodal_user_message() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        let message: ChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "name": "qin",
+            "content": [
+                {"type": "text", "text": "看看这张图"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+            ]
+        }))
+        .unwrap();
+        packet.append_user_chat_message(message);
+        assert_eq!(packet.user_input, "看看这张图\n[图片]");
+
+        let json = serde_json::to_value(&packet.messages[0]).unwrap();
+        assert_eq!(json["name"], "qin");
+        assert_eq!(json["content"][1]["type"], "image_url");
+        assert_eq!(json["content"][1]["image_url"]["url"], "https://example.com/a.png");
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.append_user_message("北京天气怎么样");
+
+        // 流式增量拼接成完整调用
+        let deltas: Vec<ToolCallDelta> = serde_json::from_value(serde_json::json!([
+            {"index": 0, "id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": ""}},
+            {"index": 0, "function": {"arguments": "{\"city\":"}},
+            {"index": 0, "function": {"arguments": "\"北京\"}"}}
+        ]))
+        .unwrap();
+        let mut calls = Vec::new();
+        for delta in &deltas {
+            delta.apply_to(&mut calls);
+        }
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.arguments, "{\"city\":\"北京\"}");
+
+        packet.append_assistant_tool_calls("", calls);
+        let json = serde_json::to_value(packet.messages.last().unwrap()).unwrap();
+        assert!(json["content"].is_null());
+        assert_eq!(json["tool_calls"][0]["function"]["name"], "get_weather");
+
+        let result: ChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "tool", "tool_call_id": "call_1", "content": "晴，25度"
+        }))
+        .unwrap();
+        assert!(packet.append_tool_result(result));
+        let memory = packet.get_short_term_memory().last().unwrap();
+        assert_eq!(memory.source, MemorySource::ToolResult);
+        assert!(memory.summary.contains("get_weather"));
+        assert_eq!(packet.messages.last().unwrap().role, "tool");
+    }
+
+    #[test]
+    fn test_tool_results_need_pending_call() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            call_type: "function".to_string(),
+            function: FunctionCall { name: "get_weather".to_string(), arguments: "{}".to_string() },
+        };
+
+        packet.append_user_message("北京和上海天气怎么样");
+        // 没有发起工具调用时不接受结果
+        assert!(!packet.append_tool_result(ChatMessage::tool("call_1", "晴")));
+        assert!(packet.pending_tool_call_ids().is_empty());
+
+        packet.append_assistant_tool_calls("", vec![call("call_1"), call("call_2")]);
+        assert_eq!(packet.pending_tool_call_ids(), vec!["call_1", "call_2"]);
+
+        assert!(packet.append_tool_result(ChatMessage::tool("call_1", "晴")));
+        // 重复提交和未知调用被忽略
+        assert!(!packet.append_tool_result(ChatMessage::tool("call_1", "晴")));
+        assert!(!packet.append_tool_result(ChatMessage::tool("call_9", "雨")));
+        assert_eq!(packet.pending_tool_call_ids(), vec!["call_2"]);
+
+        assert!(packet.append_tool_result(ChatMessage::tool("call_2", "多云")));
+        assert!(packet.pending_tool_call_ids().is_empty());
+        assert_eq!(packet.messages.iter().filter(|m| m.role == "tool").count(), 2);
+        let tool_memories: Vec<&str> = packet
+            .short_term_memory
+            .iter()
+            .filter(|m| m.source == MemorySource::ToolResult)
+            .map(|m| m.id.as_str())
+            .collect();
+        assert_eq!(tool_memories, vec!["tool_0_call_1", "tool_0_call_2"]);
+
+        // 新的用户发言之后，之前的调用不再等待结果
+        packet.append_assistant_tool_calls("", vec![call("call_3")]);
+        packet.append_user_message("算了");
+        assert!(!packet.append_tool_result(ChatMessage::tool("call_3", "晴")));
+    }
+
     #[test]
     fn test_end_turn() {
         let mut packet = ConversationPacket::new(
@@ -370,4 +730,51 @@ mod tests {
         let prev = packet.get_previous_state("TestProcessor");
         assert!(prev.is_some());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_copy_field_from() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        let mut branch = packet.clone();
+        branch.add_thinking("并行分析".to_string(), ThinkingSource::UserAnalysis);
+        branch.history_summary = "摘要".to_string();
+
+        packet.copy_field_from(&branch, PacketField::ThinkingPool);
+        assert_eq!(packet.thinking_pool.len(), 1);
+        // 未声明的字段不合并
+        assert!(packet.history_summary.is_empty());
+    }
+
+    #[test]
+    fn test_merge_changes() {
+        let mut base = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        base.add_thinking("旧思考".to_string(), ThinkingSource::UserAnalysis);
+
+        // 后台任务在副本上执行
+        let mut updated = base.clone();
+        updated.add_thinking("后台分析".to_string(), ThinkingSource::UserAnalysis);
+        updated.set_processor_state("Background", serde_json::json!({"done": true}));
+        updated.chunked_until = Some(Utc::now());
+
+        // 期间最新数据包已进入下一轮
+        let mut latest = base.clone();
+        latest.append_user_message("下一轮");
+        latest.turn_index = 1;
+
+        latest.merge_changes(&base, &updated);
+        assert_eq!(latest.thinking_pool.len(), 2);
+        assert_eq!(latest.turn_index, 1);
+        assert_eq!(latest.messages.len(), 1);
+        assert!(latest.current_states.contains_key("Background"));
+        assert_eq!(latest.chunked_until, updated.chunked_until);
+    }
+}
@@ -6,6 +6,7 @@ use thiserror::Error;
 
 use super::packet::ConversationPacket;
 use super::context::ProcessorContext;
+use super::config::ProcessorParams;
 
 /// 处理器错误
 #[derive(Debug, Error)]
@@ -26,6 +27,17 @@ pub enum ProcessorError {
     MemoryError(String),
 }
 
+/// 流式 chunk 处理结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunkAction {
+    /// 原样放行
+    Pass,
+    /// 替换为新的内容
+    Replace(String),
+    /// 屏蔽该 chunk（不发送给客户端）
+    Suppress,
+}
+
 /// 处理器统一接口
 ///
 /// 所有流水线处理器都需要实现此 trait。
@@ -57,4 +69,53 @@ pub trait Processor: Send + Sync {
         packet: &mut ConversationPacket,
         ctx: &ProcessorContext,
     ) -> Result<(), ProcessorError>;
+
+    /// 校验条目中的 params
+    ///
+    /// 默认不接受任何参数；有可配置项的处理器应覆盖此方法，
+    /// 保存助手配置时会调用它拒绝无效参数
+    fn validate_params(&self, params: &ProcessorParams) -> Result<(), ProcessorError> {
+        if params.is_empty() {
+            Ok(())
+        } else {
+            Err(ProcessorError::Config(format!("{} 不接受参数", self.name())))
+        }
+    }
+
+    /// 带参数执行处理（调度器实际调用的入口）
+    ///
+    /// 默认忽略参数直接调用 `process`，有可配置项的处理器应覆盖此方法
+    async fn process_with_params(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        _params: &ProcessorParams,
+    ) -> Result<(), ProcessorError> {
+        self.process(packet, ctx).await
+    }
+
+    /// 处理流式响应的单个 chunk（仅在 on_stream_chunk 时机调用）
+    ///
+    /// 默认原样放行，普通处理器无需实现。
+    /// 流式处理器可覆盖此方法来观察、改写或屏蔽 delta；
+    /// 跨 chunk 的状态应通过 `packet.set_processor_state` 保存（处理器实例在请求间共享）。
+    async fn process_chunk(
+        &self,
+        _chunk: &str,
+        _packet: &mut ConversationPacket,
+        _ctx: &ProcessorContext,
+    ) -> Result<StreamChunkAction, ProcessorError> {
+        Ok(StreamChunkAction::Pass)
+    }
+
+    /// 流式响应结束时调用
+    ///
+    /// 返回处理器缓冲但尚未发出的内容（如被截断的半个标签），默认无内容
+    async fn finish_stream(
+        &self,
+        _packet: &mut ConversationPacket,
+        _ctx: &ProcessorContext,
+    ) -> Result<Option<String>, ProcessorError> {
+        Ok(None)
+    }
 }
\ No newline at end of file
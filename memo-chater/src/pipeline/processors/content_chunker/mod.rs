@@ -1,30 +1,46 @@
 //! 内容切块器
 //!
 //! 将对话内容按逻辑切分成独立的信息块，便于后续存储和检索
+//!
+//! 以 packet.conversation_turns 为准，packet.chunked_until 记录已切块的水位线（最后一轮的时间戳），
+//! 每次只切水位线之后的新轮次，并可附带之前的切块摘要，让跨轮次的话题续写而不是重复切块
 
 use async_trait::async_trait;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
 use tracing::{info, warn, debug};
 
 use crate::pipeline::{
     processor::{Processor, ProcessorError},
+    config::ProcessorParams,
     context::ProcessorContext,
-    packet::ConversationPacket,
+    packet::{ConversationPacket, ConversationTurn},
 };
 use crate::types::{ChatMessage, ShortTermMemory, MemorySource};
 
-/// 切块配置
-#[derive(Debug, Clone, Deserialize)]
+/// 内置默认配置（编译进二进制，条目 params 未覆盖的字段使用这里的值）
+const DEFAULT_CONFIG: &str = include_str!("config.toml");
+
+/// 切块配置（可通过流水线条目的 params 覆盖）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct ChunkerConfig {
     /// 使用的模型（留空则使用助手配置的 processor_model）
     #[serde(default)]
     model: String,
     /// 切块提示词
     prompt: String,
+    /// 是否在提示词中附带之前的切块摘要（{previous_chunks}）
+    #[serde(default = "default_include_previous_chunks")]
+    include_previous_chunks: bool,
+    /// 附带的切块摘要条数（取最近的）
+    #[serde(default = "default_previous_chunk_count")]
+    previous_chunk_count: usize,
 }
 
+fn default_include_previous_chunks() -> bool { true }
+fn default_previous_chunk_count() -> usize { 10 }
+
 impl Default for ChunkerConfig {
     fn default() -> Self {
         Self {
@@ -47,10 +63,15 @@ impl Default for ChunkerConfig {
 
 type可选值：fact/event/preference/knowledge/task/other
 
+已有切块（之前对话切出的块的摘要；新对话延续其中的话题时只切出新增信息，不要重复）：
+{previous_chunks}
+
 对话内容：
 {conversation}
 
 请直接输出XML，不要有其他内容。"#.to_string(),
+            include_previous_chunks: default_include_previous_chunks(),
+            previous_chunk_count: default_previous_chunk_count(),
         }
     }
 }
@@ -71,49 +92,75 @@ pub struct ContentChunk {
 /// 
 /// 负责将对话内容切分成适合向量化存储的块
 pub struct ContentChunker {
-    config_path: PathBuf,
+    /// 默认配置
+    defaults: ChunkerConfig,
+}
+
+impl Default for ContentChunker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ContentChunker {
     pub fn new() -> Self {
-        // 配置文件路径：与本模块同目录
-        let config_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("src/pipeline/processors/content_chunker/config.toml");
-        
-        Self { config_path }
+        let defaults = toml::from_str(DEFAULT_CONFIG).unwrap_or_else(|e| {
+            warn!("ContentChunker 内置配置解析失败，使用代码默认值: {}", e);
+            ChunkerConfig::default()
+        });
+
+        Self { defaults }
     }
 
-    /// 加载配置
-    fn load_config(&self) -> Result<ChunkerConfig, ProcessorError> {
-        if self.config_path.exists() {
-            let content = std::fs::read_to_string(&self.config_path)
-                .map_err(|e| ProcessorError::Config(format!("读取配置文件失败: {}", e)))?;
-            
-            toml::from_str(&content)
-                .map_err(|e| ProcessorError::Config(format!("解析配置文件失败: {}", e)))
-        } else {
-            warn!("ContentChunker 配置文件不存在，使用默认配置: {:?}", self.config_path);
-            Ok(ChunkerConfig::default())
-        }
+    /// 在默认配置上叠加条目参数
+    fn load_config(&self, params: &ProcessorParams) -> Result<ChunkerConfig, ProcessorError> {
+        params.merge_into(&self.defaults)
     }
 
     /// 格式化对话内容
-    fn format_conversation(&self, messages: &[ChatMessage], user_name: &str, assistant_name: &str) -> String {
-        messages
+    fn format_turns(&self, turns: &[&ConversationTurn], user_name: &str, assistant_name: &str) -> String {
+        turns
             .iter()
-            .filter(|m| m.role != "system") // 排除系统消息
-            .map(|m| {
-                let role_name = match m.role.as_str() {
-                    "user" => user_name,
-                    "assistant" => assistant_name,
-                    _ => &m.role,
-                };
-                format!("【{}】: {}", role_name, m.content)
+            .flat_map(|turn| {
+                [
+                    (user_name, turn.user_message.trim()),
+                    (assistant_name, turn.assistant_message.trim()),
+                ]
             })
+            .filter(|(_, content)| !content.is_empty())
+            .map(|(role_name, content)| format!("【{}】: {}", role_name, content))
             .collect::<Vec<_>>()
             .join("\n\n")
     }
 
+    /// 格式化之前的切块摘要（按时间顺序，取最近的 count 条）
+    fn format_previous_chunks(&self, packet: &ConversationPacket, count: usize) -> String {
+        let mut memories: Vec<&ShortTermMemory> = packet
+            .get_short_term_memory()
+            .iter()
+            .filter(|m| matches!(m.source, MemorySource::CurrentConversation))
+            .collect();
+        memories.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+        memories.truncate(count);
+        memories
+            .iter()
+            .rev()
+            .map(|m| format!("- [{}] {}", m.memory_type, m.summary))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// 水位线之后尚未切块的轮次（没有水位线时为全部轮次）
+    ///
+    /// 水位线保存在数据包上而不是处理器状态中，处理器被跳过多轮后也不会漏切
+    fn pending_turns(packet: &ConversationPacket) -> Vec<&ConversationTurn> {
+        packet
+            .get_all_turns()
+            .iter()
+            .filter(|t| packet.chunked_until.is_none_or(|watermark| t.timestamp > watermark))
+            .collect()
+    }
+
     /// 解析大模型返回的 XML 格式切块结果
     /// 
     /// XML + CDATA 格式比 JSON 更抗特殊字符干扰
@@ -288,34 +335,70 @@ impl Processor for ContentChunker {
         &self,
         packet: &mut ConversationPacket,
         ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        self.process_with_params(packet, ctx, &ProcessorParams::default()).await
+    }
+
+    fn validate_params(&self, params: &ProcessorParams) -> Result<(), ProcessorError> {
+        self.load_config(params).map(|_| ())
+    }
+
+    async fn process_with_params(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        params: &ProcessorParams,
     ) -> Result<(), ProcessorError> {
         info!("ContentChunker 开始处理");
 
         // 加载配置
-        let config = self.load_config()?;
+        let config = self.load_config(params)?;
         debug!("配置加载成功，模型: {}", if config.model.is_empty() { "使用默认" } else { &config.model });
 
+        // 只取水位线之后的新轮次
+        let new_turns = Self::pending_turns(packet);
+        let Some(new_watermark) = new_turns.last().map(|t| t.timestamp) else {
+            info!("没有新的对话轮次，跳过切块");
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "skipped": true,
+                "reason": "no_new_turns",
+                "watermark": packet.chunked_until
+            }));
+            return Ok(());
+        };
+
         // 格式化对话内容
-        let conversation = self.format_conversation(
-            &packet.messages,
+        let conversation = self.format_turns(
+            &new_turns,
             &packet.user_name,
             &packet.assistant_name,
         );
 
         if conversation.is_empty() {
             info!("对话内容为空，跳过切块");
+            packet.chunked_until = Some(new_watermark);
             packet.set_processor_state(self.name(), serde_json::json!({
                 "skipped": true,
-                "reason": "empty_conversation"
+                "reason": "empty_conversation",
+                "watermark": new_watermark
             }));
             return Ok(());
         }
 
-        debug!("对话内容长度: {} 字符", conversation.len());
+        debug!("新增 {} 轮对话，内容长度: {} 字符", new_turns.len(), conversation.len());
+        let chunked_turns = new_turns.len();
+
+        let previous_chunks = if config.include_previous_chunks {
+            self.format_previous_chunks(packet, config.previous_chunk_count)
+        } else {
+            String::new()
+        };
+        let previous_chunks = if previous_chunks.is_empty() { "（无）" } else { &previous_chunks };
 
         // 构建提示词（替换所有占位符）
         let prompt = config.prompt
             .replace("{conversation}", &conversation)
+            .replace("{previous_chunks}", previous_chunks)
             .replace("{user_name}", &packet.user_name)
             .replace("{assistant_name}", &packet.assistant_name);
 
@@ -415,8 +498,11 @@ impl Processor for ContentChunker {
                 content: chunk.content.clone(),
                 memory_type: chunk.chunk_type.clone(),
                 should_expand: true, // 新切块默认展开
+                relevance: 1.0,
+                confidence: 1.0,
                 source: MemorySource::CurrentConversation,
                 timestamp: Utc::now(),
+                committed: false,
             };
 
             packet.add_short_term_memory(memory);
@@ -424,27 +510,96 @@ impl Processor for ContentChunker {
 
         info!("已将 {} 个切块存入短期记忆", chunks.len());
 
-        // 清空对话上下文（保留 system 消息）
-        let system_msg = packet.messages.iter()
-            .find(|m| m.role == "system")
-            .cloned();
-        
-        packet.messages.clear();
-        
-        if let Some(sys) = system_msg {
-            packet.messages.push(sys);
-        }
-
-        info!("已清空对话上下文");
+        packet.chunked_until = Some(new_watermark);
 
         // 保存到处理器状态（供后续处理器使用）
         packet.set_processor_state(self.name(), serde_json::json!({
             "chunked": true,
+            "chunked_turns": chunked_turns,
+            "watermark": new_watermark,
             "chunk_count": chunks.len(),
-            "chunks": chunks,
-            "context_cleared": true
+            "chunks": chunks
         }));
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assistant::{AssistantConfig, TopicType};
+    use chrono::DateTime;
+
+    #[test]
+    fn test_builtin_config_parses() {
+        let config: ChunkerConfig = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert!(config.include_previous_chunks);
+        assert!(config.prompt.contains("{previous_chunks}"));
+        assert!(ChunkerConfig::default().prompt.contains("{previous_chunks}"));
+    }
+
+    fn turn(packet: &mut ConversationPacket, n: i64) {
+        packet.conversation_turns.push(ConversationTurn {
+            user_message: format!("问题 {}", n),
+            assistant_message: format!("回答 {}", n),
+            timestamp: DateTime::from_timestamp(1_700_000_000 + n, 0).unwrap(),
+        });
+    }
+
+    fn user_messages<'a>(turns: &[&'a ConversationTurn]) -> Vec<&'a str> {
+        turns.iter().map(|t| t.user_message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_missing_watermark_takes_all_turns() {
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        for n in 1..=3 {
+            turn(&mut packet, n);
+        }
+        assert_eq!(
+            user_messages(&ContentChunker::pending_turns(&packet)),
+            vec!["问题 1", "问题 2", "问题 3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skipped_turns_are_not_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.messages = vec![ChatMessage::system("系统提示词"), ChatMessage::user("问题 4")];
+        turn(&mut packet, 1);
+        packet.chunked_until = Some(packet.conversation_turns[0].timestamp);
+        packet.set_processor_state("ContentChunker", serde_json::json!({ "chunked": true }));
+        packet.end_turn();
+
+        // 连续 3 轮跳过切块，处理器状态早已轮转出历史
+        for n in 2..=4 {
+            turn(&mut packet, n);
+            packet.set_processor_state("ShortTermEvictor", serde_json::json!({ "evicted": 0 }));
+            packet.end_turn();
+        }
+        assert!(packet.history_states.iter().all(|states| !states.contains_key("ContentChunker")));
+        assert_eq!(
+            user_messages(&ContentChunker::pending_turns(&packet)),
+            vec!["问题 2", "问题 3", "问题 4"]
+        );
+
+        // 切块失败时水位线不前进，对话上下文保持不变
+        let watermark = packet.chunked_until;
+        assert!(ContentChunker::new().process(&mut packet, &ctx).await.is_err());
+        assert_eq!(packet.chunked_until, watermark);
+        assert_eq!(packet.messages.len(), 2);
+        assert_eq!(ContentChunker::pending_turns(&packet).len(), 3);
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! 清理上下文中为 API 请求而构建的临时内容（如记忆注入消息），
 //! 以便后续处理器（如 ContentChunker）能处理干净的对话内容。
+//! 注入的消息通过 `ChatMessage::meta` 识别。
 
 use async_trait::async_trait;
 use tracing::{info, debug};
@@ -11,42 +12,18 @@ use crate::pipeline::{
     context::ProcessorContext,
     packet::ConversationPacket,
 };
-use crate::types::ChatMessage;
 
 /// 上下文清理器
 /// 
-/// 负责清理上下文中的临时构建内容，保留真实对话
+/// 负责清理上下文中的临时构建内容，保留真实对话。
+/// 按消息元数据识别：处理器注入时标记为临时（`MessageMeta::ephemeral`）的消息都会被移除，
+/// 不依赖注入内容的文字模板
 pub struct ContextCleaner;
 
 impl ContextCleaner {
     pub fn new() -> Self {
         Self
     }
-
-    /// 清理短期记忆注入消息（ShortTermAssembler 注入）
-    /// 
-    /// 匹配条件：
-    /// - user 消息以「【系统消息-短期记忆】现在为你注入」开头
-    fn should_remove_short_term_memory_injection(msg: &ChatMessage) -> bool {
-        msg.role == "user" && msg.content.starts_with("【系统消息-短期记忆】现在为你注入")
-    }
-
-    /// 清理短期记忆展开消息（ShortTermExpander 注入）
-    /// 
-    /// 匹配条件：
-    /// - user 消息以「【系统消息-短期记忆】根据」开头
-    fn should_remove_short_term_memory_expansion(msg: &ChatMessage) -> bool {
-        msg.role == "user" && msg.content.starts_with("【系统消息-短期记忆】根据")
-    }
-
-    // ========== 后续可在此添加更多清理规则 ==========
-
-    /// 判断消息是否应该被清理
-    fn should_remove(msg: &ChatMessage) -> bool {
-        // 依次检查各个清理规则
-        Self::should_remove_short_term_memory_injection(msg)
-            || Self::should_remove_short_term_memory_expansion(msg)
-    }
 }
 
 #[async_trait]
@@ -68,23 +45,25 @@ impl Processor for ContextCleaner {
 
         let before_count = packet.messages.len();
 
-        // 过滤掉需要清理的消息
-        packet.messages.retain(|msg| {
-            let should_keep = !Self::should_remove(msg);
-            if !should_keep {
-                debug!("清理消息: [{}] {}", msg.role, 
-                    if msg.content.chars().count() > 30 { 
-                        format!("{}...", msg.content.chars().take(30).collect::<String>()) 
-                    } else { 
-                        msg.content.clone() 
-                    }
-                );
+        // 移除所有临时注入的消息
+        let removed = packet.remove_ephemeral_messages();
+        let mut removed_origins: Vec<&str> = Vec::new();
+        for msg in &removed {
+            let origin = msg.meta.as_ref().and_then(|m| m.origin.as_deref()).unwrap_or("unknown");
+            debug!("清理消息: [{}] 来自 {} {}", msg.role, origin,
+                if msg.content.chars().count() > 30 { 
+                    format!("{}...", msg.content.chars().take(30).collect::<String>()) 
+                } else { 
+                    msg.content.clone() 
+                }
+            );
+            if !removed_origins.contains(&origin) {
+                removed_origins.push(origin);
             }
-            should_keep
-        });
+        }
 
         let after_count = packet.messages.len();
-        let removed_count = before_count - after_count;
+        let removed_count = removed.len();
 
         info!("ContextCleaner 完成，清理了 {} 条消息", removed_count);
 
@@ -93,7 +72,8 @@ impl Processor for ContextCleaner {
             "cleaned": true,
             "removed_count": removed_count,
             "before_count": before_count,
-            "after_count": after_count
+            "after_count": after_count,
+            "removed_origins": removed_origins
         }));
 
         Ok(())
@@ -1,23 +1,121 @@
 //! 历史对话简化器
 //!
-//! 简化/压缩历史对话，减少上下文长度
+//! 按助手配置的 token 预算（不超过上下文窗口中最近轮次与摘要的份额）压缩历史对话：
+//! 最近 N 轮原样保留，更早的轮次由处理模型合并进滚动摘要（存于 packet.history_summary）
 
 use async_trait::async_trait;
+use tracing::{debug, info, warn};
+
 use crate::pipeline::{
     processor::{Processor, ProcessorError},
+    budget::{BudgetSection, ContextBudget},
     context::ProcessorContext,
     packet::ConversationPacket,
 };
+use crate::types::{ChatMessage, MessageMeta};
+
+/// 历史摘要注入消息前缀
+const SUMMARY_PREFIX: &str = "【系统消息-历史摘要】";
+
+/// 历史摘要消息的注入标识
+const INJECTION_ID: &str = "history_summary";
+
+/// 摘要提示词
+const SUMMARY_PROMPT: &str = r#"你是一个对话摘要专家。请将「已有摘要」与「新增对话」合并为一份新的滚动摘要。
+
+要求：
+1. 保留人物关系、关键事实、约定、未完成的任务和重要情节
+2. 使用实际角色名（用户：{user_name}，助手：{assistant_name}），不要使用"用户"或"助手"这样的通用称呼
+3. 按时间顺序组织，语言简洁，不超过800字
+4. 直接输出摘要正文，不要有其他内容
+
+## 已有摘要
+{summary}
+
+## 新增对话
+{conversation}"#;
 
 /// 历史对话简化器
-/// 
+///
 /// 负责简化和压缩历史对话，减少token消耗
 pub struct HistorySimplifier;
 
+impl Default for HistorySimplifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HistorySimplifier {
     pub fn new() -> Self {
         Self
     }
+
+    /// 历史对话的 token 预算：助手配置的历史预算与上下文窗口中“最近轮次 + 摘要”份额的较小者
+    fn token_budget(ctx: &ProcessorContext) -> usize {
+        let window_budget = ContextBudget::for_context(ctx);
+        let window_share = window_budget.limit(BudgetSection::RecentTurns)
+            + window_budget.limit(BudgetSection::Summary);
+        ctx.history_token_budget().min(window_share)
+    }
+
+    /// 划分消息：返回（开头 system 消息数, 原样保留的起始下标），二者之间的消息可以压缩
+    fn split_messages(messages: &[ChatMessage], keep_recent_turns: usize) -> (usize, usize) {
+        let system_count = messages.iter().take_while(|m| m.role == "system").count();
+        let keep_start = Self::find_keep_start(messages, keep_recent_turns).max(system_count);
+        (system_count, keep_start)
+    }
+
+    /// 构建历史摘要注入消息
+    fn build_summary_message(summary: &str) -> ChatMessage {
+        ChatMessage::user(format!(
+            "{}以下是更早对话的摘要\n---历史摘要---\n{}\n---摘要结束---",
+            SUMMARY_PREFIX, summary
+        ))
+        .with_meta(MessageMeta::injected("HistorySimplifier", INJECTION_ID))
+    }
+
+    /// 计算需要原样保留的起始下标
+    ///
+    /// 从末尾向前数 keep_recent_turns + 1 条 user 消息（含本轮用户发言），
+    /// 该消息之前的非 system 消息都可以被压缩
+    fn find_keep_start(messages: &[ChatMessage], keep_recent_turns: usize) -> usize {
+        let mut user_seen = 0;
+        for (i, msg) in messages.iter().enumerate().rev() {
+            if msg.role == "user" {
+                user_seen += 1;
+                if user_seen > keep_recent_turns {
+                    return i;
+                }
+            }
+        }
+        0
+    }
+
+    /// 格式化待压缩的对话
+    fn format_conversation(messages: &[ChatMessage], user_name: &str, assistant_name: &str) -> String {
+        messages
+            .iter()
+            .map(|m| {
+                let role_name = match m.role.as_str() {
+                    "user" => user_name,
+                    "assistant" => assistant_name,
+                    _ => &m.role,
+                };
+                format!("【{}】: {}", role_name, m.content)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// 将摘要消息插入到开头的 system 消息之后
+    fn insert_summary(messages: &mut Vec<ChatMessage>, summary: &str) {
+        if summary.is_empty() {
+            return;
+        }
+        let pos = messages.iter().take_while(|m| m.role == "system").count();
+        messages.insert(pos, Self::build_summary_message(summary));
+    }
 }
 
 #[async_trait]
@@ -27,20 +125,190 @@ impl Processor for HistorySimplifier {
     }
 
     fn requires_memory(&self) -> bool {
-        true
+        false
     }
 
     async fn process(
         &self,
         packet: &mut ConversationPacket,
-        _ctx: &ProcessorContext,
+        ctx: &ProcessorContext,
     ) -> Result<(), ProcessorError> {
-        // TODO: 实现历史对话简化逻辑
-        // 1. 分析历史对话长度
-        // 2. 对过长的历史进行摘要压缩
-        // 3. 保留关键信息，移除冗余内容
-        
-        packet.set_processor_state(self.name(), serde_json::json!({"simplified": true}));
+        info!("HistorySimplifier 开始处理");
+
+        let budget = Self::token_budget(ctx);
+        let keep_recent_turns = ctx.history_keep_recent_turns();
+
+        // 先移除上一轮注入的摘要消息，再按最新摘要重新计算
+        let mut messages: Vec<ChatMessage> = packet
+            .messages
+            .iter()
+            .filter(|m| !m.is_injection(INJECTION_ID))
+            .cloned()
+            .collect();
+
+        let summary_tokens = if packet.history_summary.is_empty() {
+            0
+        } else {
+            ctx.count_tokens(&packet.history_summary) + 4
+        };
+        let total_tokens = ctx.count_message_tokens(&messages) + summary_tokens;
+        debug!("历史对话估算 {} tokens，预算 {}", total_tokens, budget);
+
+        if total_tokens <= budget {
+            Self::insert_summary(&mut messages, &packet.history_summary);
+            packet.messages = messages;
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "simplified": false,
+                "estimated_tokens": total_tokens,
+                "token_budget": budget
+            }));
+            return Ok(());
+        }
+
+        // 划分：开头的 system 消息 / 可压缩的较早消息 / 原样保留的最近轮次
+        let (system_count, keep_start) = Self::split_messages(&messages, keep_recent_turns);
+
+        if keep_start <= system_count {
+            warn!(
+                "历史对话超出预算（{} > {}），但最近 {} 轮之外没有可压缩的内容",
+                total_tokens, budget, keep_recent_turns
+            );
+            Self::insert_summary(&mut messages, &packet.history_summary);
+            packet.messages = messages;
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "simplified": false,
+                "over_budget": true,
+                "estimated_tokens": total_tokens,
+                "token_budget": budget
+            }));
+            return Ok(());
+        }
+
+        let older: Vec<ChatMessage> = messages[system_count..keep_start]
+            .iter()
+            .filter(|m| m.role != "system")
+            .cloned()
+            .collect();
+
+        let conversation = Self::format_conversation(&older, ctx.user_name(), ctx.assistant_name());
+        let prompt = SUMMARY_PROMPT
+            .replace("{user_name}", ctx.user_name())
+            .replace("{assistant_name}", ctx.assistant_name())
+            .replace("{summary}", if packet.history_summary.is_empty() { "（无）" } else { &packet.history_summary })
+            .replace("{conversation}", &conversation);
+
+        info!("压缩 {} 条较早消息到滚动摘要", older.len());
+
+        // 摘要失败时不修改数据包，由调度器跳过本处理器
+        let new_summary = ctx
+            .ai_client
+            .chat_with_model(&[ChatMessage::user(&prompt)], Some(ctx.processor_model()))
+            .await
+            .map_err(|e| ProcessorError::AiError(e.to_string()))?
+            .trim()
+            .to_string();
+
+        if new_summary.is_empty() {
+            return Err(ProcessorError::AiError("模型返回的摘要为空".to_string()));
+        }
+
+        let mut simplified: Vec<ChatMessage> = messages[..system_count].to_vec();
+        simplified.extend(messages[keep_start..].iter().cloned());
+        Self::insert_summary(&mut simplified, &new_summary);
+
+        let after_tokens = ctx.count_message_tokens(&simplified);
+        info!("历史压缩完成: {} -> {} tokens", total_tokens, after_tokens);
+
+        packet.history_summary = new_summary;
+        packet.messages = simplified;
+        packet.set_processor_state(self.name(), serde_json::json!({
+            "simplified": true,
+            "compressed_messages": older.len(),
+            "estimated_tokens_before": total_tokens,
+            "estimated_tokens_after": after_tokens,
+            "token_budget": budget
+        }));
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assistant::{AssistantConfig, TopicType};
+
+    fn conversation(turns: usize) -> Vec<ChatMessage> {
+        let mut messages = vec![ChatMessage::system("系统提示词")];
+        for i in 0..turns {
+            messages.push(ChatMessage::user(format!("问题 {}", i)));
+            messages.push(ChatMessage::assistant(format!("回答 {}", i)));
+        }
+        messages.push(ChatMessage::user("本轮问题"));
+        messages
+    }
+
+    #[test]
+    fn test_split_keeps_recent_turns() {
+        let messages = conversation(5);
+        let (system_count, keep_start) = HistorySimplifier::split_messages(&messages, 2);
+        assert_eq!(system_count, 1);
+        // 保留最近 2 轮 + 本轮用户发言
+        assert_eq!(messages[keep_start].content, "问题 3");
+        assert_eq!(messages.len() - keep_start, 5);
+    }
+
+    #[test]
+    fn test_split_nothing_to_compress() {
+        let messages = conversation(2);
+        let (system_count, keep_start) = HistorySimplifier::split_messages(&messages, 4);
+        assert_eq!(keep_start, system_count);
+    }
+
+    #[test]
+    fn test_token_budget_capped_by_window_share() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AssistantConfig::default();
+        config.history.token_budget = 100_000;
+        config.model.context_window = 20_000;
+        config.model.max_tokens = 0;
+        let ctx = ProcessorContext::for_test(config.clone(), TopicType::Normal, dir.path());
+        // 默认份额：最近轮次 0.35 + 摘要 0.1，约 9000
+        let window_budget = ContextBudget::for_context(&ctx);
+        let share = window_budget.limit(BudgetSection::RecentTurns)
+            + window_budget.limit(BudgetSection::Summary);
+        assert!((8_990..=9_000).contains(&share));
+        assert_eq!(HistorySimplifier::token_budget(&ctx), share);
+
+        config.history.token_budget = 1_000;
+        let ctx = ProcessorContext::for_test(config, TopicType::Normal, dir.path());
+        assert_eq!(HistorySimplifier::token_budget(&ctx), 1_000);
+    }
+
+    #[tokio::test]
+    async fn test_replaces_injected_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.messages = conversation(1);
+        HistorySimplifier::insert_summary(&mut packet.messages, "旧摘要");
+        packet.history_summary = "新摘要".to_string();
+
+        HistorySimplifier::new().process(&mut packet, &ctx).await.unwrap();
+
+        let summaries: Vec<&ChatMessage> = packet
+            .messages
+            .iter()
+            .filter(|m| m.is_injection(INJECTION_ID))
+            .collect();
+        assert_eq!(summaries.len(), 1);
+        assert!(summaries[0].content.contains("新摘要"));
+        // 摘要位于 system 消息之后
+        assert!(packet.messages[1].is_injection(INJECTION_ID));
+    }
+}
@@ -0,0 +1,224 @@
+//! 长期记忆检索器
+//!
+//! 根据用户输入从助手的长期记忆库（Qdrant）检索相关记忆，注入到短期记忆池
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::pipeline::{
+    processor::{Processor, ProcessorError},
+    context::ProcessorContext,
+    packet::ConversationPacket,
+};
+use crate::memory::RetrievedMemory;
+use crate::types::{LongTermMemory, MemorySource, ShortTermMemory};
+
+/// 摘要最大字符数
+const SUMMARY_MAX_CHARS: usize = 50;
+
+/// 长期记忆检索器
+///
+/// 对 user_input 做语义检索，命中且超过相关性阈值的记忆
+/// 以 `MemorySource::LongTermRetrieval` 加入短期记忆，并记录访问
+pub struct LongTermRetriever;
+
+impl Default for LongTermRetriever {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LongTermRetriever {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// 按相关性阈值筛选检索结果，拆分为（需要新注入的, 已在短期记忆中的）
+    fn partition_hits<'a>(
+        results: &'a [RetrievedMemory],
+        threshold: f32,
+        existing_ids: &HashSet<String>,
+    ) -> (Vec<&'a RetrievedMemory>, Vec<&'a RetrievedMemory>) {
+        results
+            .iter()
+            .filter(|r| r.relevance >= threshold)
+            .partition(|r| !existing_ids.contains(&r.memory.id))
+    }
+
+    /// 从长期记忆内容生成摘要（取首行，超长截断）
+    fn build_summary(content: &str) -> String {
+        let first_line = content.lines().next().unwrap_or("").trim();
+        if first_line.chars().count() > SUMMARY_MAX_CHARS {
+            let truncated: String = first_line.chars().take(SUMMARY_MAX_CHARS).collect();
+            format!("{}...", truncated)
+        } else {
+            first_line.to_string()
+        }
+    }
+
+    /// 转换为短期记忆条目（初始相关性取检索得分）
+    fn to_short_term(memory: &LongTermMemory, relevance: f32) -> ShortTermMemory {
+        ShortTermMemory {
+            id: memory.id.clone(),
+            summary: Self::build_summary(&memory.content),
+            content: memory.content.clone(),
+            memory_type: memory.category.clone(),
+            should_expand: false,
+            relevance,
+            confidence: 1.0,
+            source: MemorySource::LongTermRetrieval,
+            timestamp: Utc::now(),
+            committed: false,
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for LongTermRetriever {
+    fn name(&self) -> &'static str {
+        "LongTermRetriever"
+    }
+
+    fn requires_memory(&self) -> bool {
+        true
+    }
+
+    async fn process(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        info!("LongTermRetriever 开始处理");
+
+        let query = packet.user_input.trim().to_string();
+        if query.is_empty() {
+            info!("用户输入为空，跳过长期记忆检索");
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "retrieved": false,
+                "reason": "empty_user_input"
+            }));
+            return Ok(());
+        }
+
+        // 获取助手的长期记忆存储（写锁仅用于获取/创建存储实例）
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(
+                    &ctx.assistant_id,
+                    Some(ctx.embedding_model()),
+                )
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+
+        let threshold = ctx.relevance_threshold();
+        let results = long_term
+            .search_with_filter(&query, ctx.retrieval_count(), None, None)
+            .await
+            .map_err(|e| ProcessorError::MemoryError(e.to_string()))?;
+
+        let total_hits = results.len();
+
+        // 已在短期记忆中的条目不重复注入
+        let existing_ids: HashSet<String> = packet
+            .get_short_term_memory()
+            .iter()
+            .map(|m| m.id.clone())
+            .collect();
+        let (new_hits, existing_hits) = Self::partition_hits(&results, threshold, &existing_ids);
+
+        let mut hits = Vec::new();
+        for result in new_hits.iter().chain(existing_hits.iter()) {
+            hits.push(serde_json::json!({
+                "id": result.memory.id,
+                "relevance": result.relevance,
+            }));
+
+            if let Err(e) = long_term.record_access(&result.memory.id).await {
+                warn!("记录长期记忆访问失败 {}: {}", result.memory.id, e);
+            }
+        }
+
+        let injected: Vec<ShortTermMemory> = new_hits
+            .iter()
+            .map(|r| Self::to_short_term(&r.memory, r.relevance))
+            .collect();
+        let reinforced: Vec<String> = existing_hits
+            .iter()
+            .map(|r| r.memory.id.clone())
+            .collect();
+
+        // 已在短期记忆中的条目再次命中，加强相关性
+        packet.reinforce_short_term_memory(&reinforced, ctx.short_term_policy().reinforce_boost);
+
+        let injected_count = injected.len();
+        packet.add_short_term_memories(injected);
+
+        info!(
+            "长期记忆检索完成: 命中 {} 条, 超过阈值 {} 条, 新注入 {} 条, 加强 {} 条",
+            total_hits,
+            hits.len(),
+            injected_count,
+            reinforced.len()
+        );
+
+        packet.set_processor_state(self.name(), serde_json::json!({
+            "retrieved": true,
+            "query": query,
+            "total_hits": total_hits,
+            "threshold": threshold,
+            "hits": hits,
+            "injected_count": injected_count,
+            "reinforced_ids": reinforced
+        }));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retrieved(id: &str, relevance: f32) -> RetrievedMemory {
+        let mut memory = LongTermMemory::new(format!("记忆 {}", id), "fact".to_string(), 0.5);
+        memory.id = id.to_string();
+        RetrievedMemory { memory, relevance }
+    }
+
+    fn ids(hits: &[&RetrievedMemory]) -> Vec<String> {
+        hits.iter().map(|r| r.memory.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_partition_filters_below_threshold() {
+        let results = vec![retrieved("a", 0.9), retrieved("b", 0.5), retrieved("c", 0.7)];
+        let (new_hits, existing_hits) =
+            LongTermRetriever::partition_hits(&results, 0.7, &HashSet::new());
+        assert_eq!(ids(&new_hits), vec!["a", "c"]);
+        assert!(existing_hits.is_empty());
+    }
+
+    #[test]
+    fn test_partition_skips_existing_ids() {
+        let results = vec![retrieved("a", 0.9), retrieved("b", 0.8), retrieved("c", 0.1)];
+        let existing: HashSet<String> = ["b".to_string(), "c".to_string()].into();
+        let (new_hits, existing_hits) = LongTermRetriever::partition_hits(&results, 0.5, &existing);
+        assert_eq!(ids(&new_hits), vec!["a"]);
+        // 低于阈值的已有条目不加强
+        assert_eq!(ids(&existing_hits), vec!["b"]);
+    }
+
+    #[test]
+    fn test_build_summary_truncates_first_line() {
+        let long = "长".repeat(SUMMARY_MAX_CHARS + 5);
+        let summary = LongTermRetriever::build_summary(&format!("{}\n第二行", long));
+        assert_eq!(summary.chars().count(), SUMMARY_MAX_CHARS + 3);
+        assert!(summary.ends_with("..."));
+        assert_eq!(LongTermRetriever::build_summary("短内容\n第二行"), "短内容");
+    }
+}
@@ -0,0 +1,200 @@
+//! 记忆提交器
+//!
+//! 将当前对话切分出的短期记忆筛选后提交到助手的长期记忆库
+//! 位置：ContentChunker 之后执行
+
+use async_trait::async_trait;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::pipeline::{
+    processor::{Processor, ProcessorError},
+    context::ProcessorContext,
+    packet::ConversationPacket,
+};
+use crate::types::{LongTermMemory, MemorySource, ShortTermMemory};
+
+/// 提交到长期记忆的最低重要性
+const MIN_IMPORTANCE: f32 = 0.5;
+
+/// 内容最少字符数（过短的片段不值得长期保存）
+const MIN_CONTENT_CHARS: usize = 10;
+
+/// 记忆提交器
+///
+/// 只提交来源为 `MemorySource::CurrentConversation` 的短期记忆。
+/// 提交后在短期记忆上标记 `committed`，之后的轮次不再重复提交；
+/// 长期记忆ID由 助手/话题/短期记忆ID 确定性生成，同一条记忆在 Qdrant 中只有一条记录。
+pub struct MemoryCommitter;
+
+impl Default for MemoryCommitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryCommitter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// 根据记忆类型估算重要性
+    fn importance_for(memory_type: &str) -> f32 {
+        match memory_type {
+            "fact" | "preference" => 0.8,
+            "knowledge" | "code" => 0.7,
+            "event" | "task" => 0.6,
+            _ => 0.3,
+        }
+    }
+
+    /// 生成确定性的长期记忆ID（Qdrant 要求 UUID 格式）
+    pub(crate) fn long_term_id(assistant_id: &str, topic_id: &str, memory_id: &str) -> String {
+        let key = format!("{}/{}/{}", assistant_id, topic_id, memory_id);
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes()).to_string()
+    }
+
+    /// 判断短期记忆是否值得提交
+    fn is_worthwhile(memory: &ShortTermMemory) -> bool {
+        matches!(memory.source, MemorySource::CurrentConversation)
+            && memory.content.trim().chars().count() >= MIN_CONTENT_CHARS
+            && Self::importance_for(&memory.memory_type) >= MIN_IMPORTANCE
+    }
+
+    /// 转换为长期记忆（摘要作为首行，便于检索时还原）
+    pub(crate) fn to_long_term(memory: &ShortTermMemory, ctx: &ProcessorContext) -> LongTermMemory {
+        let content = format!("{}\n{}", memory.summary.trim(), memory.content.trim());
+        LongTermMemory::new(
+            content,
+            memory.memory_type.clone(),
+            Self::importance_for(&memory.memory_type),
+        )
+        .with_id(Self::long_term_id(&ctx.assistant_id, &ctx.topic_id, &memory.id))
+        .with_session(ctx.topic_id.clone())
+        .with_tag(memory.memory_type.clone())
+        .with_tag(format!("topic:{}", ctx.topic_id))
+    }
+
+    /// 筛选需要提交的短期记忆（未提交且值得长期保存）
+    fn select_candidates(memories: &[ShortTermMemory]) -> Vec<&ShortTermMemory> {
+        memories
+            .iter()
+            .filter(|m| !m.committed && Self::is_worthwhile(m))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Processor for MemoryCommitter {
+    fn name(&self) -> &'static str {
+        "MemoryCommitter"
+    }
+
+    fn requires_memory(&self) -> bool {
+        true
+    }
+
+    async fn process(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        info!("MemoryCommitter 开始处理");
+
+        let candidates = Self::select_candidates(packet.get_short_term_memory());
+
+        if candidates.is_empty() {
+            debug!("没有需要提交的短期记忆");
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "committed": 0,
+            }));
+            return Ok(());
+        }
+
+        let memories: Vec<LongTermMemory> = candidates
+            .iter()
+            .map(|m| Self::to_long_term(m, ctx))
+            .collect();
+        let new_ids: Vec<String> = candidates.iter().map(|m| m.id.clone()).collect();
+
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(
+                    &ctx.assistant_id,
+                    Some(ctx.embedding_model()),
+                )
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+
+        long_term
+            .store_batch(&memories)
+            .await
+            .map_err(|e| ProcessorError::MemoryError(e.to_string()))?;
+
+        info!("已提交 {} 条记忆到长期记忆库", memories.len());
+
+        let long_term_ids: Vec<String> = memories.iter().map(|m| m.id.clone()).collect();
+        for memory in packet
+            .short_term_memory
+            .iter_mut()
+            .filter(|m| new_ids.contains(&m.id))
+        {
+            memory.committed = true;
+        }
+
+        packet.set_processor_state(self.name(), serde_json::json!({
+            "committed": memories.len(),
+            "new_ids": new_ids,
+            "long_term_ids": long_term_ids,
+        }));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn memory(id: &str, memory_type: &str, source: MemorySource, committed: bool) -> ShortTermMemory {
+        ShortTermMemory {
+            summary: "摘要".to_string(),
+            content: "足够长的一段记忆内容，值得长期保存".to_string(),
+            memory_type: memory_type.to_string(),
+            source,
+            committed,
+            ..ShortTermMemory::for_test(id)
+        }
+    }
+
+    #[test]
+    fn test_long_term_id_deterministic() {
+        let id = MemoryCommitter::long_term_id("ast_1", "topic_1", "chunk_1");
+        assert_eq!(id, MemoryCommitter::long_term_id("ast_1", "topic_1", "chunk_1"));
+        assert!(Uuid::parse_str(&id).is_ok());
+        assert_ne!(id, MemoryCommitter::long_term_id("ast_1", "topic_2", "chunk_1"));
+        assert_ne!(id, MemoryCommitter::long_term_id("ast_2", "topic_1", "chunk_1"));
+    }
+
+    #[test]
+    fn test_select_candidates() {
+        let mut short = memory("short", "fact", MemorySource::CurrentConversation, false);
+        short.content = "太短".to_string();
+        let memories = vec![
+            memory("new", "fact", MemorySource::CurrentConversation, false),
+            memory("done", "fact", MemorySource::CurrentConversation, true),
+            memory("retrieved", "fact", MemorySource::LongTermRetrieval, false),
+            memory("tool", "tool_result", MemorySource::ToolResult, false),
+            memory("minor", "other", MemorySource::CurrentConversation, false),
+            short,
+        ];
+        let ids: Vec<&str> = MemoryCommitter::select_candidates(&memories)
+            .iter()
+            .map(|m| m.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["new"]);
+    }
+}
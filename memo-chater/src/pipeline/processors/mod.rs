@@ -9,6 +9,11 @@ mod short_term_assembler;
 mod context_cleaner;
 mod short_term_expander;
 pub mod short_term_vectorizer;
+mod short_term_evictor;
+mod long_term_retriever;
+mod memory_committer;
+mod thinking_filter;
+mod remote_processor;
 
 pub use history_simplifier::HistorySimplifier;
 pub use subconscious_processor::SubconsciousProcessor;
@@ -17,6 +22,11 @@ pub use short_term_assembler::ShortTermAssembler;
 pub use context_cleaner::ContextCleaner;
 pub use short_term_expander::ShortTermExpander;
 pub use short_term_vectorizer::ShortTermVectorizer;
+pub use short_term_evictor::ShortTermEvictor;
+pub use long_term_retriever::LongTermRetriever;
+pub use memory_committer::MemoryCommitter;
+pub use thinking_filter::ThinkingFilter;
+pub use remote_processor::RemoteProcessor;
 
 use std::sync::Arc;
 use super::processor::Processor;
@@ -31,5 +41,9 @@ pub fn create_all_processors() -> Vec<Arc<dyn Processor>> {
 Arc::new(ContextCleaner::new()),
         Arc::new(ShortTermExpander::new()),
         Arc::new(ShortTermVectorizer::new()),
+        Arc::new(ShortTermEvictor::new()),
+        Arc::new(LongTermRetriever::new()),
+        Arc::new(MemoryCommitter::new()),
+        Arc::new(ThinkingFilter::new()),
     ]
 }
\ No newline at end of file
@@ -0,0 +1,488 @@
+//! 外部处理器
+//!
+//! 通过 HTTP 或 stdio 协议调用进程外的处理器，无需修改 create_all_processors 重新编译。
+//! 由调度器根据条目的 `remote` 配置按需创建，不进入处理器注册表。
+//! stdio 方式只能运行全局配置 `remote_processors.command_dir` 目录下的程序，
+//! HTTP 方式只能访问 `remote_processors.allowed_url_prefixes` 中的地址。
+//!
+//! 协议（请求与响应均为 JSON）：
+//! - 请求：`{"protocol_version": 1, "processor": 条目名, "packet": 数据包, "context": 上下文, "params": 参数}`
+//! - 响应：`{"packet": 修改后的数据包}` 或 `{"error": "错误信息"}`
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info};
+
+use crate::assistant::TopicType;
+use crate::config::RemoteProcessorsConfig;
+use crate::pipeline::{
+    processor::{Processor, ProcessorError},
+    config::{ProcessorParams, RemoteSpec},
+    context::ProcessorContext,
+    packet::ConversationPacket,
+};
+
+/// 协议版本
+const PROTOCOL_VERSION: u32 = 1;
+
+/// 共享的 HTTP 客户端（超时由调度器按条目的 timeout_secs 或默认超时控制）
+///
+/// 不跟随重定向，否则允许的地址可以把数据包转发到任意地址
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("创建 HTTP 客户端失败")
+});
+
+/// 发送给外部处理器的上下文（只包含可序列化的配置信息）
+#[derive(Debug, Serialize)]
+struct RemoteContext<'a> {
+    assistant_id: &'a str,
+    topic_id: &'a str,
+    topic_type: &'a TopicType,
+    memory_enabled: bool,
+    main_model: &'a str,
+    processor_model: &'a str,
+    embedding_model: &'a str,
+    user_name: &'a str,
+    assistant_name: &'a str,
+}
+
+/// 请求体
+#[derive(Debug, Serialize)]
+struct RemoteRequest<'a> {
+    protocol_version: u32,
+    processor: &'a str,
+    packet: &'a ConversationPacket,
+    context: RemoteContext<'a>,
+    params: &'a ProcessorParams,
+}
+
+/// 响应体
+#[derive(Debug, Deserialize)]
+struct RemoteResponse {
+    #[serde(default)]
+    packet: Option<ConversationPacket>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// 外部处理器
+///
+/// 每个配置了 `remote` 的条目对应一个实例
+pub struct RemoteProcessor {
+    /// 条目名称（发送给外部处理器，便于一个程序实现多个处理器）
+    entry_name: String,
+    /// 外部处理器配置
+    spec: RemoteSpec,
+    /// 全局的外部处理器限制（允许运行的程序目录和访问的地址）
+    policy: RemoteProcessorsConfig,
+}
+
+impl RemoteProcessor {
+    pub fn new(entry_name: impl Into<String>, spec: RemoteSpec, policy: RemoteProcessorsConfig) -> Self {
+        Self {
+            entry_name: entry_name.into(),
+            spec,
+            policy,
+        }
+    }
+
+    /// 检查配置是否有效（url 与 command 必须且只能设置一个，且分别在允许的地址和目录中）
+    pub fn validate_spec(spec: &RemoteSpec, policy: &RemoteProcessorsConfig) -> Result<(), ProcessorError> {
+        match (&spec.url, &spec.command) {
+            (Some(url), None) => Self::check_url(url, policy),
+            (None, Some(command)) => Self::resolve_command(command, policy).map(|_| ()),
+            (Some(_), Some(_)) => Err(ProcessorError::Config("remote 不能同时设置 url 和 command".to_string())),
+            (None, None) => Err(ProcessorError::Config("remote 需要设置 url 或 command".to_string())),
+        }
+    }
+
+    /// 检查 url 是否以允许的地址前缀开头
+    ///
+    /// 前缀不以 `/` 结尾时，其后必须是路径、查询或片段的分隔符，
+    /// 避免 `http://10.0.0.1` 匹配到 `http://10.0.0.1.evil.com` 或 `http://10.0.0.1@evil.com`
+    fn check_url(url: &str, policy: &RemoteProcessorsConfig) -> Result<(), ProcessorError> {
+        if policy.allowed_url_prefixes.is_empty() {
+            return Err(ProcessorError::Config(
+                "未配置 remote_processors.allowed_url_prefixes，不允许使用 HTTP 外部处理器".to_string(),
+            ));
+        }
+
+        let allowed = policy.allowed_url_prefixes.iter().any(|prefix| {
+            url.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#'])
+            })
+        });
+        if !allowed {
+            return Err(ProcessorError::Config(format!(
+                "url 不在 allowed_url_prefixes 允许的地址中: {}",
+                url
+            )));
+        }
+        Ok(())
+    }
+
+    /// 将 command 解析为允许目录中的程序路径
+    ///
+    /// command 只能是文件名（不能包含路径），且必须是 command_dir 下已存在的文件
+    fn resolve_command(command: &str, policy: &RemoteProcessorsConfig) -> Result<PathBuf, ProcessorError> {
+        let Some(dir) = &policy.command_dir else {
+            return Err(ProcessorError::Config(
+                "未配置 remote_processors.command_dir，不允许使用 stdio 外部处理器".to_string(),
+            ));
+        };
+
+        let mut components = Path::new(command).components();
+        let is_file_name = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
+        if !is_file_name {
+            return Err(ProcessorError::Config(format!(
+                "command 只能是 command_dir 下的程序文件名: {}",
+                command
+            )));
+        }
+
+        let path = dir.join(command);
+        if !path.is_file() {
+            return Err(ProcessorError::Config(format!(
+                "command_dir 中不存在外部处理器程序: {}",
+                command
+            )));
+        }
+        Ok(path)
+    }
+
+    /// 通过 HTTP POST 调用
+    async fn call_http(&self, url: &str, body: Vec<u8>) -> Result<Vec<u8>, ProcessorError> {
+        let response = HTTP_CLIENT
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| ProcessorError::Service(format!("请求外部处理器失败: {}", e)))?;
+
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ProcessorError::Service(format!("读取外部处理器响应失败: {}", e)))?;
+
+        if !status.is_success() {
+            let text: String = String::from_utf8_lossy(&bytes).chars().take(500).collect();
+            return Err(ProcessorError::Service(format!("外部处理器返回 {}: {}", status, text)));
+        }
+
+        Ok(bytes.to_vec())
+    }
+
+    /// 以子进程方式调用：请求写入 stdin，从 stdout 读取响应
+    ///
+    /// stdin 在单独的任务中写入，外部处理器可以边读边写，不会因管道写满而互相等待
+    async fn call_stdio(&self, command: &str, body: Vec<u8>) -> Result<Vec<u8>, ProcessorError> {
+        let program = Self::resolve_command(command, &self.policy)?;
+        let mut child = tokio::process::Command::new(&program)
+            .args(&self.spec.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // 调度器超时会丢弃本 future，此时结束子进程
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| ProcessorError::Service(format!("启动外部处理器 {} 失败: {}", command, e)))?;
+
+        let stdin = child.stdin.take();
+        let writer = tokio::spawn(async move {
+            match stdin {
+                // 写完后 stdin 随之关闭，外部处理器据此判断输入结束
+                Some(mut stdin) => stdin.write_all(&body).await,
+                None => Ok(()),
+            }
+        });
+
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| ProcessorError::Service(format!("等待外部处理器退出失败: {}", e)))?;
+
+        if !output.status.success() {
+            let stderr: String = String::from_utf8_lossy(&output.stderr).chars().take(500).collect();
+            return Err(ProcessorError::Service(format!("外部处理器退出码 {}: {}", output.status, stderr)));
+        }
+
+        match writer.await {
+            Ok(Ok(())) => Ok(output.stdout),
+            Ok(Err(e)) => Err(ProcessorError::Service(format!("写入外部处理器 stdin 失败: {}", e))),
+            Err(e) => Err(ProcessorError::Internal(format!("写入外部处理器 stdin 的任务异常: {}", e))),
+        }
+    }
+
+    /// 校验外部处理器返回的数据包：不允许改变数据包归属和轮次
+    fn validate_packet(
+        original: &ConversationPacket,
+        patched: &ConversationPacket,
+    ) -> Result<(), ProcessorError> {
+        if patched.assistant_id != original.assistant_id || patched.topic_id != original.topic_id {
+            return Err(ProcessorError::Internal("外部处理器修改了数据包的 assistant_id/topic_id".to_string()));
+        }
+        if patched.turn_index != original.turn_index {
+            return Err(ProcessorError::Internal("外部处理器修改了数据包的 turn_index".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Processor for RemoteProcessor {
+    fn name(&self) -> &'static str {
+        "RemoteProcessor"
+    }
+
+    fn requires_memory(&self) -> bool {
+        self.spec.requires_memory
+    }
+
+    async fn process(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        self.process_with_params(packet, ctx, &ProcessorParams::default()).await
+    }
+
+    /// 参数原样转发给外部处理器，由其自行校验
+    fn validate_params(&self, _params: &ProcessorParams) -> Result<(), ProcessorError> {
+        Self::validate_spec(&self.spec, &self.policy)
+    }
+
+    async fn process_with_params(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        params: &ProcessorParams,
+    ) -> Result<(), ProcessorError> {
+        Self::validate_spec(&self.spec, &self.policy)?;
+        info!("RemoteProcessor 调用外部处理器: {}", self.entry_name);
+
+        let request = RemoteRequest {
+            protocol_version: PROTOCOL_VERSION,
+            processor: &self.entry_name,
+            packet,
+            context: RemoteContext {
+                assistant_id: &ctx.assistant_id,
+                topic_id: &ctx.topic_id,
+                topic_type: ctx.topic_type(),
+                memory_enabled: ctx.is_memory_enabled(),
+                main_model: ctx.main_model(),
+                processor_model: ctx.processor_model(),
+                embedding_model: ctx.embedding_model(),
+                user_name: ctx.user_name(),
+                assistant_name: ctx.assistant_name(),
+            },
+            params,
+        };
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| ProcessorError::Internal(format!("序列化请求失败: {}", e)))?;
+
+        let raw = match (&self.spec.url, &self.spec.command) {
+            (Some(url), _) => self.call_http(url, body).await?,
+            (None, Some(command)) => self.call_stdio(command, body).await?,
+            (None, None) => unreachable!("validate_spec 已检查"),
+        };
+
+        let response: RemoteResponse = serde_json::from_slice(&raw).map_err(|e| {
+            let preview: String = String::from_utf8_lossy(&raw).chars().take(300).collect();
+            ProcessorError::Internal(format!("解析外部处理器响应失败: {}。响应预览: {}", e, preview))
+        })?;
+
+        if let Some(error) = response.error {
+            return Err(ProcessorError::Service(format!("外部处理器报告错误: {}", error)));
+        }
+        let patched = response
+            .packet
+            .ok_or_else(|| ProcessorError::Internal("外部处理器响应缺少 packet".to_string()))?;
+
+        Self::validate_packet(packet, &patched)?;
+        *packet = patched;
+
+        debug!("外部处理器 {} 执行完成", self.entry_name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assistant::AssistantConfig;
+    use crate::types::ChatMessage;
+
+    fn packet() -> ConversationPacket {
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.user_input = "before".to_string();
+        packet
+    }
+
+    fn policy(command_dir: Option<&Path>) -> RemoteProcessorsConfig {
+        RemoteProcessorsConfig {
+            command_dir: command_dir.map(Path::to_path_buf),
+            ..RemoteProcessorsConfig::default()
+        }
+    }
+
+    fn stdio_spec(command: &str, args: &[&str]) -> RemoteSpec {
+        RemoteSpec {
+            url: None,
+            command: Some(command.to_string()),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            requires_memory: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_round_trip() {
+        use axum::{routing::post, Json, Router};
+
+        async fn handle(Json(mut req): Json<serde_json::Value>) -> Json<serde_json::Value> {
+            let processor = req["processor"].clone();
+            let mut packet = req["packet"].take();
+            packet["current_states"]["Echo"] = serde_json::json!({ "processor": processor });
+            Json(serde_json::json!({ "packet": packet }))
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/process", post(handle))).await.unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let spec = RemoteSpec {
+            url: Some(format!("http://{}/process", addr)),
+            command: None,
+            args: Vec::new(),
+            requires_memory: false,
+        };
+        let policy = RemoteProcessorsConfig {
+            allowed_url_prefixes: vec![format!("http://{}/", addr)],
+            ..RemoteProcessorsConfig::default()
+        };
+        let processor = RemoteProcessor::new("MyExperiment", spec, policy);
+
+        let mut packet = packet();
+        processor.process(&mut packet, &ctx).await.unwrap();
+        assert_eq!(packet.current_states["Echo"]["processor"], "MyExperiment");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_round_trip_with_large_payload() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // 先写出大量空白再读取 stdin：请求和响应都超过管道缓冲区，顺序读写会互相等待
+        let script = dir.path().join("patch.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nhead -c 200000 /dev/zero | tr '\\0' ' '\nsed \"s/\\\"user_input\\\":\\\"before\\\"/\\\"user_input\\\":\\\"$1\\\"/\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let processor = RemoteProcessor::new("Patch", stdio_spec("patch.sh", &["after"]), policy(Some(dir.path())));
+
+        let mut packet = packet();
+        packet.messages.push(ChatMessage::user("x".repeat(200_000)));
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            processor.process(&mut packet, &ctx),
+        )
+        .await
+        .expect("外部处理器读写互相等待");
+
+        result.unwrap();
+        assert_eq!(packet.user_input, "after");
+        assert_eq!(packet.messages[0].content.len(), 200_000);
+    }
+
+    #[test]
+    fn test_validate_spec_restricts_command() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("vetted.sh"), "").unwrap();
+
+        // 未配置目录时不允许 stdio
+        assert!(RemoteProcessor::validate_spec(&stdio_spec("vetted.sh", &[]), &policy(None)).is_err());
+
+        let policy = policy(Some(dir.path()));
+        assert!(RemoteProcessor::validate_spec(&stdio_spec("vetted.sh", &[]), &policy).is_ok());
+        for command in ["missing.sh", "/bin/sh", "../vetted.sh", "sub/vetted.sh", ".", ""] {
+            assert!(
+                RemoteProcessor::validate_spec(&stdio_spec(command, &[]), &policy).is_err(),
+                "应拒绝 {:?}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_spec_restricts_url() {
+        let spec = |url: &str| RemoteSpec {
+            url: Some(url.to_string()),
+            command: None,
+            args: Vec::new(),
+            requires_memory: false,
+        };
+
+        // 未配置地址前缀时不允许 HTTP
+        assert!(RemoteProcessor::validate_spec(&spec("http://127.0.0.1:9000/process"), &policy(None)).is_err());
+
+        let policy = RemoteProcessorsConfig {
+            allowed_url_prefixes: vec!["http://127.0.0.1:9000/".to_string(), "https://hooks.example.com".to_string()],
+            ..RemoteProcessorsConfig::default()
+        };
+        for url in ["http://127.0.0.1:9000/process", "https://hooks.example.com", "https://hooks.example.com/a?b=1"] {
+            assert!(RemoteProcessor::validate_spec(&spec(url), &policy).is_ok(), "应允许 {:?}", url);
+        }
+        for url in [
+            "http://127.0.0.1:9001/process",
+            "http://169.254.169.254/latest/meta-data",
+            "https://hooks.example.com.evil.com/",
+            "https://hooks.example.com@evil.com/",
+            "https://hooks.example.com:8443/",
+        ] {
+            assert!(RemoteProcessor::validate_spec(&spec(url), &policy).is_err(), "应拒绝 {:?}", url);
+        }
+    }
+
+    #[test]
+    fn test_validate_packet_rejects_ownership_and_turn_change() {
+        let original = packet();
+        assert!(RemoteProcessor::validate_packet(&original, &original.clone()).is_ok());
+
+        let mut moved = original.clone();
+        moved.topic_id = "other_topic".to_string();
+        assert!(RemoteProcessor::validate_packet(&original, &moved).is_err());
+
+        let mut moved = original.clone();
+        moved.assistant_id = "other_assistant".to_string();
+        assert!(RemoteProcessor::validate_packet(&original, &moved).is_err());
+
+        let mut advanced = original.clone();
+        advanced.turn_index += 1;
+        assert!(RemoteProcessor::validate_packet(&original, &advanced).is_err());
+    }
+}
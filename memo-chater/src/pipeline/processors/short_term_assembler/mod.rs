@@ -1,21 +1,35 @@
 //! 短期记忆组装器
 //!
 //! 将短期记忆注入到对话上下文中
+//!
+//! 记忆较多时，用本轮用户输入的向量与话题向量文件（short_term_vectors.json）
+//! 计算加权余弦相似度，只注入最相关的前 top_k 条（可叠加时间衰减加成）
 
 use async_trait::async_trait;
-use serde::Deserialize;
-use std::path::PathBuf;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tracing::{info, warn, debug};
 
 use crate::pipeline::{
     processor::{Processor, ProcessorError},
+    budget::{BudgetFit, BudgetItem, BudgetSection, ContextBudget},
+    config::ProcessorParams,
     context::ProcessorContext,
     packet::ConversationPacket,
 };
-use crate::types::ChatMessage;
+use crate::pipeline::processors::short_term_vectorizer::{ShortTermVectorizer, VectorizedMemory};
+use crate::types::{ChatMessage, MessageMeta, ShortTermMemory};
+
+/// 记忆注入消息的注入标识
+const INJECTION_ID: &str = "short_term_memory";
+
+/// 内置默认配置（编译进二进制，条目 params 未覆盖的字段使用这里的值）
+const DEFAULT_CONFIG: &str = include_str!("config.toml");
 
-/// 组装器配置
-#[derive(Debug, Clone, Deserialize)]
+/// 组装器配置（可通过流水线条目的 params 覆盖）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct AssemblerConfig {
     /// 使用的模型（留空则使用助手配置的 processor_model）
     #[serde(default)]
@@ -24,6 +38,12 @@ struct AssemblerConfig {
     user_message_template: String,
     /// 助手确认消息
     assistant_confirm_message: String,
+    /// 最多注入的记忆条数，超过时按相关度选择（0 表示全部注入）
+    top_k: usize,
+    /// 时间衰减加成的权重（0 表示不加成）
+    recency_weight: f32,
+    /// 时间衰减加成的半衰期（小时）
+    recency_half_life_hours: f32,
 }
 
 impl Default for AssemblerConfig {
@@ -36,6 +56,9 @@ impl Default for AssemblerConfig {
 ---短期记忆结束---
 "#.to_string(),
             assistant_confirm_message: "已成功回忆".to_string(),
+            top_k: 20,
+            recency_weight: 0.1,
+            recency_half_life_hours: 24.0,
         }
     }
 }
@@ -44,45 +67,171 @@ impl Default for AssemblerConfig {
 /// 
 /// 负责将短期记忆注入到对话上下文中
 pub struct ShortTermAssembler {
-    config_path: PathBuf,
+    /// 默认配置
+    defaults: AssemblerConfig,
+}
+
+impl Default for ShortTermAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ShortTermAssembler {
     pub fn new() -> Self {
-        // 配置文件路径：与本模块同目录
-        let config_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("src/pipeline/processors/short_term_assembler/config.toml");
-        
-        Self { config_path }
-    }
-
-    /// 加载配置
-    fn load_config(&self) -> Result<AssemblerConfig, ProcessorError> {
-        if self.config_path.exists() {
-            let content = std::fs::read_to_string(&self.config_path)
-                .map_err(|e| ProcessorError::Config(format!("读取配置文件失败: {}", e)))?;
-            
-            toml::from_str(&content)
-                .map_err(|e| ProcessorError::Config(format!("解析配置文件失败: {}", e)))
-        } else {
-            warn!("ShortTermAssembler 配置文件不存在，使用默认配置: {:?}", self.config_path);
-            Ok(AssemblerConfig::default())
+        let defaults = toml::from_str(DEFAULT_CONFIG).unwrap_or_else(|e| {
+            warn!("ShortTermAssembler 内置配置解析失败，使用代码默认值: {}", e);
+            AssemblerConfig::default()
+        });
+
+        Self { defaults }
+    }
+
+    /// 在默认配置上叠加条目参数
+    fn load_config(&self, params: &ProcessorParams) -> Result<AssemblerConfig, ProcessorError> {
+        params.merge_into(&self.defaults)
+    }
+
+    /// 时间衰减加成：刚产生的记忆为 recency_weight，每过一个半衰期减半
+    fn recency_boost(config: &AssemblerConfig, memory: &ShortTermMemory) -> f32 {
+        if config.recency_weight <= 0.0 || config.recency_half_life_hours <= 0.0 {
+            return 0.0;
         }
+        let age_hours = (Utc::now() - memory.timestamp).num_seconds().max(0) as f32 / 3600.0;
+        config.recency_weight * 0.5f32.powf(age_hours / config.recency_half_life_hours)
     }
 
-    /// 格式化短期记忆列表
-    fn format_memories(&self, packet: &ConversationPacket) -> String {
+    /// 选择要注入的短期记忆
+    ///
+    /// 记忆不超过 top_k 条时全部注入（按记忆自身的相关性排序）；否则按与用户输入的
+    /// 加权余弦相似度（加时间衰减加成）取前 top_k 条。尚未向量化的记忆（本轮新检索到的）优先注入。
+    /// 向量文件或 embedding 不可用时退回按记忆自身的相关性取前 top_k 条
+    async fn select_memories<'a>(
+        &self,
+        packet: &'a ConversationPacket,
+        ctx: &ProcessorContext,
+        config: &AssemblerConfig,
+    ) -> (Vec<&'a ShortTermMemory>, serde_json::Value) {
         let memories = packet.get_short_term_memory_sorted();
-        
-        if memories.is_empty() {
-            return "（暂无短期记忆）".to_string();
+        let candidates = memories.len();
+        if config.top_k == 0 || candidates <= config.top_k {
+            return (memories, serde_json::json!({ "mode": "stored_relevance", "candidates": candidates }));
         }
 
-        memories
+        let fallback = |mut memories: Vec<&'a ShortTermMemory>, reason: &str| {
+            memories.truncate(config.top_k);
+            let ranking = serde_json::json!({
+                "mode": "stored_relevance",
+                "candidates": candidates,
+                "top_k": config.top_k,
+                "fallback_reason": reason
+            });
+            (memories, ranking)
+        };
+
+        if packet.user_input.trim().is_empty() {
+            return fallback(memories, "empty_user_input");
+        }
+
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        let file = match ShortTermVectorizer::load_vector_file(&path).await {
+            Ok(Some(file)) if !file.vectors.is_empty() => file,
+            _ => {
+                info!("话题向量文件不可用，按记忆相关性注入前 {} 条短期记忆", config.top_k);
+                return fallback(memories, "no_vector_file");
+            }
+        };
+
+        // 使用向量文件中记录的模型生成 embedding，保证向量空间一致
+        let query = match ctx
+            .ai_client
+            .embedding_with_model(&packet.user_input, Some(&file.metadata.embedding_model))
+            .await
+        {
+            Ok(query) => query,
+            Err(e) => {
+                warn!("生成用户输入 embedding 失败，按记忆相关性注入: {}", e);
+                return fallback(memories, "embedding_failed");
+            }
+        };
+
+        let vectors: HashMap<&str, &VectorizedMemory> = file.vectors.iter().map(|v| (v.id.as_str(), v)).collect();
+        Self::rank_by_similarity(memories, &vectors, &query, config)
+    }
+
+    /// 按与用户输入的相似度选出前 top_k 条
+    ///
+    /// 未向量化的记忆保持原有顺序排在最前，其余按得分降序；未向量化的记忆同样计入 top_k
+    fn rank_by_similarity<'a>(
+        memories: Vec<&'a ShortTermMemory>,
+        vectors: &HashMap<&str, &VectorizedMemory>,
+        query: &[f32],
+        config: &AssemblerConfig,
+    ) -> (Vec<&'a ShortTermMemory>, serde_json::Value) {
+        let candidates = memories.len();
+        let (mut selected, rest): (Vec<_>, Vec<_>) = memories
+            .into_iter()
+            .partition(|m| !vectors.contains_key(m.id.as_str()));
+        let unvectorized = selected.len();
+        selected.truncate(config.top_k);
+
+        let mut scored: Vec<(&ShortTermMemory, f32)> = rest
+            .into_iter()
+            .map(|m| (m, vectors[m.id.as_str()].score(query) + Self::recency_boost(config, m)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(config.top_k - selected.len());
+
+        let scores: Vec<serde_json::Value> = scored
+            .iter()
+            .map(|(m, score)| serde_json::json!({ "id": m.id, "score": score }))
+            .collect();
+        selected.extend(scored.into_iter().map(|(m, _)| m));
+
+        debug!("按相关度从 {} 条中选出 {} 条短期记忆", candidates, selected.len());
+        let ranking = serde_json::json!({
+            "mode": "similarity",
+            "candidates": candidates,
+            "top_k": config.top_k,
+            "unvectorized": unvectorized,
+            "scores": scores
+        });
+        (selected, ranking)
+    }
+
+    /// 格式化短期记忆列表
+    ///
+    /// 超出注入记忆份额时按价值从低到高丢弃（以选择顺序作为价值，越靠前越高）
+    fn format_memories(memories: &[&ShortTermMemory], ctx: &ProcessorContext) -> (String, BudgetFit) {
+        let lines: Vec<String> = memories
             .iter()
             .map(|m| format!("[{}]{}", m.memory_type, m.summary))
+            .collect();
+
+        let count = memories.len();
+        let items: Vec<BudgetItem> = memories
+            .iter()
+            .zip(&lines)
+            .enumerate()
+            .map(|(rank, (m, line))| BudgetItem {
+                id: m.id.clone(),
+                tokens: ctx.count_tokens(line) + 1,
+                value: (count - rank) as f64,
+            })
+            .collect();
+        let fit = ContextBudget::for_context(ctx).fit(BudgetSection::Memories, &items);
+
+        if fit.kept.is_empty() {
+            return ("（暂无短期记忆）".to_string(), fit);
+        }
+
+        let text = fit
+            .kept
+            .iter()
+            .map(|&i| lines[i].as_str())
             .collect::<Vec<_>>()
-            .join("\n")
+            .join("\n");
+        (text, fit)
     }
 }
 
@@ -99,12 +248,25 @@ impl Processor for ShortTermAssembler {
     async fn process(
         &self,
         packet: &mut ConversationPacket,
-        _ctx: &ProcessorContext,
+        ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        self.process_with_params(packet, ctx, &ProcessorParams::default()).await
+    }
+
+    fn validate_params(&self, params: &ProcessorParams) -> Result<(), ProcessorError> {
+        self.load_config(params).map(|_| ())
+    }
+
+    async fn process_with_params(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+        params: &ProcessorParams,
     ) -> Result<(), ProcessorError> {
         info!("ShortTermAssembler 开始处理");
 
         // 加载配置
-        let config = self.load_config()?;
+        let config = self.load_config(params)?;
         debug!("配置加载成功");
 
         // 检查是否有短期记忆
@@ -118,38 +280,62 @@ impl Processor for ShortTermAssembler {
             return Ok(());
         }
 
-        info!("准备注入 {} 条短期记忆", memory_count);
-
-        // 1. 清除系统消息以外的上下文
-        let system_msg = packet.messages.iter()
-            .find(|m| m.role == "system")
-            .cloned();
-        
-        packet.messages.clear();
-        
-        if let Some(sys) = system_msg {
-            packet.messages.push(sys);
+        info!("共有 {} 条短期记忆", memory_count);
+
+        // 1. 选择并格式化短期记忆（先于修改上下文，选择结果借用数据包）
+        let (memories_text, budget, ranking) = {
+            let (selected, ranking) = self.select_memories(packet, ctx, &config).await;
+            let (text, budget) = Self::format_memories(&selected, ctx);
+            (text, budget, ranking)
+        };
+        debug!("格式化后的记忆:\n{}", memories_text);
+        if !budget.dropped.is_empty() {
+            info!(
+                "短期记忆超出预算（份额 {} tokens），丢弃 {} 条",
+                budget.limit,
+                budget.dropped.len()
+            );
         }
 
-        debug!("已清除非系统消息的上下文");
+        // 2. 替换之前注入的记忆块（历史摘要和最近轮次由 HistorySimplifier 按预算保留，不做改动）
+        packet.remove_injection(INJECTION_ID);
 
-        // 2. 格式化短期记忆
-        let memories_text = self.format_memories(packet);
-        debug!("格式化后的记忆:\n{}", memories_text);
+        // 系统提示词由助手配置决定，不做裁剪，超出份额时只提示
+        let mut system_prompt_tokens = 0;
+        if let Some(sys) = packet.messages.iter().find(|m| m.role == "system") {
+            system_prompt_tokens = ctx.count_tokens(&sys.content);
+            let limit = ContextBudget::for_context(ctx).limit(BudgetSection::SystemPrompt);
+            if system_prompt_tokens > limit {
+                warn!("系统提示词 {} tokens 超出份额 {} tokens", system_prompt_tokens, limit);
+            }
+        }
 
         // 3. 构建用户消息
         let user_content = config.user_message_template
             .replace("{user_name}", &packet.user_name)
             .replace("{memories}", &memories_text);
+        let injection = ChatMessage::user(&user_content).with_meta(MessageMeta::injected(self.name(), INJECTION_ID));
 
-        // 4. 添加用户消息（记忆注入）
-        packet.messages.push(ChatMessage::user(&user_content));
-
-        // 5. 加回用户原始输入
-        if !packet.user_input.is_empty() {
-            packet.messages.push(ChatMessage::user(&packet.user_input));
-            debug!("已加回用户原始输入: {}", packet.user_input);
-        }
+        // 4. 插入到本轮用户消息之前；上下文中没有本轮用户消息时追加记忆并加回用户原始输入
+        let user_pos = packet.messages
+            .iter()
+            .rposition(|m| m.role == "user" && !m.is_ephemeral());
+        let insert_pos = match user_pos {
+            Some(pos) => {
+                packet.messages.insert(pos, injection);
+                pos
+            }
+            None => {
+                let pos = packet.messages.len();
+                packet.messages.push(injection);
+                if !packet.user_input.is_empty() {
+                    packet.messages.push(ChatMessage::user(&packet.user_input));
+                    debug!("已加回用户原始输入: {}", packet.user_input);
+                }
+                pos
+            }
+        };
+        debug!("已在位置 {} 插入短期记忆", insert_pos);
 
         // // ===== 输出完整上下文结构（调试用）=====
         // info!("========== ShortTermAssembler 组装后完整上下文 ==========");
@@ -164,9 +350,141 @@ impl Processor for ShortTermAssembler {
         packet.set_processor_state(self.name(), serde_json::json!({
             "assembled": true,
             "memory_count": memory_count,
-            "context_cleared": true
+            "injected_count": budget.kept.len(),
+            "insert_position": insert_pos,
+            "system_prompt_tokens": system_prompt_tokens,
+            "ranking": ranking,
+            "budget": budget
         }));
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assistant::{AssistantConfig, TopicType};
+
+    fn memory(id: &str, relevance: f32, age_hours: i64) -> ShortTermMemory {
+        ShortTermMemory {
+            relevance,
+            timestamp: Utc::now() - chrono::Duration::hours(age_hours),
+            ..ShortTermMemory::for_test(id)
+        }
+    }
+
+    fn config(top_k: usize, recency_weight: f32) -> AssemblerConfig {
+        AssemblerConfig {
+            top_k,
+            recency_weight,
+            ..AssemblerConfig::default()
+        }
+    }
+
+    fn ids(memories: &[&ShortTermMemory]) -> Vec<String> {
+        memories.iter().map(|m| m.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_recency_boost() {
+        let config = config(20, 0.1);
+        assert!((ShortTermAssembler::recency_boost(&config, &memory("new", 1.0, 0)) - 0.1).abs() < 1e-3);
+        // 一个半衰期后减半
+        let aged = memory("aged", 1.0, config.recency_half_life_hours as i64);
+        assert!((ShortTermAssembler::recency_boost(&config, &aged) - 0.05).abs() < 1e-3);
+
+        let disabled = AssemblerConfig { recency_weight: 0.0, ..config.clone() };
+        assert_eq!(ShortTermAssembler::recency_boost(&disabled, &memory("new", 1.0, 0)), 0.0);
+        let disabled = AssemblerConfig { recency_half_life_hours: 0.0, ..config };
+        assert_eq!(ShortTermAssembler::recency_boost(&disabled, &memory("new", 1.0, 0)), 0.0);
+    }
+
+    #[test]
+    fn test_rank_by_similarity() {
+        let memories = [memory("far", 0.9, 0), memory("near", 0.1, 0), memory("mid", 0.5, 0), memory("new", 0.2, 0)];
+        let vectors = [
+            VectorizedMemory::for_test("far", vec![0.0, 1.0]),
+            VectorizedMemory::for_test("near", vec![1.0, 0.0]),
+            VectorizedMemory::for_test("mid", vec![1.0, 1.0]),
+        ];
+        let vectors: HashMap<&str, &VectorizedMemory> = vectors.iter().map(|v| (v.id.as_str(), v)).collect();
+
+        let (selected, ranking) = ShortTermAssembler::rank_by_similarity(
+            memories.iter().collect(),
+            &vectors,
+            &[1.0, 0.0],
+            &config(3, 0.0),
+        );
+        // 未向量化的记忆在前，其余按相似度
+        assert_eq!(ids(&selected), vec!["new", "near", "mid"]);
+        assert_eq!(ranking["unvectorized"], 1);
+    }
+
+    #[test]
+    fn test_unvectorized_memories_respect_top_k() {
+        let memories: Vec<ShortTermMemory> = (0..5).map(|i| memory(&format!("new{}", i), 1.0, 0)).collect();
+        let vectors = [VectorizedMemory::for_test("old", vec![1.0])];
+        let vectors: HashMap<&str, &VectorizedMemory> = vectors.iter().map(|v| (v.id.as_str(), v)).collect();
+
+        let (selected, _) =
+            ShortTermAssembler::rank_by_similarity(memories.iter().collect(), &vectors, &[1.0], &config(3, 0.0));
+        assert_eq!(ids(&selected), vec!["new0", "new1", "new2"]);
+    }
+
+    #[tokio::test]
+    async fn test_select_memories() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let assembler = ShortTermAssembler::new();
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.user_input = "上次说的地方".to_string();
+        packet.short_term_memory = vec![memory("low", 0.2, 0), memory("high", 0.9, 0), memory("mid", 0.5, 0)];
+
+        // 不超过 top_k 时全部注入
+        let (selected, ranking) = assembler.select_memories(&packet, &ctx, &config(3, 0.1)).await;
+        assert_eq!(ids(&selected), vec!["high", "mid", "low"]);
+        assert_eq!(ranking["mode"], "stored_relevance");
+
+        // 没有向量文件时按记忆相关性截断
+        let (selected, ranking) = assembler.select_memories(&packet, &ctx, &config(2, 0.1)).await;
+        assert_eq!(ids(&selected), vec!["high", "mid"]);
+        assert_eq!(ranking["fallback_reason"], "no_vector_file");
+    }
+
+    #[tokio::test]
+    async fn test_process_keeps_summary_and_recent_turns() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ProcessorContext::for_test(AssistantConfig::default(), TopicType::Normal, dir.path());
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.user_input = "今天呢".to_string();
+        packet.short_term_memory = vec![memory("m1", 1.0, 0)];
+        packet.messages = vec![
+            ChatMessage::system("系统提示词"),
+            ChatMessage::user("更早对话的摘要").with_meta(MessageMeta::injected("HistorySimplifier", "history_summary")),
+            ChatMessage::user("昨天天气怎么样"),
+            ChatMessage::assistant("昨天下雨"),
+            ChatMessage::user("上一轮的记忆").with_meta(MessageMeta::injected("ShortTermAssembler", INJECTION_ID)),
+            ChatMessage::user("今天呢"),
+        ];
+
+        ShortTermAssembler::new().process(&mut packet, &ctx).await.unwrap();
+
+        let contents: Vec<&str> = packet.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents.len(), 6);
+        assert_eq!(contents[..4], ["系统提示词", "更早对话的摘要", "昨天天气怎么样", "昨天下雨"]);
+        assert!(packet.messages[4].is_injection(INJECTION_ID));
+        assert!(contents[4].contains("m1 概述"));
+        assert_eq!(contents[5], "今天呢");
+        assert_eq!(packet.current_states["ShortTermAssembler"]["insert_position"], 4);
+    }
+}
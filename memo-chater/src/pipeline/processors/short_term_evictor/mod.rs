@@ -0,0 +1,347 @@
+//! 短期记忆淘汰器
+//!
+//! 按助手配置的策略（AssistantConfig.short_term）维护短期记忆池：
+//! 相关性按经过的时间衰减（单次计入的时间有上限，长时间空闲后不会一次清空），
+//! 低于阈值或超出最大条数时淘汰相关性最低的记忆，
+//! 被淘汰的记忆按配置删除、归档到话题向量文件，或提交到长期记忆库
+//! 位置：ShortTermVectorizer / MemoryCommitter 之后执行
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::{debug, info, warn};
+
+use crate::assistant::{EvictionAction, ShortTermPolicyConfig};
+use crate::pipeline::{
+    processor::{Processor, ProcessorError},
+    context::ProcessorContext,
+    packet::ConversationPacket,
+};
+use crate::types::{LongTermMemory, MemorySource, ShortTermMemory};
+
+use super::memory_committer::MemoryCommitter;
+use super::short_term_vectorizer::{ShortTermVectorFile, ShortTermVectorizer};
+
+/// 短期记忆淘汰器
+pub struct ShortTermEvictor;
+
+impl Default for ShortTermEvictor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShortTermEvictor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// 按距上次衰减经过的时间（不超过 max_decay_hours）衰减相关性，返回衰减系数（首次运行只记录时间）
+    fn apply_decay(packet: &mut ConversationPacket, policy: &ShortTermPolicyConfig) -> f32 {
+        let now = Utc::now();
+        let factor = match packet.memory_decayed_at {
+            Some(last) if policy.decay_half_life_hours > 0.0 => {
+                let mut hours = (now - last).num_seconds().max(0) as f32 / 3600.0;
+                if policy.max_decay_hours > 0.0 {
+                    hours = hours.min(policy.max_decay_hours);
+                }
+                0.5f32.powf(hours / policy.decay_half_life_hours)
+            }
+            _ => 1.0,
+        };
+        if factor < 1.0 {
+            packet.decay_short_term_memory(factor);
+        }
+        packet.memory_decayed_at = Some(now);
+        factor
+    }
+
+    /// 选出需要淘汰的记忆ID：先淘汰相关性低于阈值的，再按相关性从低到高淘汰超出最大条数的部分
+    fn select_evictions(packet: &ConversationPacket, policy: &ShortTermPolicyConfig) -> Vec<String> {
+        let sorted = packet.get_short_term_memory_sorted();
+        let keep_limit = if policy.max_count == 0 { sorted.len() } else { policy.max_count };
+
+        sorted
+            .iter()
+            .enumerate()
+            .filter(|(rank, m)| *rank >= keep_limit || m.relevance < policy.min_relevance)
+            .map(|(_, m)| m.id.clone())
+            .collect()
+    }
+
+    /// 归档到话题向量文件：已向量化的条目标记为归档，尚未向量化的先生成向量
+    async fn archive(
+        packet: &ConversationPacket,
+        ctx: &ProcessorContext,
+        evicted: &[ShortTermMemory],
+    ) -> Result<usize, ProcessorError> {
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        let mut file = ShortTermVectorizer::load_vector_file(&path)
+            .await?
+            .unwrap_or_else(|| ShortTermVectorFile::new(ctx.embedding_model().to_string(), 0));
+
+        let mut archived = 0;
+        for memory in evicted {
+            if let Some(existing) = file.vectors.iter_mut().find(|v| v.id == memory.id) {
+                existing.archived = true;
+                existing.confidence = memory.confidence;
+                archived += 1;
+                continue;
+            }
+            match ShortTermVectorizer::vectorize_memory(memory, ctx).await {
+                Ok(mut vectorized) => {
+                    if file.metadata.dimension == 0 {
+                        file.metadata.dimension = vectorized.summary_embedding.len();
+                    }
+                    vectorized.archived = true;
+                    file.vectors.push(vectorized);
+                    archived += 1;
+                }
+                Err(e) => warn!("归档前向量化记忆 {} 失败，直接删除: {:?}", memory.id, e),
+            }
+        }
+
+        file.metadata.last_updated = Utc::now().to_rfc3339();
+        ShortTermVectorizer::save_vector_file(&path, &file).await?;
+        Ok(archived)
+    }
+
+    /// 直接删除：同时从话题向量文件中移除，避免之后仍能被检索到
+    async fn delete_vectors(
+        packet: &ConversationPacket,
+        ctx: &ProcessorContext,
+        ids: &[String],
+    ) -> Result<usize, ProcessorError> {
+        let path = ShortTermVectorizer::get_vector_file_path(ctx, packet);
+        let Some(mut file) = ShortTermVectorizer::load_vector_file(&path).await? else {
+            return Ok(0);
+        };
+
+        let before = file.vectors.len();
+        file.vectors.retain(|v| !ids.contains(&v.id));
+        let removed = before - file.vectors.len();
+        if removed > 0 {
+            file.metadata.last_updated = Utc::now().to_rfc3339();
+            ShortTermVectorizer::save_vector_file(&path, &file).await?;
+        }
+        Ok(removed)
+    }
+
+    /// 提交到长期记忆库（来自长期记忆检索或已提交的条目本就在库中，跳过）
+    async fn promote(ctx: &ProcessorContext, evicted: &[ShortTermMemory]) -> Result<usize, ProcessorError> {
+        let memories: Vec<LongTermMemory> = evicted
+            .iter()
+            .filter(|m| !m.committed && !matches!(m.source, MemorySource::LongTermRetrieval))
+            .map(|m| MemoryCommitter::to_long_term(m, ctx))
+            .collect();
+        if memories.is_empty() {
+            return Ok(0);
+        }
+
+        let long_term = {
+            let mut memory_manager = ctx.memory_manager.write().await;
+            memory_manager
+                .get_assistant_long_term_with_embedding(
+                    &ctx.assistant_id,
+                    Some(ctx.embedding_model()),
+                )
+                .await
+                .map_err(|e| ProcessorError::MemoryError(e.to_string()))?
+        };
+        long_term
+            .store_batch(&memories)
+            .await
+            .map_err(|e| ProcessorError::MemoryError(e.to_string()))?;
+
+        Ok(memories.len())
+    }
+}
+
+#[async_trait]
+impl Processor for ShortTermEvictor {
+    fn name(&self) -> &'static str {
+        "ShortTermEvictor"
+    }
+
+    fn requires_memory(&self) -> bool {
+        true
+    }
+
+    async fn process(
+        &self,
+        packet: &mut ConversationPacket,
+        ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        info!("ShortTermEvictor 开始处理");
+
+        let policy = ctx.short_term_policy().clone();
+        let decay_factor = Self::apply_decay(packet, &policy);
+        debug!("短期记忆相关性衰减系数 {:.4}", decay_factor);
+
+        let evict_ids = Self::select_evictions(packet, &policy);
+        if evict_ids.is_empty() {
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "decay_factor": decay_factor,
+                "evicted": 0,
+                "remaining": packet.get_short_term_memory().len()
+            }));
+            return Ok(());
+        }
+
+        // 归档/提交成功后再从数据包移除
+        let evicted: Vec<ShortTermMemory> = packet
+            .get_short_term_memory()
+            .iter()
+            .filter(|m| evict_ids.contains(&m.id))
+            .cloned()
+            .collect();
+
+        let handled = match policy.eviction {
+            EvictionAction::Delete => Self::delete_vectors(packet, ctx, &evict_ids).await?,
+            EvictionAction::Archive => Self::archive(packet, ctx, &evicted).await?,
+            EvictionAction::Promote => Self::promote(ctx, &evicted).await?,
+        };
+
+        packet.remove_short_term_memories(&evict_ids);
+
+        info!(
+            "淘汰 {} 条短期记忆（{:?}，处理 {} 条），剩余 {} 条",
+            evict_ids.len(),
+            policy.eviction,
+            handled,
+            packet.get_short_term_memory().len()
+        );
+
+        packet.set_processor_state(self.name(), serde_json::json!({
+            "decay_factor": decay_factor,
+            "evicted": evict_ids.len(),
+            "evicted_ids": evict_ids,
+            "action": policy.eviction,
+            "handled": handled,
+            "remaining": packet.get_short_term_memory().len()
+        }));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    use crate::assistant::{AssistantConfig, TopicType};
+    use crate::pipeline::processors::short_term_vectorizer::VectorizedMemory;
+
+    fn packet(relevances: &[f32]) -> ConversationPacket {
+        let mut packet = ConversationPacket::new(
+            "ast_test".to_string(),
+            "topic_test".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.short_term_memory = relevances
+            .iter()
+            .enumerate()
+            .map(|(i, &relevance)| ShortTermMemory {
+                relevance,
+                ..ShortTermMemory::for_test(&format!("m{}", i))
+            })
+            .collect();
+        packet
+    }
+
+    fn relevance(packet: &ConversationPacket, id: &str) -> f32 {
+        packet.short_term_memory.iter().find(|m| m.id == id).unwrap().relevance
+    }
+
+    #[test]
+    fn test_first_run_does_not_decay() {
+        let mut packet = packet(&[1.0]);
+        let factor = ShortTermEvictor::apply_decay(&mut packet, &ShortTermPolicyConfig::default());
+        assert_eq!(factor, 1.0);
+        assert_eq!(relevance(&packet, "m0"), 1.0);
+        assert!(packet.memory_decayed_at.is_some());
+    }
+
+    #[test]
+    fn test_decay_by_elapsed_time() {
+        let policy = ShortTermPolicyConfig {
+            max_decay_hours: 0.0,
+            ..ShortTermPolicyConfig::default()
+        };
+        let mut packet = packet(&[1.0]);
+        packet.memory_decayed_at = Some(Utc::now() - Duration::hours(72));
+        let factor = ShortTermEvictor::apply_decay(&mut packet, &policy);
+        assert!((factor - 0.5).abs() < 1e-3);
+        assert!((relevance(&packet, "m0") - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_idle_decay_is_capped() {
+        let policy = ShortTermPolicyConfig::default();
+        let mut packet = packet(&[1.0, 0.5]);
+        // 两周没有对话：不设上限时所有记忆都会低于阈值
+        packet.memory_decayed_at = Some(Utc::now() - Duration::days(14));
+
+        let factor = ShortTermEvictor::apply_decay(&mut packet, &policy);
+        let expected = 0.5f32.powf(policy.max_decay_hours / policy.decay_half_life_hours);
+        assert!((factor - expected).abs() < 1e-3);
+        assert!(ShortTermEvictor::select_evictions(&packet, &policy).is_empty());
+    }
+
+    #[test]
+    fn test_select_evictions() {
+        let packet = packet(&[0.3, 0.9, 0.01, 0.5]);
+
+        // 低于阈值的总是淘汰
+        let unlimited = ShortTermPolicyConfig {
+            max_count: 0,
+            ..ShortTermPolicyConfig::default()
+        };
+        assert_eq!(ShortTermEvictor::select_evictions(&packet, &unlimited), vec!["m2"]);
+
+        // 超出最大条数时按相关性从低到高淘汰
+        let limited = ShortTermPolicyConfig {
+            max_count: 2,
+            ..ShortTermPolicyConfig::default()
+        };
+        assert_eq!(ShortTermEvictor::select_evictions(&packet, &limited), vec!["m0", "m2"]);
+
+        let strict = ShortTermPolicyConfig {
+            max_count: 0,
+            min_relevance: 0.6,
+            ..ShortTermPolicyConfig::default()
+        };
+        assert_eq!(ShortTermEvictor::select_evictions(&packet, &strict), vec!["m3", "m0", "m2"]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_vectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AssistantConfig {
+            short_term: ShortTermPolicyConfig {
+                eviction: EvictionAction::Delete,
+                ..ShortTermPolicyConfig::default()
+            },
+            ..AssistantConfig::default()
+        };
+        let ctx = ProcessorContext::for_test(config, TopicType::Normal, dir.path());
+        let mut packet = packet(&[0.9, 0.01]);
+
+        let path = ShortTermVectorizer::get_vector_file_path(&ctx, &packet);
+        let mut file = ShortTermVectorFile::new("test-embedding".to_string(), 1);
+        file.vectors = vec![
+            VectorizedMemory::for_test("m0", vec![1.0]),
+            VectorizedMemory::for_test("m1", vec![1.0]),
+            VectorizedMemory { archived: true, ..VectorizedMemory::for_test("old", vec![1.0]) },
+        ];
+        ShortTermVectorizer::save_vector_file(&path, &file).await.unwrap();
+
+        ShortTermEvictor::new().process(&mut packet, &ctx).await.unwrap();
+
+        assert_eq!(packet.short_term_memory.len(), 1);
+        let file = ShortTermVectorizer::load_vector_file(&path).await.unwrap().unwrap();
+        let ids: Vec<&str> = file.vectors.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["m0", "old"]);
+    }
+}
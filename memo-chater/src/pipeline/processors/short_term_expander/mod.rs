@@ -8,10 +8,14 @@ use chrono::{DateTime, Utc};
 
 use crate::pipeline::{
     processor::{Processor, ProcessorError},
+    budget::{BudgetFit, BudgetItem, BudgetSection, ContextBudget},
     context::ProcessorContext,
     packet::ConversationPacket,
 };
-use crate::types::ChatMessage;
+use crate::types::{ChatMessage, MessageMeta};
+
+/// 展开消息的注入标识
+const INJECTION_ID: &str = "expanded_memory";
 
 /// 短期记忆展开器
 /// 
@@ -29,10 +33,15 @@ impl ShortTermExpander {
     }
 
     /// 构建展开后的记忆文本
-    fn build_expanded_text(packet: &ConversationPacket) -> String {
-        packet.get_short_term_memory()
+    ///
+    /// 超出展开记忆份额时按价值从低到高丢弃（以记忆时间作为价值，越早越先丢弃）
+    fn build_expanded_text(packet: &ConversationPacket, ctx: &ProcessorContext) -> (String, Vec<String>, BudgetFit) {
+        let memories: Vec<_> = packet.get_short_term_memory()
             .iter()
             .filter(|m| m.should_expand)
+            .collect();
+        let blocks: Vec<String> = memories
+            .iter()
             .map(|m| format!(
                 "[{}][{}]{}\n{}",
                 Self::format_timestamp(&m.timestamp),
@@ -40,8 +49,27 @@ impl ShortTermExpander {
                 m.summary,
                 m.content
             ))
+            .collect();
+
+        let items: Vec<BudgetItem> = memories
+            .iter()
+            .zip(&blocks)
+            .map(|(m, block)| BudgetItem {
+                id: m.id.clone(),
+                tokens: ctx.count_tokens(block) + 2,
+                value: m.timestamp.timestamp_millis() as f64,
+            })
+            .collect();
+        let fit = ContextBudget::for_context(ctx).fit(BudgetSection::ExpandedMemories, &items);
+
+        let text = fit.kept.iter()
+            .map(|&i| blocks[i].as_str())
             .collect::<Vec<_>>()
-            .join("\n\n")
+            .join("\n\n");
+        let ids = fit.kept.iter()
+            .map(|&i| memories[i].id.clone())
+            .collect();
+        (text, ids, fit)
     }
 }
 
@@ -58,7 +86,7 @@ impl Processor for ShortTermExpander {
     async fn process(
         &self,
         packet: &mut ConversationPacket,
-        _ctx: &ProcessorContext,
+        ctx: &ProcessorContext,
     ) -> Result<(), ProcessorError> {
         info!("ShortTermExpander 开始处理");
 
@@ -80,13 +108,21 @@ impl Processor for ShortTermExpander {
         info!("发现 {} 条需要展开的短期记忆", expand_count);
 
         // 构建展开后的文本
-        let expanded_text = Self::build_expanded_text(packet);
+        let (expanded_text, expanded_ids, budget) = Self::build_expanded_text(packet, ctx);
+        if !budget.dropped.is_empty() {
+            info!(
+                "展开记忆超出预算（份额 {} tokens），丢弃 {} 条",
+                budget.limit,
+                budget.dropped.len()
+            );
+        }
 
         if expanded_text.is_empty() {
             info!("展开文本为空，跳过");
             packet.set_processor_state(self.name(), serde_json::json!({
                 "expanded": false,
-                "reason": "expanded_text_empty"
+                "reason": "expanded_text_empty",
+                "budget": budget
             }));
             return Ok(());
         }
@@ -98,6 +134,9 @@ impl Processor for ShortTermExpander {
             expanded_text
         );
 
+        // 替换本轮已注入的展开消息（重复执行时）
+        packet.remove_injection(INJECTION_ID);
+
         // 插入到倒数第二个位置（用户最新发言之前）
         let insert_pos = if packet.messages.len() >= 1 {
             packet.messages.len() - 1
@@ -105,23 +144,24 @@ impl Processor for ShortTermExpander {
             packet.messages.len()
         };
 
-        packet.messages.insert(insert_pos, ChatMessage::user(&injection_content));
+        packet.messages.insert(
+            insert_pos,
+            ChatMessage::user(&injection_content).with_meta(MessageMeta::injected(self.name(), INJECTION_ID)),
+        );
 
         info!("已在位置 {} 插入展开的记忆内容", insert_pos);
 
-        // 获取展开的记忆ID列表
-        let expanded_ids: Vec<String> = packet.get_short_term_memory()
-            .iter()
-            .filter(|m| m.should_expand)
-            .map(|m| m.id.clone())
-            .collect();
+        // 被展开视为一次访问，加强相关性
+        packet.reinforce_short_term_memory(&expanded_ids, ctx.short_term_policy().reinforce_boost);
 
         // 保存处理器状态
         packet.set_processor_state(self.name(), serde_json::json!({
             "expanded": true,
             "expanded_ids": expanded_ids,
-            "expanded_count": expand_count,
-            "insert_position": insert_pos
+            "expanded_count": expanded_ids.len(),
+            "marked_count": expand_count,
+            "insert_position": insert_pos,
+            "budget": budget
         }));
 
         Ok(())
@@ -15,6 +15,7 @@ use crate::pipeline::{
     packet::ConversationPacket,
     processor::{Processor, ProcessorError},
 };
+use crate::storage::{read_with_backup, write_atomic, VECTOR_FILE_SCHEMA};
 use crate::types::ShortTermMemory;
 
 /// 向量化后的短期记忆条目
@@ -32,11 +33,67 @@ pub struct VectorizedMemory {
     pub summary_embedding: Vec<f32>,
     /// content 的向量 (权重 0.6)
     pub content_embedding: Vec<f32>,
+    /// 已从短期记忆中淘汰，仅保留在向量文件中供搜索
+    #[serde(default)]
+    pub archived: bool,
+}
+
+/// 加权余弦相似度中 summary 向量的权重
+pub const SUMMARY_WEIGHT: f32 = 0.4;
+/// 加权余弦相似度中 content 向量的权重
+pub const CONTENT_WEIGHT: f32 = 0.6;
+
+impl VectorizedMemory {
+    /// 与查询向量的加权余弦相似度（summary 0.4, content 0.6）
+    pub fn score(&self, query: &[f32]) -> f32 {
+        SUMMARY_WEIGHT * cosine_similarity(query, &self.summary_embedding)
+            + CONTENT_WEIGHT * cosine_similarity(query, &self.content_embedding)
+    }
+}
+
+#[cfg(test)]
+impl VectorizedMemory {
+    /// 测试用向量条目：summary 与 content 使用同一向量，未归档
+    pub(crate) fn for_test(id: &str, embedding: Vec<f32>) -> Self {
+        Self {
+            id: id.to_string(),
+            summary: String::new(),
+            content: String::new(),
+            memory_type: "fact".to_string(),
+            source: "CurrentConversation".to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+            should_expand: false,
+            confidence: 1.0,
+            summary_embedding: embedding.clone(),
+            content_embedding: embedding,
+            archived: false,
+        }
+    }
+}
+
+/// 计算余弦相似度
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    
+    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    
+    dot / (norm_a * norm_b)
 }
 
 /// 短期记忆向量文件结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShortTermVectorFile {
+    /// 持久化格式版本（见 storage::VECTOR_FILE_SCHEMA）
+    #[serde(default)]
+    pub schema_version: u32,
     pub vectors: Vec<VectorizedMemory>,
     pub metadata: VectorFileMetadata,
 }
@@ -52,6 +109,7 @@ pub struct VectorFileMetadata {
 impl ShortTermVectorFile {
     pub fn new(embedding_model: String, dimension: usize) -> Self {
         Self {
+            schema_version: VECTOR_FILE_SCHEMA.current,
             vectors: Vec::new(),
             metadata: VectorFileMetadata {
                 embedding_model,
@@ -71,7 +129,7 @@ impl ShortTermVectorizer {
     }
 
     /// 获取话题的向量文件路径
-    fn get_vector_file_path(ctx: &ProcessorContext, packet: &ConversationPacket) -> PathBuf {
+    pub(crate) fn get_vector_file_path(ctx: &ProcessorContext, packet: &ConversationPacket) -> PathBuf {
         let data_dir = ctx.global_config.data_dir.clone();
         PathBuf::from(data_dir)
             .join("assistants")
@@ -81,22 +139,24 @@ impl ShortTermVectorizer {
             .join("short_term_vectors.json")
     }
 
-    /// 加载现有的向量文件
-    async fn load_vector_file(path: &PathBuf) -> Option<ShortTermVectorFile> {
-        match fs::read_to_string(path).await {
-            Ok(content) => match serde_json::from_str(&content) {
-                Ok(file) => Some(file),
-                Err(e) => {
-                    warn!("解析向量文件失败: {}", e);
-                    None
-                }
-            },
-            Err(_) => None,
+    /// 加载现有的向量文件，文件不存在时返回 None
+    ///
+    /// 读取失败时返回错误，写入方不能新建文件覆盖（如版本高于程序支持的文件）
+    pub(crate) async fn load_vector_file(path: &PathBuf) -> Result<Option<ShortTermVectorFile>, ProcessorError> {
+        if !path.exists() {
+            return Ok(None);
         }
+        read_with_backup(path, |content| VECTOR_FILE_SCHEMA.parse_io(content))
+            .await
+            .map(Some)
+            .map_err(|e| {
+                warn!("解析向量文件失败: {}", e);
+                ProcessorError::Internal(format!("读取向量文件失败: {}", e))
+            })
     }
 
     /// 保存向量文件
-    async fn save_vector_file(path: &PathBuf, file: &ShortTermVectorFile) -> Result<(), ProcessorError> {
+    pub(crate) async fn save_vector_file(path: &PathBuf, file: &ShortTermVectorFile) -> Result<(), ProcessorError> {
         // 确保目录存在
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).await.map_err(|e| {
@@ -108,7 +168,7 @@ impl ShortTermVectorizer {
             ProcessorError::Internal(format!("序列化向量文件失败: {}", e))
         })?;
 
-        fs::write(path, content).await.map_err(|e| {
+        write_atomic(path, content).await.map_err(|e| {
             ProcessorError::Internal(format!("写入向量文件失败: {}", e))
         })?;
 
@@ -125,8 +185,7 @@ impl ShortTermVectorizer {
     }
 
     /// 为记忆生成向量（summary + content 双向量）
-    async fn vectorize_memory(
-        &self,
+    pub(crate) async fn vectorize_memory(
         memory: &ShortTermMemory,
         ctx: &ProcessorContext,
     ) -> Result<VectorizedMemory, ProcessorError> {
@@ -154,9 +213,10 @@ impl ShortTermVectorizer {
             source: Self::memory_source_to_string(memory),
             timestamp: memory.timestamp.to_rfc3339(),
             should_expand: memory.should_expand,
-            confidence: 1.0, // 新记忆默认置信度 1.0
+            confidence: memory.confidence,
             summary_embedding,
             content_embedding,
+            archived: false,
         })
     }
 }
@@ -197,7 +257,7 @@ impl Processor for ShortTermVectorizer {
 
         // 加载现有向量文件或创建新的
         let mut vector_file = Self::load_vector_file(&vector_file_path)
-            .await
+            .await?
             .unwrap_or_else(|| {
                 info!("创建新的向量文件");
                 ShortTermVectorFile::new(ctx.embedding_model().to_string(), 0)
@@ -217,7 +277,7 @@ impl Processor for ShortTermVectorizer {
             let is_new = !existing_ids.contains(&memory.id);
 
             // 生成向量
-            match self.vectorize_memory(memory, ctx).await {
+            match Self::vectorize_memory(memory, ctx).await {
                 Ok(vectorized) => {
                     // 更新维度信息（首次）
                     if vector_file.metadata.dimension == 0 {
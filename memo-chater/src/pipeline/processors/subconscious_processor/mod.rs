@@ -1,23 +1,163 @@
 //! 潜意识处理器
 //!
 //! 处理潜意识层面的信息，如情感、意图、隐含需求等
+//! 每轮调用一次处理模型，分析结果写入思考池，情绪趋势记录在处理器状态中供下一轮对比
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
 use crate::pipeline::{
     processor::{Processor, ProcessorError},
     context::ProcessorContext,
     packet::ConversationPacket,
 };
+use crate::types::{ChatMessage, ThinkingSource};
+
+/// 思考池条目前缀（用于识别本处理器写入的条目）
+const THINKING_PREFIX: &str = "【潜意识分析】";
+
+/// 思考池中保留的潜意识分析条数
+const MAX_ANALYSIS_ENTRIES: usize = 3;
+
+/// 情绪历史保留条数
+const MAX_EMOTION_HISTORY: usize = 5;
+
+/// 强度变化超过此值视为上升/下降
+const TREND_THRESHOLD: f32 = 0.15;
+
+/// 分析提示词
+const ANALYSIS_PROMPT: &str = r#"你是一个细腻的心理分析师。请分析{user_name}在本轮对话中的潜意识信息。
+
+## 上一轮情绪
+{previous_emotion}
+
+## 本轮对话
+【{user_name}】: {user_input}
+
+【{assistant_name}】: {ai_response}
+
+## 输出要求
+只输出一个 JSON 对象，不要有其他内容：
+{
+  "emotion": "情绪（如 平静/开心/焦虑/沮丧/愤怒/好奇/疲惫）",
+  "intensity": 0.0到1.0之间的情绪强度,
+  "implicit_intent": "{user_name}没有直接说出的真实意图",
+  "hidden_needs": ["潜在需求1", "潜在需求2"],
+  "tone_suggestion": "{assistant_name}下一轮回应时适合的语气"
+}"#;
+
+/// 潜意识分析结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SubconsciousAnalysis {
+    #[serde(default)]
+    emotion: String,
+    #[serde(default)]
+    intensity: f32,
+    #[serde(default)]
+    implicit_intent: String,
+    #[serde(default)]
+    hidden_needs: Vec<String>,
+    #[serde(default)]
+    tone_suggestion: String,
+}
 
 /// 潜意识处理器
-/// 
+///
 /// 负责分析和处理对话中的潜意识信息
 pub struct SubconsciousProcessor;
 
+impl Default for SubconsciousProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SubconsciousProcessor {
     pub fn new() -> Self {
         Self
     }
+
+    /// 从模型响应中提取 JSON 对象并解析
+    fn parse_analysis(response: &str) -> Result<SubconsciousAnalysis, ProcessorError> {
+        let start = response.find('{');
+        let end = response.rfind('}');
+        let json_str = match (start, end) {
+            (Some(s), Some(e)) if s < e => &response[s..=e],
+            _ => return Err(ProcessorError::Internal("响应中未找到 JSON 对象".to_string())),
+        };
+
+        let mut analysis: SubconsciousAnalysis = serde_json::from_str(json_str)
+            .map_err(|e| ProcessorError::Internal(format!("解析分析结果失败: {}", e)))?;
+        analysis.intensity = analysis.intensity.clamp(0.0, 1.0);
+        Ok(analysis)
+    }
+
+    /// 与上一轮对比得出情绪趋势
+    fn compute_trend(previous: Option<(&str, f32)>, current: &SubconsciousAnalysis) -> &'static str {
+        match previous {
+            None => "initial",
+            Some((emotion, _)) if emotion != current.emotion => "shifted",
+            Some((_, intensity)) => {
+                let delta = current.intensity - intensity;
+                if delta > TREND_THRESHOLD {
+                    "rising"
+                } else if delta < -TREND_THRESHOLD {
+                    "falling"
+                } else {
+                    "stable"
+                }
+            }
+        }
+    }
+
+    /// 趋势的中文描述
+    fn trend_label(trend: &str) -> &'static str {
+        match trend {
+            "shifted" => "情绪发生转变",
+            "rising" => "较上轮增强",
+            "falling" => "较上轮减弱",
+            "stable" => "与上轮持平",
+            _ => "首次记录",
+        }
+    }
+
+    /// 构建思考池条目内容
+    fn build_thinking(analysis: &SubconsciousAnalysis, trend: &str) -> String {
+        let needs = if analysis.hidden_needs.is_empty() {
+            "无".to_string()
+        } else {
+            analysis.hidden_needs.join("；")
+        };
+        format!(
+            "{}情绪：{}（强度 {:.2}，{}）\n隐含意图：{}\n潜在需求：{}\n语气建议：{}",
+            THINKING_PREFIX,
+            analysis.emotion,
+            analysis.intensity,
+            Self::trend_label(trend),
+            analysis.implicit_intent,
+            needs,
+            analysis.tone_suggestion
+        )
+    }
+
+    /// 只保留最近几条潜意识分析，避免思考池无限增长
+    fn prune_thinking(packet: &mut ConversationPacket) {
+        let total = packet
+            .thinking_pool
+            .iter()
+            .filter(|t| t.content.starts_with(THINKING_PREFIX))
+            .count();
+        let mut to_remove = total.saturating_sub(MAX_ANALYSIS_ENTRIES);
+        packet.thinking_pool.retain(|t| {
+            if to_remove > 0 && t.content.starts_with(THINKING_PREFIX) {
+                to_remove -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
 }
 
 #[async_trait]
@@ -33,15 +173,130 @@ impl Processor for SubconsciousProcessor {
     async fn process(
         &self,
         packet: &mut ConversationPacket,
-        _ctx: &ProcessorContext,
+        ctx: &ProcessorContext,
     ) -> Result<(), ProcessorError> {
-        // TODO: 实现潜意识处理逻辑
-        // 1. 分析用户情感状态
-        // 2. 识别隐含意图
-        // 3. 提取潜在需求
-        // 4. 记录情感变化趋势
-        
-        packet.set_processor_state(self.name(), serde_json::json!({"processed": true}));
+        info!("SubconsciousProcessor 开始处理");
+
+        if packet.user_input.trim().is_empty() {
+            packet.set_processor_state(self.name(), serde_json::json!({
+                "processed": false,
+                "reason": "empty_user_input"
+            }));
+            return Ok(());
+        }
+
+        // 上一轮的情绪记录
+        let previous_state = packet.get_previous_state(self.name()).cloned();
+        let previous = previous_state.as_ref().and_then(|s| {
+            let emotion = s.get("emotion")?.as_str()?;
+            let intensity = s.get("intensity")?.as_f64()? as f32;
+            Some((emotion.to_string(), intensity))
+        });
+        let mut emotion_history: Vec<serde_json::Value> = previous_state
+            .as_ref()
+            .and_then(|s| s.get("emotion_history"))
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        let previous_desc = match &previous {
+            Some((emotion, intensity)) => format!("{}（强度 {:.2}）", emotion, intensity),
+            None => "无记录".to_string(),
+        };
+
+        let prompt = ANALYSIS_PROMPT
+            .replace("{previous_emotion}", &previous_desc)
+            .replace("{user_input}", &packet.user_input)
+            .replace("{ai_response}", packet.ai_response.as_deref().unwrap_or(""))
+            .replace("{user_name}", &packet.user_name)
+            .replace("{assistant_name}", &packet.assistant_name);
+
+        let response = ctx
+            .ai_client
+            .chat_with_model(&[ChatMessage::user(&prompt)], Some(ctx.processor_model()))
+            .await
+            .map_err(|e| ProcessorError::AiError(e.to_string()))?;
+
+        debug!("潜意识分析响应: {}", response);
+
+        let analysis = Self::parse_analysis(&response)?;
+        let trend = Self::compute_trend(
+            previous.as_ref().map(|(e, i)| (e.as_str(), *i)),
+            &analysis,
+        );
+        let intensity_delta = previous.as_ref().map(|(_, i)| analysis.intensity - i);
+
+        info!(
+            "潜意识分析完成: 情绪 {}（强度 {:.2}），趋势 {}",
+            analysis.emotion, analysis.intensity, trend
+        );
+
+        packet.add_thinking(Self::build_thinking(&analysis, trend), ThinkingSource::UserAnalysis);
+        Self::prune_thinking(packet);
+
+        emotion_history.push(serde_json::json!({
+            "emotion": analysis.emotion,
+            "intensity": analysis.intensity,
+        }));
+        if emotion_history.len() > MAX_EMOTION_HISTORY {
+            let overflow = emotion_history.len() - MAX_EMOTION_HISTORY;
+            emotion_history.drain(..overflow);
+        }
+
+        packet.set_processor_state(self.name(), serde_json::json!({
+            "processed": true,
+            "emotion": analysis.emotion,
+            "intensity": analysis.intensity,
+            "implicit_intent": analysis.implicit_intent,
+            "hidden_needs": analysis.hidden_needs,
+            "tone_suggestion": analysis.tone_suggestion,
+            "previous_emotion": previous.as_ref().map(|(e, _)| e.clone()),
+            "intensity_delta": intensity_delta,
+            "trend": trend,
+            "emotion_history": emotion_history,
+        }));
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(emotion: &str, intensity: f32) -> SubconsciousAnalysis {
+        SubconsciousAnalysis {
+            emotion: emotion.to_string(),
+            intensity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_fenced_json() {
+        let response = "分析如下：\n```json\n{\"emotion\": \"焦虑\", \"intensity\": 1.4, \"hidden_needs\": [\"安慰\"]}\n```";
+        let parsed = SubconsciousProcessor::parse_analysis(response).unwrap();
+        assert_eq!(parsed.emotion, "焦虑");
+        // 强度截断到 0~1
+        assert_eq!(parsed.intensity, 1.0);
+        assert_eq!(parsed.hidden_needs, vec!["安慰"]);
+        assert!(parsed.implicit_intent.is_empty());
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(SubconsciousProcessor::parse_analysis("没有 JSON").is_err());
+        assert!(SubconsciousProcessor::parse_analysis("} 反了 {").is_err());
+        assert!(SubconsciousProcessor::parse_analysis("{\"emotion\": \"开心\", }").is_err());
+    }
+
+    #[test]
+    fn test_compute_trend() {
+        let current = analysis("焦虑", 0.6);
+        assert_eq!(SubconsciousProcessor::compute_trend(None, &current), "initial");
+        assert_eq!(SubconsciousProcessor::compute_trend(Some(("平静", 0.6)), &current), "shifted");
+        assert_eq!(SubconsciousProcessor::compute_trend(Some(("焦虑", 0.3)), &current), "rising");
+        assert_eq!(SubconsciousProcessor::compute_trend(Some(("焦虑", 0.9)), &current), "falling");
+        assert_eq!(SubconsciousProcessor::compute_trend(Some(("焦虑", 0.5)), &current), "stable");
+    }
+}
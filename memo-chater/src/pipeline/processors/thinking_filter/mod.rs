@@ -0,0 +1,298 @@
+//! 思考标签过滤器
+//!
+//! 在流式响应中实时过滤模型泄露的 <think>...</think> / <thinking>...</thinking> 块
+//! 位置：on_stream_chunk
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::ai::AiClient;
+use crate::pipeline::{
+    processor::{Processor, ProcessorError, StreamChunkAction},
+    context::ProcessorContext,
+    packet::ConversationPacket,
+};
+
+/// 开始标签前缀（匹配 <think> 与 <thinking>，可带属性）
+const OPEN_TAG: &str = "<think";
+
+/// 结束标签前缀（匹配 </think> 与 </thinking>）
+const CLOSE_TAG: &str = "</think";
+
+/// 从标签前缀处匹配完整标签的结果
+#[derive(Debug, PartialEq)]
+enum TagMatch {
+    /// 完整的思考标签，携带标签长度
+    Complete(usize),
+    /// 可能是思考标签，但还没有收全
+    Partial,
+    /// 只是以相同前缀开头的普通文本（如 <thinker>、<thinkpad>）
+    NoMatch,
+}
+
+/// 跨 chunk 的过滤状态（保存在处理器状态中）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct FilterState {
+    /// 是否处于思考块内部
+    in_think: bool,
+    /// 可能是标签开头、尚未确定的缓冲内容
+    pending: String,
+    /// 当前思考块已读取的原文（块没有结束时在流结束时原样发出）
+    #[serde(default)]
+    block: String,
+    /// 是否已经发出过正文（正文开始前的空白会被丢弃）
+    emitted: bool,
+}
+
+impl FilterState {
+    /// 处理一段文本，返回可以发出的内容
+    fn feed(&mut self, chunk: &str) -> String {
+        let mut buffer = std::mem::take(&mut self.pending);
+        buffer.push_str(chunk);
+        let mut output = String::new();
+
+        loop {
+            let marker = if self.in_think { CLOSE_TAG } else { OPEN_TAG };
+            match buffer.find(marker) {
+                Some(start) => {
+                    self.consume(&buffer[..start], &mut output);
+                    match Self::match_tag(&buffer[start..], marker, !self.in_think) {
+                        TagMatch::Complete(len) => {
+                            self.in_think = !self.in_think;
+                            self.block = if self.in_think {
+                                buffer[start..start + len].to_string()
+                            } else {
+                                String::new()
+                            };
+                            buffer = buffer[start + len..].to_string();
+                        }
+                        TagMatch::Partial => {
+                            // 标签未收全时先缓冲
+                            self.pending = buffer[start..].to_string();
+                            break;
+                        }
+                        TagMatch::NoMatch => {
+                            self.consume(marker, &mut output);
+                            buffer = buffer[start + marker.len()..].to_string();
+                        }
+                    }
+                }
+                None => {
+                    // 末尾可能是被截断的标签开头，留到下一个 chunk
+                    let hold = Self::partial_marker_len(&buffer, marker);
+                    let split = buffer.len() - hold;
+                    self.consume(&buffer[..split], &mut output);
+                    self.pending = buffer[split..].to_string();
+                    break;
+                }
+            }
+        }
+
+        self.emit(output)
+    }
+
+    /// 已确定不是标签的文本：思考块外直接输出，块内记入当前块
+    fn consume(&mut self, text: &str, output: &mut String) {
+        if self.in_think {
+            self.block.push_str(text);
+        } else {
+            output.push_str(text);
+        }
+    }
+
+    /// 流结束：缓冲内容原样发出（没有结束标签的思考块不过滤，与 strip_thinking_tags 一致）
+    fn flush(&mut self) -> String {
+        let mut rest = std::mem::take(&mut self.block);
+        rest.push_str(&std::mem::take(&mut self.pending));
+        self.in_think = false;
+        self.emit(rest)
+    }
+
+    /// 丢弃正文开始前的空白（与 strip_thinking_tags 的 trim 行为一致）
+    fn emit(&mut self, output: String) -> String {
+        if self.emitted {
+            return output;
+        }
+        let trimmed = output.trim_start();
+        if !trimmed.is_empty() {
+            self.emitted = true;
+        }
+        trimmed.to_string()
+    }
+
+    /// 从 marker 处匹配 `<think>` / `<thinking>`（开始标签可在空白后带属性）或对应的结束标签
+    fn match_tag(text: &str, marker: &str, allow_attributes: bool) -> TagMatch {
+        let rest = &text[marker.len()..];
+        let rest = match rest.strip_prefix("ing") {
+            Some(rest) => rest,
+            None if "ing".starts_with(rest) => return TagMatch::Partial,
+            None => rest,
+        };
+        let tag_len = |end: usize| text.len() - rest.len() + end + 1;
+
+        match rest.chars().next() {
+            None => TagMatch::Partial,
+            Some('>') => TagMatch::Complete(tag_len(0)),
+            Some(c) if c.is_whitespace() => {
+                let end = if allow_attributes {
+                    rest.find('>')
+                } else {
+                    match rest.find(|c: char| !c.is_whitespace()) {
+                        Some(i) if rest[i..].starts_with('>') => Some(i),
+                        Some(_) => return TagMatch::NoMatch,
+                        None => None,
+                    }
+                };
+                end.map_or(TagMatch::Partial, |end| TagMatch::Complete(tag_len(end)))
+            }
+            Some(_) => TagMatch::NoMatch,
+        }
+    }
+
+    /// 计算 buffer 末尾与 marker 前缀重合的长度
+    fn partial_marker_len(buffer: &str, marker: &str) -> usize {
+        (1..marker.len())
+            .rev()
+            .find(|&k| buffer.ends_with(&marker[..k]))
+            .unwrap_or(0)
+    }
+}
+
+/// 思考标签过滤器
+///
+/// 流式时逐 chunk 过滤思考块；在整包时机执行时对最后一条助手消息做整体清理
+pub struct ThinkingFilter;
+
+impl Default for ThinkingFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThinkingFilter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn load_state(&self, packet: &ConversationPacket) -> FilterState {
+        packet
+            .current_states
+            .get(self.name())
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_state(&self, packet: &mut ConversationPacket, state: &FilterState) {
+        packet.set_processor_state(
+            self.name(),
+            serde_json::to_value(state).unwrap_or_default(),
+        );
+    }
+}
+
+#[async_trait]
+impl Processor for ThinkingFilter {
+    fn name(&self) -> &'static str {
+        "ThinkingFilter"
+    }
+
+    fn requires_memory(&self) -> bool {
+        false
+    }
+
+    async fn process(
+        &self,
+        packet: &mut ConversationPacket,
+        _ctx: &ProcessorContext,
+    ) -> Result<(), ProcessorError> {
+        if let Some(last) = packet.messages.iter_mut().rev().find(|m| m.role == "assistant") {
+            last.content = AiClient::strip_thinking_tags(&last.content);
+        }
+        Ok(())
+    }
+
+    async fn process_chunk(
+        &self,
+        chunk: &str,
+        packet: &mut ConversationPacket,
+        _ctx: &ProcessorContext,
+    ) -> Result<StreamChunkAction, ProcessorError> {
+        let mut state = self.load_state(packet);
+        let output = state.feed(chunk);
+        self.save_state(packet, &state);
+
+        if output.is_empty() {
+            debug!("ThinkingFilter 屏蔽 chunk");
+            Ok(StreamChunkAction::Suppress)
+        } else if output == chunk {
+            Ok(StreamChunkAction::Pass)
+        } else {
+            Ok(StreamChunkAction::Replace(output))
+        }
+    }
+
+    async fn finish_stream(
+        &self,
+        packet: &mut ConversationPacket,
+        _ctx: &ProcessorContext,
+    ) -> Result<Option<String>, ProcessorError> {
+        let mut state = self.load_state(packet);
+        let rest = state.flush();
+        self.save_state(packet, &state);
+        Ok(if rest.is_empty() { None } else { Some(rest) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(chunks: &[&str]) -> String {
+        let mut state = FilterState::default();
+        let mut out: String = chunks.iter().map(|c| state.feed(c)).collect();
+        out.push_str(&state.flush());
+        out
+    }
+
+    #[test]
+    fn test_filter_whole_block() {
+        assert_eq!(run(&["<think>推理过程</think>\n\n你好"]), "你好");
+    }
+
+    #[test]
+    fn test_filter_split_tags() {
+        assert_eq!(run(&["<thi", "nking>想一", "想</thin", "king>答", "案"]), "答案");
+    }
+
+    #[test]
+    fn test_pass_through_plain_text() {
+        assert_eq!(run(&["a < b", " 且 c > d"]), "a < b 且 c > d");
+    }
+
+    #[test]
+    fn test_filter_tag_with_attributes() {
+        assert_eq!(run(&["<think ", "type=\"plan\">计划</think >", "回答"]), "回答");
+    }
+
+    #[test]
+    fn test_unclosed_block_emitted() {
+        let chunks = ["正文", "<think>没有结束"];
+        assert_eq!(run(&chunks), "正文<think>没有结束");
+        assert_eq!(run(&chunks), AiClient::strip_thinking_tags(&chunks.concat()));
+    }
+
+    #[test]
+    fn test_look_alike_tags_pass_through() {
+        let cases: [&[&str]; 3] = [
+            &["我的 <thinkpad", " 坏了，后面的内容不能丢"],
+            &["<thinker>哲学家</thinker> 说", "了</think>很多"],
+            &["结尾是 <think"],
+        ];
+        for chunks in cases {
+            let text = chunks.concat();
+            assert_eq!(run(chunks), text);
+            assert_eq!(run(chunks), AiClient::strip_thinking_tags(&text));
+        }
+    }
+}
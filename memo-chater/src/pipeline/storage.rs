@@ -1,35 +1,220 @@
 //! 对话数据包持久化
 //!
-//! 负责 ConversationPacket 的加载和保存
+//! 负责 ConversationPacket 的加载和保存，以及按轮次保存的快照（用于撤销和回滚）
 
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
 use super::packet::ConversationPacket;
+use super::processors::short_term_vectorizer::ShortTermVectorFile;
+use crate::storage::{read_with_backup, remove_with_backup, write_atomic, PACKET_SCHEMA, VECTOR_FILE_SCHEMA};
 
 /// 数据包存储文件名
 const PACKET_FILENAME: &str = "conversation_state.json";
 
+/// 对话记忆向量文件名
+const VECTOR_FILENAME: &str = "short_term_vectors.json";
+
+/// 快照目录名
+const SNAPSHOT_DIR: &str = "snapshots";
+
+/// 默认保留的快照数
+const DEFAULT_MAX_SNAPSHOTS: usize = 20;
+
+/// 轮次快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacketSnapshot {
+    /// 快照对应的轮次（轮次结束后的 packet.turn_index）
+    pub turn_index: u64,
+    /// 创建时间
+    pub created_at: DateTime<Utc>,
+    /// 当时 history.json 的消息条数（恢复时截断到此长度）
+    pub history_len: usize,
+    /// 数据包
+    pub packet: ConversationPacket,
+}
+
+/// 快照概要（列表展示用）
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub turn_index: u64,
+    pub created_at: DateTime<Utc>,
+    pub history_len: usize,
+    pub message_count: usize,
+    pub short_term_count: usize,
+    pub thinking_count: usize,
+}
+
+impl From<&PacketSnapshot> for SnapshotInfo {
+    fn from(s: &PacketSnapshot) -> Self {
+        Self {
+            turn_index: s.turn_index,
+            created_at: s.created_at,
+            history_len: s.history_len,
+            message_count: s.packet.messages.len(),
+            short_term_count: s.packet.short_term_memory.len(),
+            thinking_count: s.packet.thinking_pool.len(),
+        }
+    }
+}
+
+/// 两个快照之间的差异
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketDiff {
+    pub from_turn: u64,
+    pub to_turn: u64,
+    /// messages 条数变化（from → to）
+    pub messages: (usize, usize),
+    /// 新增的短期记忆 id
+    pub short_term_added: Vec<String>,
+    /// 移除的短期记忆 id
+    pub short_term_removed: Vec<String>,
+    /// 内容发生变化的短期记忆 id
+    pub short_term_changed: Vec<String>,
+    /// 思考池条数变化（from → to）
+    pub thinking_pool: (usize, usize),
+    /// 处理器状态中新增或变化的键
+    pub states_changed: Vec<String>,
+    /// 历史摘要是否变化
+    pub history_summary_changed: bool,
+}
+
+impl PacketDiff {
+    /// 计算两个快照之间的差异
+    pub fn between(from: &PacketSnapshot, to: &PacketSnapshot) -> Self {
+        let (a, b) = (&from.packet, &to.packet);
+        let a_ids: HashSet<&str> = a.short_term_memory.iter().map(|m| m.id.as_str()).collect();
+        let b_ids: HashSet<&str> = b.short_term_memory.iter().map(|m| m.id.as_str()).collect();
+
+        let short_term_changed = b
+            .short_term_memory
+            .iter()
+            .filter(|m| {
+                a.short_term_memory
+                    .iter()
+                    .any(|old| old.id == m.id && old != *m)
+            })
+            .map(|m| m.id.clone())
+            .collect();
+
+        // 快照都在轮次结束后保存，处理器状态已转入 history_states
+        let a_states = a.history_states.front();
+        let b_states = b.history_states.front();
+        let mut states_changed: Vec<String> = b_states
+            .map(|states| {
+                states
+                    .iter()
+                    .filter(|(k, v)| a_states.and_then(|s| s.get(*k)) != Some(*v))
+                    .map(|(k, _)| k.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        states_changed.sort();
+
+        Self {
+            from_turn: from.turn_index,
+            to_turn: to.turn_index,
+            messages: (a.messages.len(), b.messages.len()),
+            short_term_added: b
+                .short_term_memory
+                .iter()
+                .filter(|m| !a_ids.contains(m.id.as_str()))
+                .map(|m| m.id.clone())
+                .collect(),
+            short_term_removed: a
+                .short_term_memory
+                .iter()
+                .filter(|m| !b_ids.contains(m.id.as_str()))
+                .map(|m| m.id.clone())
+                .collect(),
+            short_term_changed,
+            thinking_pool: (a.thinking_pool.len(), b.thinking_pool.len()),
+            states_changed,
+            history_summary_changed: a.history_summary != b.history_summary,
+        }
+    }
+}
+
+/// 读取 JSON 文件，主文件损坏时回退到备份
+pub(super) async fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, StorageError> {
+    read_parsed(path, |content| serde_json::from_str(content).map_err(io::Error::from)).await
+}
+
+/// 读取并解析文件，主文件损坏时回退到备份
+async fn read_parsed<T>(path: &Path, parse: impl Fn(&str) -> io::Result<T>) -> Result<T, StorageError> {
+    read_with_backup(path, parse)
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                StorageError::ParseFailed(path.to_path_buf(), e.to_string())
+            }
+            _ => StorageError::ReadFailed(path.to_path_buf(), e.to_string()),
+        })
+}
+
+/// 原子写入 JSON 文件（自动创建父目录）
+pub(super) async fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), StorageError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|e| StorageError::CreateDirFailed(parent.to_path_buf(), e.to_string()))?;
+    }
+
+    let content = serde_json::to_string_pretty(value)
+        .map_err(|e| StorageError::SerializeFailed(e.to_string()))?;
+
+    write_atomic(path, content)
+        .await
+        .map_err(|e| StorageError::WriteFailed(path.to_path_buf(), e.to_string()))
+}
+
+/// 删除文件及其备份
+pub(super) async fn remove_json(path: &Path) -> Result<(), StorageError> {
+    remove_with_backup(path)
+        .await
+        .map_err(|e| StorageError::DeleteFailed(path.to_path_buf(), e.to_string()))
+}
+
 /// 数据包存储
 pub struct PacketStorage {
     /// 数据根目录
     data_dir: PathBuf,
+    /// 每个话题保留的快照数
+    max_snapshots: usize,
 }
 
 impl PacketStorage {
     /// 创建数据包存储
     pub fn new(data_dir: PathBuf) -> Self {
-        Self { data_dir }
+        Self {
+            data_dir,
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+        }
     }
 
-    /// 获取数据包文件路径
-    fn get_packet_path(&self, assistant_id: &str, topic_id: &str) -> PathBuf {
+    /// 获取话题目录
+    fn get_topic_dir(&self, assistant_id: &str, topic_id: &str) -> PathBuf {
         self.data_dir
             .join("assistants")
             .join(assistant_id)
             .join("topics")
             .join(topic_id)
-            .join(PACKET_FILENAME)
+    }
+
+    /// 获取数据包文件路径
+    fn get_packet_path(&self, assistant_id: &str, topic_id: &str) -> PathBuf {
+        self.get_topic_dir(assistant_id, topic_id).join(PACKET_FILENAME)
+    }
+
+    /// 获取快照文件路径
+    fn get_snapshot_path(&self, assistant_id: &str, topic_id: &str, turn_index: u64) -> PathBuf {
+        self.get_topic_dir(assistant_id, topic_id)
+            .join(SNAPSHOT_DIR)
+            .join(format!("turn_{:06}.json", turn_index))
     }
 
     /// 加载数据包
@@ -47,12 +232,7 @@ impl PacketStorage {
             return Ok(None);
         }
 
-        let content = fs::read_to_string(&path)
-            .await
-            .map_err(|e| StorageError::ReadFailed(path.clone(), e.to_string()))?;
-
-        let packet: ConversationPacket = serde_json::from_str(&content)
-            .map_err(|e| StorageError::ParseFailed(path.clone(), e.to_string()))?;
+        let packet: ConversationPacket = read_parsed(&path, |content| PACKET_SCHEMA.parse_io(content)).await?;
 
         tracing::debug!("加载数据包成功: {:?}", path);
         Ok(Some(packet))
@@ -61,20 +241,7 @@ impl PacketStorage {
     /// 保存数据包
     pub async fn save(&self, packet: &ConversationPacket) -> Result<(), StorageError> {
         let path = self.get_packet_path(&packet.assistant_id, &packet.topic_id);
-
-        // 确保目录存在
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)
-                .await
-                .map_err(|e| StorageError::CreateDirFailed(parent.to_path_buf(), e.to_string()))?;
-        }
-
-        let content = serde_json::to_string_pretty(packet)
-            .map_err(|e| StorageError::SerializeFailed(e.to_string()))?;
-
-        fs::write(&path, content)
-            .await
-            .map_err(|e| StorageError::WriteFailed(path.clone(), e.to_string()))?;
+        write_json(&path, packet).await?;
 
         tracing::debug!("保存数据包成功: {:?}", path);
         Ok(())
@@ -89,9 +256,7 @@ impl PacketStorage {
         let path = self.get_packet_path(assistant_id, topic_id);
 
         if path.exists() {
-            fs::remove_file(&path)
-                .await
-                .map_err(|e| StorageError::DeleteFailed(path.clone(), e.to_string()))?;
+            remove_json(&path).await?;
             tracing::debug!("删除数据包成功: {:?}", path);
         }
 
@@ -103,6 +268,170 @@ impl PacketStorage {
         let path = self.get_packet_path(assistant_id, topic_id);
         path.exists()
     }
+
+    /// 保存轮次快照（轮次结束后调用），超出保留数量时删除最旧的快照
+    pub async fn save_snapshot(
+        &self,
+        packet: &ConversationPacket,
+        history_len: usize,
+    ) -> Result<(), StorageError> {
+        let snapshot = PacketSnapshot {
+            turn_index: packet.turn_index,
+            created_at: Utc::now(),
+            history_len,
+            packet: packet.clone(),
+        };
+        let path = self.get_snapshot_path(&packet.assistant_id, &packet.topic_id, packet.turn_index);
+        write_json(&path, &snapshot).await?;
+
+        // 清理超出数量的旧快照
+        let turns = self.list_snapshot_turns(&packet.assistant_id, &packet.topic_id).await?;
+        if turns.len() > self.max_snapshots {
+            for turn in &turns[..turns.len() - self.max_snapshots] {
+                self.delete_snapshot(&packet.assistant_id, &packet.topic_id, *turn).await?;
+            }
+        }
+
+        tracing::debug!("保存快照成功: {:?}", path);
+        Ok(())
+    }
+
+    /// 列出快照的轮次（从旧到新）
+    async fn list_snapshot_turns(&self, assistant_id: &str, topic_id: &str) -> Result<Vec<u64>, StorageError> {
+        let dir = self.get_topic_dir(assistant_id, topic_id).join(SNAPSHOT_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .map_err(|e| StorageError::ReadFailed(dir.clone(), e.to_string()))?;
+
+        let mut turns = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let turn = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("turn_"))
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(turn) = turn {
+                turns.push(turn);
+            }
+        }
+
+        turns.sort_unstable();
+        Ok(turns)
+    }
+
+    /// 列出话题的快照（从旧到新）
+    pub async fn list_snapshots(&self, assistant_id: &str, topic_id: &str) -> Result<Vec<SnapshotInfo>, StorageError> {
+        let mut infos = Vec::new();
+        for turn in self.list_snapshot_turns(assistant_id, topic_id).await? {
+            if let Some(snapshot) = self.load_snapshot(assistant_id, topic_id, turn).await? {
+                infos.push(SnapshotInfo::from(&snapshot));
+            }
+        }
+        Ok(infos)
+    }
+
+    /// 加载指定轮次的快照，不存在时返回 Ok(None)
+    pub async fn load_snapshot(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        turn_index: u64,
+    ) -> Result<Option<PacketSnapshot>, StorageError> {
+        let path = self.get_snapshot_path(assistant_id, topic_id, turn_index);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        // 快照内的数据包与当前数据包使用同一格式版本
+        let parse = |content: &str| -> io::Result<PacketSnapshot> {
+            let mut value: serde_json::Value = serde_json::from_str(content)?;
+            if let Some(packet) = value.get_mut("packet") {
+                PACKET_SCHEMA
+                    .migrate(packet)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            Ok(serde_json::from_value(value)?)
+        };
+        read_parsed(&path, parse).await.map(Some)
+    }
+
+    /// 删除指定轮次的快照
+    async fn delete_snapshot(&self, assistant_id: &str, topic_id: &str, turn_index: u64) -> Result<(), StorageError> {
+        remove_json(&self.get_snapshot_path(assistant_id, topic_id, turn_index)).await
+    }
+
+    /// 按恢复后的数据包整理向量文件，返回删除的条目数
+    ///
+    /// 数据包中的短期记忆保留并取消归档；不在数据包中的条目只保留快照之前归档的，
+    /// 快照之后新增的记忆被删除，避免检索到已撤销轮次的内容
+    async fn prune_vectors(&self, snapshot: &PacketSnapshot) -> Result<usize, StorageError> {
+        let packet = &snapshot.packet;
+        let path = self.get_topic_dir(&packet.assistant_id, &packet.topic_id).join(VECTOR_FILENAME);
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        let mut file: ShortTermVectorFile = read_parsed(&path, |content| VECTOR_FILE_SCHEMA.parse_io(content)).await?;
+        let restored_ids: HashSet<&str> = packet.short_term_memory.iter().map(|m| m.id.as_str()).collect();
+
+        let before = file.vectors.len();
+        let mut changed = false;
+        file.vectors.retain_mut(|vector| {
+            if restored_ids.contains(vector.id.as_str()) {
+                changed |= vector.archived;
+                vector.archived = false;
+                return true;
+            }
+            // 时间无法解析时保守保留
+            vector.archived
+                && DateTime::parse_from_rfc3339(&vector.timestamp)
+                    .map(|t| t <= snapshot.created_at)
+                    .unwrap_or(true)
+        });
+        let pruned = before - file.vectors.len();
+
+        if pruned > 0 || changed {
+            file.metadata.last_updated = Utc::now().to_rfc3339();
+            write_json(&path, &file).await?;
+        }
+        Ok(pruned)
+    }
+
+    /// 恢复到指定轮次的快照
+    ///
+    /// 快照写回为当前数据包，并删除更新的快照；返回快照以便调用方同步截断 history.json。
+    /// 快照不存在时返回 Ok(None)
+    pub async fn restore_snapshot(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        turn_index: u64,
+    ) -> Result<Option<PacketSnapshot>, StorageError> {
+        let Some(snapshot) = self.load_snapshot(assistant_id, topic_id, turn_index).await? else {
+            return Ok(None);
+        };
+
+        self.save(&snapshot.packet).await?;
+
+        for turn in self.list_snapshot_turns(assistant_id, topic_id).await? {
+            if turn > turn_index {
+                self.delete_snapshot(assistant_id, topic_id, turn).await?;
+            }
+        }
+
+        let pruned = self.prune_vectors(&snapshot).await?;
+        if pruned > 0 {
+            tracing::info!("恢复快照时删除 {} 条快照之后产生的记忆向量", pruned);
+        }
+
+        tracing::info!("已恢复到快照: {}:{} 轮次 {}", assistant_id, topic_id, turn_index);
+        Ok(Some(snapshot))
+    }
 }
 
 /// 存储错误
@@ -164,4 +493,39 @@ mod tests {
         let result = storage.load("nonexistent", "topic").await.unwrap();
         assert!(result.is_none());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_snapshot_restore() {
+        let temp_dir = tempdir().unwrap();
+        let storage = PacketStorage::new(temp_dir.path().to_path_buf());
+
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        for turn in 0..3 {
+            packet.append_user_message(&format!("第{}轮", turn));
+            packet.end_turn();
+            storage.save(&packet).await.unwrap();
+            storage.save_snapshot(&packet, (turn + 1) * 2).await.unwrap();
+        }
+
+        let snapshots = storage.list_snapshots("ast_001", "topic_001").await.unwrap();
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots[0].turn_index, 1);
+
+        let first = storage.load_snapshot("ast_001", "topic_001", 1).await.unwrap().unwrap();
+        let last = storage.load_snapshot("ast_001", "topic_001", 3).await.unwrap().unwrap();
+        assert_eq!(PacketDiff::between(&first, &last).messages, (1, 3));
+
+        let restored = storage.restore_snapshot("ast_001", "topic_001", 2).await.unwrap().unwrap();
+        assert_eq!(restored.history_len, 4);
+        let current = storage.load("ast_001", "topic_001").await.unwrap().unwrap();
+        assert_eq!(current.turn_index, 2);
+        assert_eq!(current.messages.len(), 2);
+        // 更新的快照被删除
+        assert_eq!(storage.list_snapshots("ast_001", "topic_001").await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,149 @@
+//! 话题级互斥锁
+//!
+//! 同一话题的对话请求、数据包编辑和后台任务写回共享一把异步锁，
+//! 避免并发读改写 conversation_state.json 导致后写覆盖先写、丢失轮次
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::assistant::{ConcurrencyConfig, ConcurrencyMode};
+
+/// 话题锁守卫（释放即解锁）
+pub type TopicGuard = OwnedMutexGuard<()>;
+
+/// 获取话题锁失败
+#[derive(Debug, thiserror::Error)]
+pub enum TopicLockError {
+    #[error("话题正在处理其他请求，请稍后重试")]
+    Busy,
+
+    #[error("话题正在处理其他请求，排队等待超过 {0} 秒")]
+    Timeout(u64),
+}
+
+/// <(助手ID, 话题ID), 锁>
+type LockMap = HashMap<(String, String), Arc<AsyncMutex<()>>>;
+
+/// 话题锁表
+#[derive(Default)]
+pub struct TopicLocks {
+    locks: Mutex<LockMap>,
+}
+
+impl TopicLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 获取话题对应的锁，顺便清理无人持有的锁
+    fn get(&self, assistant_id: &str, topic_id: &str) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks
+            .entry((assistant_id.to_string(), topic_id.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// 无条件排队等待话题锁（数据包编辑、后台任务写回使用）
+    pub async fn lock(&self, assistant_id: &str, topic_id: &str) -> TopicGuard {
+        self.get(assistant_id, topic_id).lock_owned().await
+    }
+
+    /// 按助手的并发配置获取话题锁（对话请求使用）
+    pub async fn acquire(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        config: &ConcurrencyConfig,
+    ) -> Result<TopicGuard, TopicLockError> {
+        let lock = self.get(assistant_id, topic_id);
+        match config.mode {
+            ConcurrencyMode::Reject => lock.try_lock_owned().map_err(|_| TopicLockError::Busy),
+            ConcurrencyMode::Queue => {
+                let timeout = Duration::from_secs(config.queue_timeout_secs);
+                tokio::time::timeout(timeout, lock.lock_owned())
+                    .await
+                    .map_err(|_| TopicLockError::Timeout(config.queue_timeout_secs))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn config(mode: ConcurrencyMode, queue_timeout_secs: u64) -> ConcurrencyConfig {
+        ConcurrencyConfig { mode, queue_timeout_secs }
+    }
+
+    #[tokio::test]
+    async fn test_reject_while_held() {
+        let locks = TopicLocks::new();
+        let reject = config(ConcurrencyMode::Reject, 0);
+
+        let guard = locks.acquire("ast_001", "topic_001", &reject).await.unwrap();
+        assert!(matches!(
+            locks.acquire("ast_001", "topic_001", &reject).await,
+            Err(TopicLockError::Busy)
+        ));
+        // 其他话题不受影响
+        assert!(locks.acquire("ast_001", "topic_002", &reject).await.is_ok());
+
+        drop(guard);
+        assert!(locks.acquire("ast_001", "topic_001", &reject).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_waits_for_release() {
+        let locks = Arc::new(TopicLocks::new());
+        let queue = config(ConcurrencyMode::Queue, 5);
+
+        let guard = locks.lock("ast_001", "topic_001").await;
+        let waiter = {
+            let locks = locks.clone();
+            tokio::spawn(async move { locks.acquire("ast_001", "topic_001", &queue).await.is_ok() })
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        drop(guard);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout() {
+        let locks = TopicLocks::new();
+        let _guard = locks.lock("ast_001", "topic_001").await;
+
+        let started = Instant::now();
+        let result = locks.acquire("ast_001", "topic_001", &config(ConcurrencyMode::Queue, 1)).await;
+        assert!(matches!(result, Err(TopicLockError::Timeout(1))));
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_released_locks_are_pruned() {
+        let locks = TopicLocks::new();
+        let guard = locks.lock("ast_001", "topic_001").await;
+        drop(locks.lock("ast_001", "topic_002").await);
+
+        // 下一次获取时清理无人持有的锁，仍被持有的锁保留
+        let _other = locks.lock("ast_002", "topic_001").await;
+        {
+            let map = locks.locks.lock().unwrap();
+            assert!(map.contains_key(&("ast_001".to_string(), "topic_001".to_string())));
+            assert!(!map.contains_key(&("ast_001".to_string(), "topic_002".to_string())));
+        }
+
+        drop(guard);
+        assert!(locks
+            .acquire("ast_001", "topic_001", &config(ConcurrencyMode::Reject, 0))
+            .await
+            .is_ok());
+    }
+}
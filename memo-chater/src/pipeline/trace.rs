@@ -0,0 +1,218 @@
+//! 流水线执行追踪
+//!
+//! 每次请求记录一条各时机的调度报告，持久化到话题目录的 traces.json，
+//! 只保留最近若干条，供 webui 排查某一轮的异常行为。
+//! 后台处理器的报告按追踪ID合并到触发它的那次请求的记录中
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::dispatcher::{DispatchReport, DispatcherError};
+use super::storage::{read_json, write_json, StorageError};
+
+/// 追踪文件名（与 conversation_state.json 同目录）
+const TRACE_FILENAME: &str = "traces.json";
+
+/// 默认保留的记录条数
+const DEFAULT_MAX_TURNS: usize = 20;
+
+/// 单轮的流水线执行记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnTrace {
+    /// 追踪ID（每次请求唯一；轮次序号在中止或快照回滚后会重复，不能用来区分记录）
+    #[serde(default = "new_trace_id")]
+    pub id: String,
+    /// 轮次序号（对应 packet.turn_index）
+    pub turn_index: u64,
+    /// 本轮开始时间
+    pub started_at: DateTime<Utc>,
+    /// 各时机的执行报告（按执行顺序）
+    pub timings: Vec<DispatchReport>,
+    /// 本轮被 abort 策略中止时的错误信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted: Option<String>,
+}
+
+fn new_trace_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+impl TurnTrace {
+    pub fn new(turn_index: u64) -> Self {
+        Self::with_id(new_trace_id(), turn_index)
+    }
+
+    /// 使用已有的追踪ID创建记录（后台任务沿用触发请求的ID，保存时合并到该记录）
+    pub fn with_id(id: String, turn_index: u64) -> Self {
+        Self {
+            id,
+            turn_index,
+            started_at: Utc::now(),
+            timings: Vec::new(),
+            aborted: None,
+        }
+    }
+
+    /// 记录一次调度结果，中止时同样记录中止前的报告，并原样返回错误
+    pub fn record(
+        &mut self,
+        result: Result<DispatchReport, DispatcherError>,
+    ) -> Result<(), DispatcherError> {
+        match result {
+            Ok(report) => {
+                self.timings.push(report);
+                Ok(())
+            }
+            Err(e) => {
+                if let DispatcherError::Aborted { report, .. } = &e {
+                    self.timings.push(report.as_ref().clone());
+                }
+                self.aborted = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+}
+
+/// 执行追踪存储
+pub struct TraceStorage {
+    /// 数据根目录
+    data_dir: PathBuf,
+    /// 保留的记录条数
+    max_turns: usize,
+}
+
+impl TraceStorage {
+    /// 创建追踪存储
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            data_dir,
+            max_turns: DEFAULT_MAX_TURNS,
+        }
+    }
+
+    /// 获取追踪文件路径
+    fn get_trace_path(&self, assistant_id: &str, topic_id: &str) -> PathBuf {
+        self.data_dir
+            .join("assistants")
+            .join(assistant_id)
+            .join("topics")
+            .join(topic_id)
+            .join(TRACE_FILENAME)
+    }
+
+    /// 加载话题的追踪记录（从旧到新），文件不存在时返回空列表
+    pub async fn load(&self, assistant_id: &str, topic_id: &str) -> Result<Vec<TurnTrace>, StorageError> {
+        let path = self.get_trace_path(assistant_id, topic_id);
+
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        read_json(&path).await
+    }
+
+    /// 追加一条追踪记录
+    ///
+    /// 已有相同ID的记录时（后台处理器晚于同步阶段完成）合并到该记录，
+    /// 超出保留条数时丢弃最旧的记录
+    pub async fn append(
+        &self,
+        assistant_id: &str,
+        topic_id: &str,
+        trace: TurnTrace,
+    ) -> Result<(), StorageError> {
+        let mut traces = match self.load(assistant_id, topic_id).await {
+            Ok(traces) => traces,
+            Err(e) => {
+                tracing::warn!("读取追踪记录失败，重新开始记录: {}", e);
+                Vec::new()
+            }
+        };
+
+        match traces.iter_mut().find(|t| t.id == trace.id) {
+            Some(existing) => {
+                existing.timings.extend(trace.timings);
+                if trace.aborted.is_some() {
+                    existing.aborted = trace.aborted;
+                }
+            }
+            None => traces.push(trace),
+        }
+
+        if traces.len() > self.max_turns {
+            let overflow = traces.len() - self.max_turns;
+            traces.drain(..overflow);
+        }
+
+        let path = self.get_trace_path(assistant_id, topic_id);
+        write_json(&path, &traces).await?;
+
+        tracing::debug!("保存执行追踪成功: {:?}", path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::dispatcher::PipelineTiming;
+
+    fn report(timing: PipelineTiming) -> DispatchReport {
+        serde_json::from_value(serde_json::json!({ "timing": timing, "runs": [] })).unwrap()
+    }
+
+    fn trace(turn_index: u64, timing: PipelineTiming) -> TurnTrace {
+        let mut trace = TurnTrace::new(turn_index);
+        trace.record(Ok(report(timing))).unwrap();
+        trace
+    }
+
+    #[tokio::test]
+    async fn test_merge_background_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = TraceStorage::new(dir.path().to_path_buf());
+
+        // 中止的一轮与重新发起的一轮轮次相同，但是两条记录
+        let aborted = trace(3, PipelineTiming::OnUserMessage);
+        let retried = trace(3, PipelineTiming::AfterAiResponse);
+        let retried_id = retried.id.clone();
+        storage.append("ast", "topic", aborted).await.unwrap();
+        storage.append("ast", "topic", retried).await.unwrap();
+
+        let mut background = TurnTrace::with_id(retried_id.clone(), 3);
+        background.record(Ok(report(PipelineTiming::BackgroundProcess))).unwrap();
+        storage.append("ast", "topic", background).await.unwrap();
+
+        let traces = storage.load("ast", "topic").await.unwrap();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].timings.len(), 1);
+        assert_eq!(traces[1].id, retried_id);
+        let timings: Vec<PipelineTiming> = traces[1].timings.iter().map(|r| r.timing).collect();
+        assert_eq!(timings, vec![PipelineTiming::AfterAiResponse, PipelineTiming::BackgroundProcess]);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_oldest_beyond_max_turns() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = TraceStorage::new(dir.path().to_path_buf());
+        storage.max_turns = 3;
+
+        for turn in 0..5 {
+            storage
+                .append("ast", "topic", trace(turn, PipelineTiming::OnUserMessage))
+                .await
+                .unwrap();
+        }
+
+        let turns: Vec<u64> = storage
+            .load("ast", "topic")
+            .await
+            .unwrap()
+            .iter()
+            .map(|t| t.turn_index)
+            .collect();
+        assert_eq!(turns, vec![2, 3, 4]);
+    }
+}
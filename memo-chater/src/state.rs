@@ -4,7 +4,7 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::config::AppConfig;
-use crate::pipeline::{PipelineDispatcher, PacketStorage};
+use crate::pipeline::{JobQueue, PipelineDispatcher, PacketStorage, TopicLocks, TraceStorage};
 use crate::assistant::AssistantManager;
 use crate::memory::MemoryManager;
 
@@ -16,6 +16,12 @@ pub struct AppState {
     pub dispatcher: Arc<PipelineDispatcher>,
     /// 数据包存储
     pub packet_storage: Arc<PacketStorage>,
+    /// 执行追踪存储
+    pub trace_storage: Arc<TraceStorage>,
+    /// 后台任务队列
+    pub job_queue: Arc<JobQueue>,
+    /// 话题锁（串行化同一话题的数据包读写）
+    pub topic_locks: Arc<TopicLocks>,
     /// 记忆管理器（供 admin_api 使用）
     pub memory_manager: Arc<RwLock<MemoryManager>>,
 }
\ No newline at end of file
@@ -0,0 +1,198 @@
+//! 崩溃安全的文件写入
+//!
+//! 所有 JSON/TOML 存储共用的持久化写入层：
+//! - 写入：先写临时文件并 fsync，再 rename 覆盖主文件；覆盖前将上一版本复制为 `.bak`
+//! - 读取：主文件读取或解析失败时回退到 `.bak`，并用备份修复主文件；
+//!   数据版本高于程序支持时直接报错，不会用旧备份覆盖新数据
+//!
+//! 写入过程中崩溃最多留下一个孤立的临时文件，主文件始终是完整的某一版本
+
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::migration::MigrationError;
+
+/// 获取同目录下追加后缀的文件路径（如 `history.json.bak`）
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// 获取备份文件路径
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling_path(path, "bak")
+}
+
+/// fsync 所在目录，确保 rename 落盘（仅 unix，失败忽略）
+async fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = fs::File::open(parent).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// 写临时文件并 rename 覆盖目标文件（不生成备份）
+async fn replace(path: &Path, content: &[u8]) -> io::Result<()> {
+    // 临时文件名唯一，避免并发写同一文件时互相覆盖临时文件
+    let tmp = sibling_path(path, &format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+
+    let result = async {
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&tmp, path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp).await;
+    }
+    result?;
+
+    sync_parent(path).await;
+    Ok(())
+}
+
+/// 原子写入文件，并将上一版本保留为 `.bak`
+///
+/// 与 `fs::write` 相同，不会创建父目录
+pub async fn write_atomic(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+
+    if fs::try_exists(path).await.unwrap_or(false) {
+        fs::copy(path, backup_path(path)).await?;
+    }
+
+    replace(path, content.as_ref()).await
+}
+
+/// `read_with_backup` 的解析错误
+pub trait ReadError: From<io::Error> + Display {
+    /// 是否禁止回退到备份并修复主文件
+    fn blocks_backup(&self) -> bool;
+}
+
+impl ReadError for io::Error {
+    /// 数据版本高于程序支持时主文件是完好的，用旧备份覆盖会丢失新版本写入的数据
+    fn blocks_backup(&self) -> bool {
+        MigrationError::is_too_new(self)
+    }
+}
+
+/// 读取并解析文件，失败时回退到 `.bak`
+///
+/// 备份可用时记录修复事件并用备份内容修复主文件；
+/// 备份不存在、同样无法解析或错误禁止回退（见 `ReadError::blocks_backup`）时返回主文件的错误
+pub async fn read_with_backup<T, E, F>(path: impl AsRef<Path>, parse: F) -> Result<T, E>
+where
+    E: ReadError,
+    F: Fn(&str) -> Result<T, E>,
+{
+    let path = path.as_ref();
+
+    let primary_err = match fs::read_to_string(path).await {
+        Ok(content) => match parse(&content) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        },
+        Err(e) => E::from(e),
+    };
+
+    if primary_err.blocks_backup() {
+        tracing::error!("数据文件无法由备份修复 {:?}: {}", path, primary_err);
+        return Err(primary_err);
+    }
+
+    let backup = backup_path(path);
+    let Ok(content) = fs::read_to_string(&backup).await else {
+        return Err(primary_err);
+    };
+    let value = match parse(&content) {
+        Ok(value) => value,
+        Err(e) => {
+            tracing::error!("数据文件与备份均无法读取 {:?}: {}；备份: {}", path, primary_err, e);
+            return Err(primary_err);
+        }
+    };
+
+    tracing::warn!("[修复] 数据文件损坏，已从备份恢复 {:?}: {}", path, primary_err);
+    if let Err(e) = replace(path, content.as_bytes()).await {
+        tracing::error!("[修复] 用备份覆盖数据文件失败 {:?}: {}", path, e);
+    }
+
+    Ok(value)
+}
+
+/// 删除文件及其备份，文件不存在时不报错
+pub async fn remove_with_backup(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    for target in [path.to_path_buf(), backup_path(path)] {
+        match fs::remove_file(&target).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn parse(content: &str) -> io::Result<Vec<u32>> {
+        serde_json::from_str(content).map_err(io::Error::from)
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic_and_recover_from_backup() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("data.json");
+
+        write_atomic(&path, "[1]").await.unwrap();
+        write_atomic(&path, "[1,2]").await.unwrap();
+        assert_eq!(fs::read_to_string(backup_path(&path)).await.unwrap(), "[1]");
+        assert_eq!(read_with_backup(&path, parse).await.unwrap(), vec![1, 2]);
+
+        // 模拟写入中途崩溃留下的截断文件
+        fs::write(&path, "[1,").await.unwrap();
+        assert_eq!(read_with_backup(&path, parse).await.unwrap(), vec![1]);
+        // 主文件已被修复
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), "[1]");
+
+        remove_with_backup(&path).await.unwrap();
+        assert!(!path.exists());
+        assert!(!backup_path(&path).exists());
+        assert!(read_with_backup(&path, parse).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_too_new_skips_backup() {
+        use crate::storage::PACKET_SCHEMA;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("conversation_state.json");
+        let parse = |content: &str| PACKET_SCHEMA.parse_io::<serde_json::Value>(content);
+
+        write_atomic(&path, r#"{"schema_version": 1}"#).await.unwrap();
+        // 更新版本的程序写入的数据
+        let newer = format!(r#"{{"schema_version": {}}}"#, PACKET_SCHEMA.current + 1);
+        write_atomic(&path, &newer).await.unwrap();
+
+        let err = read_with_backup(&path, parse).await.unwrap_err();
+        assert!(MigrationError::is_too_new(&err));
+        assert!(err.blocks_backup());
+        // 主文件保持不变，不会被旧备份覆盖
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), newer);
+    }
+}
@@ -3,6 +3,8 @@
 //! 使用 JSON 文件存储记忆关联的文件内容（代码块等）
 //! 避免 SQLite 的 C 编译依赖
 
+use super::atomic::{read_with_backup, write_atomic, ReadError};
+use super::migration::FILE_INDEX_SCHEMA;
 use crate::types::MemoryFile;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -21,9 +23,18 @@ pub enum FileStoreError {
     NotFound(String),
 }
 
+impl ReadError for FileStoreError {
+    fn blocks_backup(&self) -> bool {
+        matches!(self, FileStoreError::Io(e) if e.blocks_backup())
+    }
+}
+
 /// 存储索引结构
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct FileIndex {
+    /// 持久化格式版本（见 FILE_INDEX_SCHEMA）
+    #[serde(default)]
+    schema_version: u32,
     /// 文件ID -> 文件名映射
     files: HashMap<String, String>,
     /// 记忆ID -> 文件ID列表映射
@@ -33,7 +44,7 @@ struct FileIndex {
 /// 文件内容存储
 /// 
 /// 存储结构：
-/// ```
+/// ```text
 /// storage_dir/
 /// ├── index.json          # 索引文件
 /// └── files/
@@ -58,10 +69,14 @@ impl FileStore {
         // 加载或创建索引
         let index_path = storage_dir.join("index.json");
         let index = if index_path.exists() {
-            let content = fs::read_to_string(&index_path).await?;
-            serde_json::from_str(&content)?
+            read_with_backup(&index_path, |content| {
+                FILE_INDEX_SCHEMA.parse_io(content).map_err(FileStoreError::from)
+            }).await?
         } else {
-            FileIndex::default()
+            FileIndex {
+                schema_version: FILE_INDEX_SCHEMA.current,
+                ..Default::default()
+            }
         };
         
         Ok(Self { storage_dir, index })
@@ -71,7 +86,7 @@ impl FileStore {
     async fn save_index(&self) -> Result<(), FileStoreError> {
         let index_path = self.storage_dir.join("index.json");
         let content = serde_json::to_string_pretty(&self.index)?;
-        fs::write(&index_path, content).await?;
+        write_atomic(&index_path, content).await?;
         Ok(())
     }
 
@@ -80,7 +95,7 @@ impl FileStore {
         // 写入文件内容
         let file_path = self.storage_dir.join("files").join(format!("{}.json", file.id));
         let content = serde_json::to_string_pretty(file)?;
-        fs::write(&file_path, content).await?;
+        write_atomic(&file_path, content).await?;
         
         // 更新索引
         self.index.files.insert(file.id.clone(), format!("{}.json", file.id));
@@ -99,7 +114,7 @@ impl FileStore {
             // 写入文件内容
             let file_path = self.storage_dir.join("files").join(format!("{}.json", file.id));
             let content = serde_json::to_string_pretty(file)?;
-            fs::write(&file_path, content).await?;
+            write_atomic(&file_path, content).await?;
             
             // 更新索引
             self.index.files.insert(file.id.clone(), format!("{}.json", file.id));
@@ -119,9 +134,9 @@ impl FileStore {
             .ok_or_else(|| FileStoreError::NotFound(id.to_string()))?;
         
         let file_path = self.storage_dir.join("files").join(filename);
-        let content = fs::read_to_string(&file_path).await?;
-        let file: MemoryFile = serde_json::from_str(&content)?;
-        Ok(file)
+        read_with_backup(&file_path, |content| {
+            serde_json::from_str(content).map_err(FileStoreError::from)
+        }).await
     }
 
     /// 获取记忆关联的所有文件
@@ -0,0 +1,414 @@
+//! 持久化格式的版本与迁移
+//!
+//! 每种持久化格式在顶层记录 `schema_version`（缺失视为版本 0），
+//! 加载时按注册的迁移逐级升级到当前版本后再反序列化。
+//! 迁移只在内存中进行，下次保存时写入新版本；`migrate` 命令可提前批量升级整个 data_dir。
+//!
+//! 新增格式变更时：提升对应格式的 `current`，并在 `migrations` 末尾追加从旧版本升级的迁移
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io;
+
+/// 版本字段名
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// 迁移错误
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("JSON 解析失败: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{format} 版本 {found} 高于程序支持的版本 {current}，请升级程序")]
+    TooNew {
+        format: &'static str,
+        found: u32,
+        current: u32,
+    },
+
+    #[error("{format} 缺少从版本 {from} 升级的迁移")]
+    MissingMigration { format: &'static str, from: u32 },
+
+    #[error("{format} 从版本 {from} 迁移失败: {message}")]
+    Failed {
+        format: &'static str,
+        from: u32,
+        message: String,
+    },
+}
+
+impl MigrationError {
+    /// IO 错误是否由数据版本高于程序支持引起（见 `SchemaFormat::parse_io`）
+    pub fn is_too_new(err: &io::Error) -> bool {
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<MigrationError>())
+            .is_some_and(|e| matches!(e, MigrationError::TooNew { .. }))
+    }
+}
+
+/// 单步迁移：把 `from` 版本的数据升级到 `from + 1`
+pub struct Migration {
+    /// 源版本
+    pub from: u32,
+    /// 说明
+    pub description: &'static str,
+    /// 修改数据（版本号由调用方更新）
+    pub apply: fn(&mut Value) -> Result<(), String>,
+}
+
+/// 持久化格式
+pub struct SchemaFormat {
+    /// 格式名称
+    pub name: &'static str,
+    /// 当前版本
+    pub current: u32,
+    /// 迁移列表（按源版本排列）
+    pub migrations: &'static [Migration],
+}
+
+impl SchemaFormat {
+    /// 读取数据的版本号（缺失视为 0）
+    pub fn version_of(value: &Value) -> u32 {
+        value
+            .get(SCHEMA_VERSION_KEY)
+            .and_then(Value::as_u64)
+            .map(|v| v as u32)
+            .unwrap_or(0)
+    }
+
+    /// 将数据升级到当前版本，返回是否发生了迁移
+    pub fn migrate(&self, value: &mut Value) -> Result<bool, MigrationError> {
+        let found = Self::version_of(value);
+        if found > self.current {
+            return Err(MigrationError::TooNew {
+                format: self.name,
+                found,
+                current: self.current,
+            });
+        }
+
+        let mut version = found;
+        while version < self.current {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|m| m.from == version)
+                .ok_or(MigrationError::MissingMigration {
+                    format: self.name,
+                    from: version,
+                })?;
+
+            (migration.apply)(value).map_err(|message| MigrationError::Failed {
+                format: self.name,
+                from: version,
+                message,
+            })?;
+
+            version += 1;
+            if let Some(obj) = value.as_object_mut() {
+                obj.insert(SCHEMA_VERSION_KEY.to_string(), Value::from(version));
+            }
+            tracing::debug!("{} 迁移 v{} -> v{}: {}", self.name, migration.from, version, migration.description);
+        }
+
+        Ok(version != found)
+    }
+
+    /// 解析 JSON 并升级到当前版本
+    pub fn parse<T: DeserializeOwned>(&self, content: &str) -> Result<T, MigrationError> {
+        let mut value: Value = serde_json::from_str(content)?;
+        self.migrate(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// 同 `parse`，错误转为 `InvalidData` 类型的 IO 错误（供 `read_with_backup` 使用）
+    ///
+    /// 版本过新的错误可以用 `MigrationError::is_too_new` 识别，此时不会回退到备份
+    pub fn parse_io<T: DeserializeOwned>(&self, content: &str) -> io::Result<T> {
+        self.parse(content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// 初始迁移：引入 schema_version 前的数据结构不变，只补版本号
+fn stamp_version(_value: &mut Value) -> Result<(), String> {
+    Ok(())
+}
+
+/// v2：短期记忆新增相关性与置信度，已有记忆以满分起算
+fn init_memory_scores(value: &mut Value) -> Result<(), String> {
+    let Some(memories) = value.get_mut("short_term_memory").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+    for memory in memories {
+        let obj = memory.as_object_mut().ok_or("短期记忆条目不是对象")?;
+        obj.entry("relevance").or_insert(Value::from(1.0));
+        obj.entry("confidence").or_insert(Value::from(1.0));
+    }
+    Ok(())
+}
+
+/// v3 之前按内容前缀识别的注入消息：(前缀, 来源处理器, 注入标识)
+const LEGACY_INJECTIONS: &[(&str, &str, &str)] = &[
+    ("【系统消息-短期记忆】现在为你注入", "ShortTermAssembler", "short_term_memory"),
+    ("【系统消息-短期记忆】根据", "ShortTermExpander", "expanded_memory"),
+    ("【系统消息-历史摘要】", "HistorySimplifier", "history_summary"),
+];
+
+/// v3：注入消息改用 meta 标记，为旧数据中按前缀识别的注入消息补上 meta
+fn tag_injected_messages(value: &mut Value) -> Result<(), String> {
+    let Some(messages) = value.get_mut("messages").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+    for message in messages {
+        let obj = message.as_object_mut().ok_or("消息不是对象")?;
+        if obj.get("role").and_then(Value::as_str) != Some("user") || obj.contains_key("meta") {
+            continue;
+        }
+        let content = obj.get("content").and_then(Value::as_str).unwrap_or_default();
+        if let Some((_, origin, injection_id)) =
+            LEGACY_INJECTIONS.iter().find(|(prefix, _, _)| content.starts_with(prefix))
+        {
+            obj.insert(
+                "meta".to_string(),
+                serde_json::json!({ "origin": origin, "ephemeral": true, "injection_id": injection_id }),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// v4：短期记忆新增提交标记，按 MemoryCommitter 最近一次记录的 committed_ids 补上
+fn mark_committed_memories(value: &mut Value) -> Result<(), String> {
+    let current = value.get("current_states").into_iter();
+    let history = value
+        .get("history_states")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    let committed: Vec<String> = current
+        .chain(history)
+        .find_map(|states| states.get("MemoryCommitter"))
+        .and_then(|state| state.get("committed_ids"))
+        .and_then(Value::as_array)
+        .map(|ids| ids.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default();
+
+    let Some(memories) = value.get_mut("short_term_memory").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+    for memory in memories {
+        let obj = memory.as_object_mut().ok_or("短期记忆条目不是对象")?;
+        let id = obj.get("id").and_then(Value::as_str).unwrap_or_default();
+        let is_committed = committed.iter().any(|c| c == id);
+        obj.entry("committed").or_insert(Value::from(is_committed));
+    }
+    Ok(())
+}
+
+/// v5：切块水位线从 ContentChunker 的处理器状态移到 chunked_until（处理器状态只保留最近几轮）
+fn lift_chunk_watermark(value: &mut Value) -> Result<(), String> {
+    let current = value.get("current_states").into_iter();
+    let history = value
+        .get("history_states")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    let watermark = current
+        .chain(history)
+        .find_map(|states| states.get("ContentChunker"))
+        .and_then(|state| state.get("watermark"))
+        .filter(|watermark| !watermark.is_null())
+        .cloned();
+
+    let obj = value.as_object_mut().ok_or("数据包不是对象")?;
+    if let Some(watermark) = watermark {
+        obj.entry("chunked_until").or_insert(watermark);
+    }
+    Ok(())
+}
+
+/// 对话数据包（conversation_state.json，以及快照中的 packet）
+pub const PACKET_SCHEMA: SchemaFormat = SchemaFormat {
+    name: "ConversationPacket",
+    current: 5,
+    migrations: &[
+        Migration {
+            from: 0,
+            description: "引入 schema_version",
+            apply: stamp_version,
+        },
+        Migration {
+            from: 1,
+            description: "短期记忆新增 relevance / confidence",
+            apply: init_memory_scores,
+        },
+        Migration {
+            from: 2,
+            description: "注入消息改用 meta 标记",
+            apply: tag_injected_messages,
+        },
+        Migration {
+            from: 3,
+            description: "短期记忆新增 committed 标记",
+            apply: mark_committed_memories,
+        },
+        Migration {
+            from: 4,
+            description: "切块水位线移到 chunked_until",
+            apply: lift_chunk_watermark,
+        },
+    ],
+};
+
+/// 对话记忆向量文件（short_term_vectors.json）
+pub const VECTOR_FILE_SCHEMA: SchemaFormat = SchemaFormat {
+    name: "ShortTermVectorFile",
+    current: 1,
+    migrations: &[Migration {
+        from: 0,
+        description: "引入 schema_version",
+        apply: stamp_version,
+    }],
+};
+
+/// 关系图（graphs/*.json 等）
+pub const GRAPH_SCHEMA: SchemaFormat = SchemaFormat {
+    name: "DimensionGraph",
+    current: 1,
+    migrations: &[Migration {
+        from: 0,
+        description: "引入 schema_version",
+        apply: stamp_version,
+    }],
+};
+
+/// 文件存储索引（files/index.json）
+pub const FILE_INDEX_SCHEMA: SchemaFormat = SchemaFormat {
+    name: "FileIndex",
+    current: 1,
+    migrations: &[Migration {
+        from: 0,
+        description: "引入 schema_version",
+        apply: stamp_version,
+    }],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rename_field(value: &mut Value) -> Result<(), String> {
+        let obj = value.as_object_mut().ok_or("不是对象")?;
+        let old = obj.remove("name").ok_or("缺少 name")?;
+        obj.insert("title".to_string(), old);
+        Ok(())
+    }
+
+    const TEST_SCHEMA: SchemaFormat = SchemaFormat {
+        name: "Test",
+        current: 2,
+        migrations: &[
+            Migration { from: 0, description: "引入 schema_version", apply: stamp_version },
+            Migration { from: 1, description: "name 改名为 title", apply: rename_field },
+        ],
+    };
+
+    #[test]
+    fn test_migrate_to_current() {
+        let mut value = json!({"name": "旧数据"});
+        assert!(TEST_SCHEMA.migrate(&mut value).unwrap());
+        assert_eq!(value, json!({"title": "旧数据", "schema_version": 2}));
+
+        // 已是当前版本时不做修改
+        assert!(!TEST_SCHEMA.migrate(&mut value).unwrap());
+
+        let mut newer = json!({"schema_version": 3});
+        assert!(matches!(
+            TEST_SCHEMA.migrate(&mut newer),
+            Err(MigrationError::TooNew { found: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_mark_committed_memories() {
+        let mut value = json!({
+            "short_term_memory": [{"id": "a"}, {"id": "b"}],
+            "history_states": [{}, {"MemoryCommitter": {"committed_ids": ["a"]}}],
+        });
+        mark_committed_memories(&mut value).unwrap();
+        assert_eq!(value["short_term_memory"][0]["committed"], json!(true));
+        assert_eq!(value["short_term_memory"][1]["committed"], json!(false));
+    }
+
+    #[test]
+    fn test_lift_chunk_watermark() {
+        let mut value = json!({
+            "current_states": {},
+            "history_states": [{"Other": {}}, {"ContentChunker": {"watermark": "2025-01-01T00:00:00Z"}}],
+        });
+        lift_chunk_watermark(&mut value).unwrap();
+        assert_eq!(value["chunked_until"], json!("2025-01-01T00:00:00Z"));
+
+        let mut value = json!({"history_states": [{"ContentChunker": {"watermark": null}}]});
+        lift_chunk_watermark(&mut value).unwrap();
+        assert!(value.get("chunked_until").is_none());
+    }
+
+    #[test]
+    fn test_packet_schema_full_chain() {
+        use crate::pipeline::ConversationPacket;
+        use crate::types::{ChatMessage, ShortTermMemory};
+
+        let memory = |id: &str| ShortTermMemory {
+            relevance: 0.5,
+            confidence: 0.5,
+            ..ShortTermMemory::for_test(id)
+        };
+        let mut packet = ConversationPacket::new(
+            "ast_001".to_string(),
+            "topic_001".to_string(),
+            "秦".to_string(),
+            "诺亚".to_string(),
+        );
+        packet.messages = vec![
+            ChatMessage::system("系统提示词"),
+            ChatMessage::user("【系统消息-历史摘要】以下是更早对话的摘要"),
+            ChatMessage::user("你好"),
+        ];
+        packet.short_term_memory = vec![memory("a"), memory("b")];
+        let watermark = chrono::Utc::now();
+        packet.history_states.push_front(
+            [
+                ("MemoryCommitter".to_string(), json!({"committed_ids": ["a"]})),
+                ("ContentChunker".to_string(), json!({"watermark": watermark})),
+            ]
+            .into(),
+        );
+
+        // 还原为 v0 的结构：无版本号、记忆无评分和提交标记、注入消息无 meta
+        let mut value = serde_json::to_value(&packet).unwrap();
+        let obj = value.as_object_mut().unwrap();
+        obj.remove("schema_version");
+        obj.remove("chunked_until");
+        for memory in obj["short_term_memory"].as_array_mut().unwrap() {
+            let memory = memory.as_object_mut().unwrap();
+            for field in ["relevance", "confidence", "committed"] {
+                memory.remove(field);
+            }
+        }
+        for message in obj["messages"].as_array_mut().unwrap() {
+            message.as_object_mut().unwrap().remove("meta");
+        }
+
+        let migrated: ConversationPacket = PACKET_SCHEMA.parse(&value.to_string()).unwrap();
+        assert_eq!(migrated.schema_version, PACKET_SCHEMA.current);
+        assert!(migrated.short_term_memory.iter().all(|m| m.relevance == 1.0 && m.confidence == 1.0));
+        assert!(migrated.short_term_memory[0].committed);
+        assert!(!migrated.short_term_memory[1].committed);
+        assert!(migrated.messages[1].is_injection("history_summary"));
+        assert!(migrated.messages[2].meta.is_none());
+        assert_eq!(migrated.chunked_until, Some(watermark));
+    }
+}
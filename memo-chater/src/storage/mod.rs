@@ -1,7 +1,11 @@
 //! 存储模块
 //! 
-//! 提供文件内容的持久化存储（JSON文件，无C依赖）
+//! 提供文件内容的持久化存储（JSON文件，无C依赖），以及所有存储共用的崩溃安全写入和格式迁移
 
+mod atomic;
 mod file_store;
+mod migration;
 
-pub use file_store::*;
\ No newline at end of file
+pub use atomic::*;
+pub use file_store::*;
+pub use migration::*;
@@ -6,7 +6,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 /// 思考来源
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ThinkingSource {
     /// 用户输入分析
     UserAnalysis,
@@ -19,7 +19,7 @@ pub enum ThinkingSource {
 }
 
 /// 短期记忆条目
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShortTermMemory {
     pub id: String,
     /// 概述/标题
@@ -31,14 +31,44 @@ pub struct ShortTermMemory {
     /// 是否需要展开（前端控制）
     #[serde(default)]
     pub should_expand: bool,
+    /// 相关性得分（0~1，随时间衰减，被展开或再次检索时加强，过低时被淘汰）
+    #[serde(default = "default_memory_score")]
+    pub relevance: f32,
+    /// 置信度（0~1）
+    #[serde(default = "default_memory_score")]
+    pub confidence: f32,
     /// 来源（从长期记忆检索/当前对话提取）
     pub source: MemorySource,
     /// 创建时间
     pub timestamp: DateTime<Utc>,
+    /// 是否已提交到长期记忆库（MemoryCommitter 维护，提交后不再重复提交）
+    #[serde(default)]
+    pub committed: bool,
+}
+
+fn default_memory_score() -> f32 { 1.0 }
+
+#[cfg(test)]
+impl ShortTermMemory {
+    /// 测试用短期记忆：摘要和内容由 id 生成，来自当前对话，相关性与置信度为满分
+    pub(crate) fn for_test(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            summary: format!("{} 概述", id),
+            content: format!("{} 内容", id),
+            memory_type: "fact".to_string(),
+            should_expand: false,
+            relevance: 1.0,
+            confidence: 1.0,
+            source: MemorySource::CurrentConversation,
+            timestamp: Utc::now(),
+            committed: false,
+        }
+    }
 }
 
 /// 记忆来源
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MemorySource {
     /// 从长期记忆检索
     LongTermRetrieval,
@@ -88,6 +118,12 @@ impl LongTermMemory {
         }
     }
 
+    /// 指定记忆ID（用于需要确定性ID的场景，须为 UUID 格式）
+    pub fn with_id(mut self, id: String) -> Self {
+        self.id = id;
+        self
+    }
+
     /// 设置来源会话
     pub fn with_session(mut self, session_id: String) -> Self {
         self.source_session = Some(session_id);
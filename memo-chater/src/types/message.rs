@@ -3,10 +3,253 @@
 use serde::{Deserialize, Serialize};
 
 /// 聊天消息
+///
+/// `content` 始终是纯文本（多模态消息为文本投影，供切块、embedding、token 计数使用）；
+/// 多模态消息的原始内容保存在 `parts` 中，序列化和转发给上游时以 `parts` 为准
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "ChatMessageRepr", into = "ChatMessageRepr")]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+    /// 多模态内容（OpenAI content parts），纯文本消息为 None
+    pub parts: Option<Vec<ContentPart>>,
+    /// 参与者名称（OpenAI `name` 字段）
+    pub name: Option<String>,
+    /// 助手发起的工具调用（OpenAI `tool_calls` 字段）
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// 工具结果对应的调用ID（`tool` 角色消息）
+    pub tool_call_id: Option<String>,
+    /// 内部元数据（只在流水线内部流转，不会发送给上游 API）
+    pub meta: Option<MessageMeta>,
+}
+
+/// 消息内容（OpenAI 格式：字符串或 content part 数组）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// 多模态内容片段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+    File { file: FileData },
+}
+
+/// 图片（URL 或 data URL）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// 音频（base64）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    pub data: String,
+    pub format: String,
+}
+
+/// 工具调用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_call_type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+fn default_tool_call_type() -> String {
+    "function".to_string()
+}
+
+/// 函数调用（arguments 为 JSON 字符串，原样透传）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// 流式响应中的工具调用增量（同一 index 的增量拼接成完整调用）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub call_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// 函数调用增量
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+impl ToolCall {
+    /// 转换为携带完整调用的流式增量
+    pub fn to_delta(&self, index: usize) -> ToolCallDelta {
+        ToolCallDelta {
+            index,
+            id: Some(self.id.clone()),
+            call_type: Some(self.call_type.clone()),
+            function: Some(FunctionCallDelta {
+                name: Some(self.function.name.clone()),
+                arguments: Some(self.function.arguments.clone()),
+            }),
+        }
+    }
+}
+
+impl ToolCallDelta {
+    /// 把增量合并到已累积的工具调用中
+    pub fn apply_to(&self, calls: &mut Vec<ToolCall>) {
+        while calls.len() <= self.index {
+            calls.push(ToolCall {
+                id: String::new(),
+                call_type: default_tool_call_type(),
+                function: FunctionCall::default(),
+            });
+        }
+        let call = &mut calls[self.index];
+        if let Some(id) = &self.id {
+            call.id = id.clone();
+        }
+        if let Some(call_type) = &self.call_type {
+            call.call_type = call_type.clone();
+        }
+        if let Some(function) = &self.function {
+            if let Some(name) = &function.name {
+                call.function.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+}
+
+/// 文件（已上传的 file_id 或 base64 数据）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+impl ContentPart {
+    /// 片段的文本投影（非文本片段用占位符表示）
+    pub fn text_projection(&self) -> String {
+        match self {
+            ContentPart::Text { text } => text.clone(),
+            ContentPart::ImageUrl { .. } => "[图片]".to_string(),
+            ContentPart::InputAudio { .. } => "[音频]".to_string(),
+            ContentPart::File { file } => match &file.filename {
+                Some(name) => format!("[文件: {}]", name),
+                None => "[文件]".to_string(),
+            },
+        }
+    }
+}
+
+/// 多个片段的文本投影（以换行连接）
+fn project_parts(parts: &[ContentPart]) -> String {
+    parts
+        .iter()
+        .map(ContentPart::text_projection)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// ChatMessage 的序列化格式（与 OpenAI 消息格式一致）
+#[derive(Serialize, Deserialize)]
+struct ChatMessageRepr {
+    role: String,
+    #[serde(default)]
+    content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta: Option<MessageMeta>,
+}
+
+impl From<ChatMessageRepr> for ChatMessage {
+    fn from(repr: ChatMessageRepr) -> Self {
+        let (content, parts) = match repr.content {
+            Some(MessageContent::Parts(parts)) => (project_parts(&parts), Some(parts)),
+            Some(MessageContent::Text(text)) => (text, None),
+            None => (String::new(), None),
+        };
+        Self {
+            role: repr.role,
+            content,
+            parts,
+            name: repr.name,
+            tool_calls: repr.tool_calls,
+            tool_call_id: repr.tool_call_id,
+            meta: repr.meta,
+        }
+    }
+}
+
+impl From<ChatMessage> for ChatMessageRepr {
+    fn from(message: ChatMessage) -> Self {
+        let content = message.to_content();
+        Self {
+            role: message.role,
+            content,
+            name: message.name,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+            meta: message.meta,
+        }
+    }
+}
+
+/// 消息的内部元数据
+///
+/// 处理器注入的消息通过元数据识别，而不是匹配内容前缀，修改注入模板不影响清理和替换
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageMeta {
+    /// 注入该消息的处理器
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// 临时消息：只用于本轮请求，AI 响应后由 ContextCleaner 移除
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// 注入标识（同类注入使用相同标识，重新注入时按此替换）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub injection_id: Option<String>,
+}
+
+impl MessageMeta {
+    /// 处理器注入的临时消息
+    pub fn injected(origin: impl Into<String>, injection_id: impl Into<String>) -> Self {
+        Self {
+            origin: Some(origin.into()),
+            ephemeral: true,
+            injection_id: Some(injection_id.into()),
+        }
+    }
 }
 
 impl ChatMessage {
@@ -14,6 +257,11 @@ impl ChatMessage {
         Self {
             role: "system".to_string(),
             content: content.into(),
+            parts: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+            meta: None,
         }
     }
 
@@ -21,6 +269,11 @@ impl ChatMessage {
         Self {
             role: "user".to_string(),
             content: content.into(),
+            parts: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+            meta: None,
         }
     }
 
@@ -28,8 +281,65 @@ impl ChatMessage {
         Self {
             role: "assistant".to_string(),
             content: content.into(),
+            parts: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+            meta: None,
         }
     }
+
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: content.into(),
+            parts: None,
+            name: None,
+            tool_calls: None,
+            tool_call_id: Some(tool_call_id.into()),
+            meta: None,
+        }
+    }
+
+    /// 发送给上游的内容（多模态消息为 content parts，否则为纯文本；
+    /// 只有工具调用、没有文本的助手消息为 None）
+    pub fn to_content(&self) -> Option<MessageContent> {
+        match &self.parts {
+            Some(parts) => Some(MessageContent::Parts(parts.clone())),
+            None if self.content.is_empty() && self.has_tool_calls() => None,
+            None => Some(MessageContent::Text(self.content.clone())),
+        }
+    }
+
+    /// 附加工具调用（为空时不设置）
+    pub fn with_tool_calls(mut self, tool_calls: Vec<ToolCall>) -> Self {
+        self.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        self
+    }
+
+    /// 是否包含工具调用
+    pub fn has_tool_calls(&self) -> bool {
+        self.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty())
+    }
+
+    /// 附加内部元数据
+    pub fn with_meta(mut self, meta: MessageMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
+    /// 是否为临时消息
+    pub fn is_ephemeral(&self) -> bool {
+        self.meta.as_ref().is_some_and(|m| m.ephemeral)
+    }
+
+    /// 是否为指定注入标识的消息
+    pub fn is_injection(&self, injection_id: &str) -> bool {
+        self.meta
+            .as_ref()
+            .and_then(|m| m.injection_id.as_deref())
+            == Some(injection_id)
+    }
 }
 
 /// 处理后的用户输入